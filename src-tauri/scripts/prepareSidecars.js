@@ -1,3 +1,4 @@
+import { createHash } from "node:crypto";
 import * as fs from "node:fs/promises";
 import * as path from "node:path";
 import { fileURLToPath } from "node:url";
@@ -10,6 +11,15 @@ const __filename = fileURLToPath(import.meta.url);
 const __dirname = path.dirname(__filename);
 
 const binariesDir = path.join(__dirname, "../../target/binaries");
+const modelsDir = path.join(__dirname, "../../target/models");
+
+// SeetaFace frontal face model used by the offline face presence check.
+// Pinned to a commit and checksum so a changed upstream file is never bundled, bump both together
+const FACE_MODEL = {
+  commit: "",
+  sha256: "",
+  path: "seeta_fd_frontal_v1.0.bin",
+};
 
 const FFMPEG_BINARIES = {
   "aarch64-apple-darwin": {
@@ -38,7 +48,50 @@ async function exists(path) {
     .catch(() => false);
 }
 
+async function download(url) {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Error(
+      `GET ${url} failed: ${response.status} ${response.statusText}`,
+    );
+  }
+
+  return Buffer.from(await response.arrayBuffer());
+}
+
+function sha256(data) {
+  return createHash("sha256").update(data).digest("hex");
+}
+
+async function prepareFaceModel() {
+  if (!FACE_MODEL.commit || !FACE_MODEL.sha256) {
+    throw new Error("FACE_MODEL.commit and FACE_MODEL.sha256 must be pinned");
+  }
+
+  const modelPath = path.join(modelsDir, FACE_MODEL.path);
+  if (await exists(modelPath)) {
+    if (sha256(await fs.readFile(modelPath)) === FACE_MODEL.sha256) return;
+    console.log("face model checksum changed, downloading it again");
+  }
+
+  await fs.mkdir(modelsDir, { recursive: true });
+  console.log("downloading face model");
+  const url = `https://raw.githubusercontent.com/atomashpolskiy/rustface/${FACE_MODEL.commit}/model/${FACE_MODEL.path}`;
+  const model = await download(url);
+
+  const checksum = sha256(model);
+  if (checksum !== FACE_MODEL.sha256) {
+    throw new Error(
+      `face model checksum ${checksum} does not match ${FACE_MODEL.sha256}`,
+    );
+  }
+
+  await fs.writeFile(modelPath, model);
+}
+
 async function main() {
+  await prepareFaceModel();
+
   const targetTriple = process.env.TARGET_TRIPLE ?? (await getRustupTarget());
   const binaries = FFMPEG_BINARIES[targetTriple];
   if (!binaries) return;
@@ -47,10 +100,7 @@ async function main() {
   if (!(await exists(ffmpegDownloadPath))) {
     await fs.mkdir(binariesDir, { recursive: true });
     console.log("downloading ffmpeg archive");
-    const archive = await fetch(binaries.url)
-      .then((r) => r.blob())
-      .then((b) => b.arrayBuffer())
-      .then((a) => Buffer.from(a));
+    const archive = await download(binaries.url);
 
     await fs.writeFile(ffmpegDownloadPath, archive);
   }
@@ -73,4 +123,7 @@ async function main() {
   );
 }
 
-main();
+main().catch((err) => {
+  console.error(err);
+  process.exit(1);
+});
@@ -1,19 +1,33 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
 
 use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
 
 use crate::storage;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Auth {
     pub token: String,
     pub name: String,
 }
 
+// keeps the token out of logs
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("token", &"[redacted]")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
 impl Default for Auth {
     fn default() -> Self {
         if let Ok(this) = storage::load_from_path::<Self>(storage::auth_path::<Self>()) {
-            println!("Loaded auth from: {:?}", &this);
+            debug!("Loaded saved auth");
             return this;
         }
 
@@ -22,7 +36,9 @@ impl Default for Auth {
             token: "".into(),
         };
 
-        storage::save(&this);
+        if let Err(err) = storage::save(&this) {
+            error!("Error saving auth: {:?}", err);
+        }
 
         this
     }
@@ -1,63 +1,187 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 use std::time::Duration;
 
 use base64::Engine;
-use image::{ImageBuffer, Rgb};
 use nokhwa::utils::{CameraInfo, RequestedFormat, RequestedFormatType, Resolution};
 use nokhwa::Camera;
 use nokhwa::{native_api_backend, pixel_format, NokhwaError};
 
 use serde::{Deserialize, Serialize};
-use tauri::utils::platform;
-use tokio::fs;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    capture::{CaptureError, CaptureOutput},
+    compressor, encryption,
+    ffmpeg_backend::{CaptureBackend, FfmpegBackend, FfmpegInput},
+    CameraSettings,
+};
+#[cfg(not(target_os = "macos"))]
+use crate::frame_convert::{convert_frame, PixelFormat};
+#[cfg(not(target_os = "macos"))]
+use image::{imageops::FilterType, DynamicImage};
+
+/// Returns None when no camera is connected
+pub fn get_default_camera() -> crate::Result<Option<CameraInfo>> {
+    Ok(query_cameras()?.into_iter().next())
+}
+
+pub fn query_cameras() -> Result<Vec<CameraInfo>, String> {
+    let backend = native_api_backend().ok_or("No camera backend available on this platform")?;
+    nokhwa::query(backend).map_err(|err| format!("nokhwa::query(backend) error: {:?}", err))
+}
+
+/// Id that stays the same across restarts and tells identical webcams apart
+///
+/// Backends put the device unique id in `misc`, the index is only a fallback
+pub fn camera_id(info: &CameraInfo) -> String {
+    let misc = info.misc();
+    if misc.trim().is_empty() {
+        format!("{}#{}", info.human_name(), info.index().as_string())
+    } else {
+        misc
+    }
+}
 
-use crate::compressor;
+/// Formats by camera id, opening a camera to read them turns its light on
+static FORMATS: RwLock<BTreeMap<String, Vec<CameraFormatInfo>>> = RwLock::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraFormatInfo {
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraDevice {
+    pub id: String,
+    pub index: String,
+    pub name: String,
+    pub misc: String,
+    pub description: String,
+    /// Empty until the camera is selected, or when it could not be opened to query its formats
+    pub formats: Vec<CameraFormatInfo>,
+}
+
+impl CameraDevice {
+    /// Never opens the camera, formats come from what `load_formats` cached
+    pub fn from_info(info: &CameraInfo) -> Self {
+        let id = camera_id(info);
+        let formats = FORMATS.read().unwrap().get(&id).cloned().unwrap_or_default();
+
+        Self {
+            id,
+            index: info.index().as_string(),
+            name: info.human_name(),
+            misc: info.misc(),
+            description: info.description().to_string(),
+            formats,
+        }
+    }
+}
 
-pub fn get_default_camera() -> crate::Result<CameraInfo> {
-    let backend = native_api_backend().unwrap();
+/// Read the formats of a camera once and cache them for `CameraDevice::from_info`
+pub fn load_formats(info: &CameraInfo) -> Vec<CameraFormatInfo> {
+    let id = camera_id(info);
+    if let Some(formats) = FORMATS.read().unwrap().get(&id) {
+        return formats.clone();
+    }
 
-    let devices =
-        nokhwa::query(backend).map_err(|err| format!("nokhwa::query(backend) error: {:?}", err))?;
-    #[allow(clippy::get_first)]
-    Ok(devices.get(0).unwrap().to_owned())
+    match supported_formats(info) {
+        Ok(formats) => {
+            FORMATS.write().unwrap().insert(id, formats.clone());
+            formats
+        }
+        Err(err) => {
+            error!("Error reading formats of {}: {}", info.human_name(), err);
+            vec![]
+        }
+    }
 }
 
-pub fn create_camera(info: &CameraInfo) -> Result<Camera, NokhwaError> {
-    let frame_rate = 30;
-    let resolution = Resolution::new(1920, 1080);
+fn supported_formats(info: &CameraInfo) -> Result<Vec<CameraFormatInfo>, NokhwaError> {
+    let requested = RequestedFormat::new::<pixel_format::RgbFormat>(RequestedFormatType::None);
+    let mut camera = Camera::new(info.index().to_owned(), requested)?;
 
+    Ok(camera
+        .compatible_camera_formats()?
+        .into_iter()
+        .map(|format| CameraFormatInfo {
+            format: format.format().to_string(),
+            width: format.width(),
+            height: format.height(),
+            frame_rate: format.frame_rate(),
+        })
+        .collect())
+}
+
+pub fn create_camera(info: &CameraInfo, settings: &CameraSettings) -> Result<Camera, NokhwaError> {
     let requested = RequestedFormat::new::<pixel_format::RgbFormat>(
         RequestedFormatType::ClosestIgnoringFormat {
-            resolution,
-            frame_rate,
+            resolution: Resolution::new(settings.width, settings.height),
+            frame_rate: settings.frame_rate,
         },
     );
-    dbg!(&requested);
+    debug!(?requested, "Requested camera format");
 
     Camera::new(info.index().to_owned(), requested)
 }
 
+/// Find a connected camera by id, index or human name
 pub fn find_camera(selected_device: &String) -> Result<CameraInfo, String> {
-    let backend = native_api_backend().unwrap();
-    let devices = nokhwa::query(backend).map_err(|err| err.to_string())?;
-    println!("There are {} available cameras.", devices.len());
-    dbg!(&devices);
+    let devices = query_cameras()?;
+    info!("There are {} available cameras.", devices.len());
+    debug!(?devices, "Cameras found");
 
     devices
         .into_iter()
-        .find(|device| &device.human_name() == selected_device)
+        .find(|device| {
+            &camera_id(device) == selected_device
+                || &device.index().as_string() == selected_device
+                || &device.human_name() == selected_device
+        })
         .ok_or(format!("Cannot find selected device: {}", selected_device))
 }
 
-pub fn find_and_create_camera(selected_device: &String) -> Result<(CameraInfo, Camera), String> {
+pub fn find_and_create_camera(
+    selected_device: &String,
+    settings: &CameraSettings,
+) -> Result<(CameraInfo, Camera), String> {
     let info = find_camera(selected_device)?;
-    let camera = create_camera(&info).map_err(|err| err.to_string())?;
-    dbg!(camera.camera_format());
-    dbg!(camera.frame_format());
+    let camera = create_camera(&info, settings).map_err(|err| err.to_string())?;
+    debug!(camera_format = ?camera.camera_format(), "Camera format");
+    debug!(frame_format = ?camera.frame_format(), "Frame format");
 
     Ok((info, camera))
 }
 
+/// Backends only report a device held by another app through their error text
+fn is_busy_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "busy",
+        "in use",
+        "could not lock",
+        // MF_E_HW_MFT_FAILED_START_STREAMING and sharing violation on Windows
+        "0xc00d3704",
+        "0x80070020",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+pub(crate) fn camera_error(err: impl ToString) -> CaptureError {
+    let message = err.to_string();
+    if is_busy_error(&message) {
+        CaptureError::DeviceBusy
+    } else {
+        CaptureError::Failed(message)
+    }
+}
+
 /// implement camera controller that handles webcamp capture
 /// on init it takes in camera_tx
 ///
@@ -68,163 +192,147 @@ pub struct CameraSnapshotOptions {
     // pub id: String,
     pub selected_device: String,
     pub compress: bool,
+    /// Seconds to wait before capturing
     pub delay: u64,
+    pub settings: CameraSettings,
+    #[serde(default)]
+    pub mode: WebcamCaptureMode,
+    #[serde(default)]
+    pub backend: CaptureBackend,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum WebcamCaptureMode {
+    #[default]
+    Still,
+    /// Short low resolution video saved as mp4 next to where the still would go
+    Clip { duration_in_seconds: u32, width: u32 },
 }
 
 #[derive(Debug, Clone)]
 pub struct CameraController {}
 
 impl CameraController {
-    pub async fn take_snapshot(options: CameraSnapshotOptions) -> Result<String, String> {
+    /// Take a still or record a clip depending on `options.mode`
+    pub async fn capture(options: CameraSnapshotOptions) -> Result<CaptureOutput, CaptureError> {
+        let WebcamCaptureMode::Clip { duration_in_seconds, width } = options.mode else {
+            let save_path = options.save_path.clone();
+            return CameraController::take_snapshot(options).await.map(|_| vec![save_path].into());
+        };
+
         let is_granted = nokhwa::nokhwa_check();
         if !is_granted {
-            println!("Permission not granted: {is_granted}");
-            return Err("Permission required!".into());
+            warn!("Permission not granted: {is_granted}");
+            return Err(CaptureError::PermissionDenied);
         }
 
-        let save_path = options.save_path.clone();
-
-        let _ = tokio::spawn(tokio::time::sleep(Duration::from_secs(5))).await;
-
-        #[cfg(target_os = "macos")]
-        {
-            let mut cmd = std::process::Command::new(relative_command_path("ffmpeg").unwrap());
-
-            cmd.args(vec!["-ss", "0.5"])
-                .args(vec!["-t", "2"])
-                .args(vec!["-f", "avfoundation"])
-                .args(vec!["-framerate", "30"])
-                .args(vec!["-i", &options.selected_device])
-                .args(vec!["-vf", "scale=720:-1,setdar=16/9"])
-                .args(vec!["-vframes", "1", save_path.to_str().unwrap()]);
-
-            let mut child = cmd
-                .spawn()
-                .unwrap_or_else(|err| panic!("Ffmpeg command not found {:?}", err));
-
-            match child.wait() {
-                Ok(status) if status.success() => {
-                    println!("exited with: {status}");
-                    if options.compress {
-                        compressor::compress_image(
-                            save_path.clone(),
-                            options.save_path.clone().to_path_buf(),
-                        );
-                    }
-                }
-                Ok(status) => eprintln!("exited with: {status}"),
-                Err(e) => println!("error attempting to wait: {e}"),
-            };
+        tokio::time::sleep(Duration::from_secs(options.delay)).await;
+
+        let save_path = options.save_path.with_extension("mp4");
+        let clip = FfmpegBackend::with_default_ffmpeg()
+            .clip(
+                &FfmpegInput::Device(options.selected_device.clone()),
+                &save_path,
+                &options.settings,
+                duration_in_seconds,
+                width,
+            )
+            .await?;
+
+        Ok(CaptureOutput {
+            files: vec![save_path],
+            clip: Some(clip),
+            face: None,
+        })
+    }
 
+    pub async fn take_snapshot(options: CameraSnapshotOptions) -> Result<String, CaptureError> {
+        let is_granted = nokhwa::nokhwa_check();
+        if !is_granted {
+            warn!("Permission not granted: {is_granted}");
+            return Err(CaptureError::PermissionDenied);
         }
 
-        #[cfg(not(target_os = "macos"))]
-        {
-            // make the camera
-            let (info, mut camera) = find_and_create_camera(&options.selected_device)?;
-            dbg!(info);
-
-            camera.open_stream().unwrap();
-
-            // get a frame
-            println!(
-                "Frame format: {:?}, camera_format: {:?}",
-                camera.frame_format(),
-                camera.camera_format()
-            );
-            let frame = camera.frame().unwrap();
-            camera.stop_stream().unwrap();
-            println!("Captured Single Frame of {}", frame.buffer().len());
-
-            let path = save_path.clone();
-
-            match convert_buffer_to_image(frame.clone()) {
-                Ok(image) => {
-                    if let Err(err) = image.save(path) {
-                        println!("Error saving webcam image {:?}", err);
-                    } else {
-                        compressor::compress_image(
-                            save_path.clone(),
-                            options.save_path.clone().to_path_buf(),
-                        );
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error saving webcam image {:?}", err);
-                }
-            };
-
-            // decode into an ImageBuffer
-            let decoded = frame.decode_image::<pixel_format::RgbFormat>().unwrap();
-            println!("Decoded Frame of {}", decoded.len());
-
-            let path = save_path.clone();
-
-            if let Err(err) = decoded.save(path) {
-                eprintln!("Error saving webcam image {:?}", err);
-            } else {
-                compressor::compress_image(
-                    save_path.clone(),
-                    options.save_path.clone().to_path_buf(),
-                );
+        let save_path = options.save_path.clone();
+        // compression writes a jpg next to the original and removes it
+        let capture_dir = save_path.parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+        let mut saved_path = save_path.clone();
+
+        let settings = &options.settings;
+        tokio::time::sleep(Duration::from_secs(options.delay)).await;
+
+        // nokhwa can't grab stills on macOS, avfoundation through ffmpeg can
+        let backend = if cfg!(target_os = "macos") {
+            CaptureBackend::Ffmpeg
+        } else {
+            options.backend
+        };
+
+        match backend {
+            CaptureBackend::Ffmpeg => {
+                FfmpegBackend::with_default_ffmpeg()
+                    .still(&FfmpegInput::Device(options.selected_device.clone()), &save_path, settings)
+                    .await?
             }
+            CaptureBackend::Native => native_snapshot(&options.selected_device, &save_path, settings)?,
         }
 
-        let data = fs::read(options.save_path).await.map_err(|err| err.to_string())?;
+        if options.compress {
+            saved_path = compressor::compress_image(save_path.clone(), capture_dir.clone());
+        }
+
+        let data = encryption::read_file(&saved_path).map_err(|err| err.to_string())?;
         let result = base64::engine::general_purpose::STANDARD.encode(&data);
         Ok(result)
     }
 }
 
-fn relative_command_path(command: impl AsRef<Path>) -> crate::Result<PathBuf> {
-    match platform::current_exe()?.parent() {
-        #[cfg(windows)]
-        Some(exe_dir) => Ok(exe_dir.join(command.as_ref()).with_extension("exe")),
-        #[cfg(not(windows))]
-        Some(exe_dir) => Ok(exe_dir.join(command.as_ref())),
-        None => Err("Error::CurrentExeHasNoParent".into()),
+#[cfg(not(target_os = "macos"))]
+fn native_snapshot(selected_device: &str, save_path: &Path, settings: &CameraSettings) -> Result<(), CaptureError> {
+    // make the camera
+    let (info, mut camera) =
+        find_and_create_camera(&selected_device.to_string(), settings).map_err(camera_error)?;
+    debug!(?info, "Camera info");
+
+    camera.open_stream().map_err(camera_error)?;
+
+    // get a frame
+    info!(
+        "Frame format: {:?}, camera_format: {:?}",
+        camera.frame_format(),
+        camera.camera_format()
+    );
+    // discard warm-up frames so auto exposure can settle
+    let frame = (0..settings.warmup_frames)
+        .try_for_each(|_| camera.frame().map(|_| ()))
+        .and_then(|_| camera.frame());
+    if let Err(err) = camera.stop_stream() {
+        error!("Error stopping camera stream: {:?}", err);
     }
-}
+    let frame = frame.map_err(camera_error)?;
+    info!("Captured Single Frame of {}", frame.buffer().len());
 
-#[allow(dead_code)]
-fn convert_buffer_to_image(
-    buffer: nokhwa::Buffer,
-) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, NokhwaError> {
     let Resolution {
         width_x: width,
         height_y: height,
-    } = buffer.resolution();
-    let mut image_buffer = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(width, height);
-    let data = buffer.buffer();
-
-    for (y, chunk) in data
-        .chunks_exact((width * 2) as usize)
-        .enumerate()
-        .take(height as usize)
-    {
-        for (x, pixel) in chunk.chunks_exact(4).enumerate() {
-            let [u, y1, v, y2] = [
-                pixel[0] as f32,
-                pixel[1] as f32,
-                pixel[2] as f32,
-                pixel[3] as f32,
-            ];
-            let x = (x * 2) as u32;
-            image_buffer.put_pixel(x, y as u32, yuv_to_rgb(y1, u, v));
-            image_buffer.put_pixel(x + 1, y as u32, yuv_to_rgb(y2, u, v));
-        }
+    } = frame.resolution();
+    let format = PixelFormat::from_frame_format(frame.source_frame_format())
+        .ok_or_else(|| format!("Unsupported frame format: {:?}", frame.source_frame_format()))?;
+
+    let mut image = convert_frame(frame.buffer(), width, height, format)?;
+    if let Some(output_width) = settings.output_width {
+        let output_height = (height as u64 * output_width as u64 / width.max(1) as u64) as u32;
+        image = image::imageops::resize(&image, output_width, output_height.max(1), FilterType::Triangle);
     }
+    encryption::save_image(&DynamicImage::ImageRgb8(image), save_path)
+        .map_err(|err| format!("Error saving webcam image {:?}", err))?;
 
-    Ok(image_buffer)
+    Ok(())
 }
 
-//YUV to RGB conversion BT.709
-fn yuv_to_rgb(y: f32, u: f32, v: f32) -> Rgb<u8> {
-    let r = y + 1.5748 * (v - 128.0);
-    let g = y - 0.1873 * (u - 128.0) - 0.4681 * (v - 128.0);
-    let b = y + 1.8556 * (u - 128.0);
-
-    Rgb([r as u8, g as u8, b as u8])
+#[cfg(target_os = "macos")]
+fn native_snapshot(_selected_device: &str, _save_path: &Path, _settings: &CameraSettings) -> Result<(), CaptureError> {
+    Err(CaptureError::Failed("Native webcam capture is not available on macOS".into()))
 }
 
 // fn yuv_to_rgb_bt709(y: f32, u: f32, v: f32) -> Rgb<u8> {
@@ -239,3 +347,28 @@ fn yuv_to_rgb(y: f32, u: f32, v: f32) -> Rgb<u8> {
 //     // (r, g, b)
 //     Rgb([r, g, b])
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_backend_errors_map_to_device_busy() {
+        for message in [
+            "Could not open device: Device or resource busy",
+            "The camera is in use by another application",
+            "Could not lock configuration",
+            "MediaFoundation error 0xC00D3704",
+            "HRESULT 0x80070020 sharing violation",
+        ] {
+            assert!(matches!(camera_error(message), CaptureError::DeviceBusy), "{message}");
+        }
+    }
+
+    #[test]
+    fn other_backend_errors_stay_failures() {
+        let err = camera_error("No cameras available");
+
+        assert!(matches!(err, CaptureError::Failed(message) if message == "No cameras available"));
+    }
+}
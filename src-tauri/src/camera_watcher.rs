@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use nokhwa::utils::CameraInfo;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tracing::{error, info};
+
+use crate::{camera_id, query_cameras, CameraDevice, GeneralConfig, SelectedDevice};
+
+/// Seconds between camera device queries
+pub const CAMERA_POLL_INTERVAL: u64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraDevicesChangedPayload {
+    pub devices: Vec<CameraDevice>,
+    /// Id of the camera now in use
+    pub selected: Option<String>,
+}
+
+/// Pick the camera to use out of the connected devices
+///
+/// The preferred camera wins whenever it is connected, otherwise the current
+/// camera is kept while still connected, falling back to the first device
+pub fn resolve_camera(
+    devices: &[CameraInfo],
+    preferred: Option<&str>,
+    current: Option<&CameraInfo>,
+) -> Option<CameraInfo> {
+    let find = |id: &str| devices.iter().find(|device| camera_id(device) == id);
+
+    preferred
+        .and_then(find)
+        .or_else(|| current.and_then(|current| find(&camera_id(current))))
+        .or_else(|| devices.first())
+        .cloned()
+}
+
+/// Polls the connected cameras and keeps `SelectedDevice` pointing at one that exists
+pub struct CameraWatcher {
+    app: AppHandle,
+    interval: Duration,
+}
+
+impl CameraWatcher {
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            interval: Duration::from_secs(CAMERA_POLL_INTERVAL),
+        }
+    }
+
+    pub fn spawn(self) {
+        tauri::async_runtime::spawn(async move { self.run().await });
+    }
+
+    async fn run(self) {
+        let mut known_devices: Option<Vec<String>> = None;
+
+        loop {
+            let devices = tauri::async_runtime::spawn_blocking(query_cameras)
+                .await
+                .unwrap_or_else(|err| Err(format!("Camera query task failed: {:?}", err)));
+            match devices {
+                Ok(devices) => {
+                    let ids: Vec<String> = devices.iter().map(camera_id).collect();
+                    if known_devices.as_ref() != Some(&ids) {
+                        self.on_devices_changed(&devices);
+                        known_devices = Some(ids);
+                    }
+                }
+                Err(err) => error!("[camera_watcher] {}", err),
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    fn on_devices_changed(&self, devices: &[CameraInfo]) {
+        let preferred = self.app.state::<GeneralConfig>().lock().unwrap().preferred_camera_id.clone();
+
+        let selected_device = self.app.state::<SelectedDevice>();
+        let mut selected_device = selected_device.lock().unwrap();
+        let selected = resolve_camera(devices, preferred.as_deref(), selected_device.as_ref());
+
+        let previous = selected_device.as_ref().map(camera_id);
+        let current = selected.as_ref().map(camera_id);
+        if previous != current {
+            info!("[camera_watcher] camera changed from {:?} to {:?}", previous, current);
+        }
+        *selected_device = selected;
+        drop(selected_device);
+
+        let payload = CameraDevicesChangedPayload {
+            devices: devices.iter().map(CameraDevice::from_info).collect(),
+            selected: current,
+        };
+        if let Err(err) = self.app.emit_all("CameraDevicesChanged", payload) {
+            error!("Error emitting camera devices: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nokhwa::utils::CameraIndex;
+
+    use super::*;
+
+    fn camera(name: &str, index: u32) -> CameraInfo {
+        CameraInfo::new(name, "", &format!("{name}-id"), CameraIndex::Index(index))
+    }
+
+    fn resolved(devices: &[CameraInfo], preferred: Option<&str>, current: Option<&CameraInfo>) -> Option<String> {
+        resolve_camera(devices, preferred, current).as_ref().map(camera_id)
+    }
+
+    #[test]
+    fn preferred_camera_wins_when_plugged_in() {
+        let builtin = camera("builtin", 0);
+        let external = camera("external", 1);
+        let devices = [builtin.clone(), external];
+
+        assert_eq!(
+            resolved(&devices, Some("external-id"), Some(&builtin)),
+            Some("external-id".into())
+        );
+    }
+
+    #[test]
+    fn current_camera_is_kept_while_preferred_is_unplugged() {
+        let builtin = camera("builtin", 0);
+        let usb = camera("usb", 1);
+        let devices = [builtin, usb.clone()];
+
+        assert_eq!(resolved(&devices, Some("external-id"), Some(&usb)), Some("usb-id".into()));
+    }
+
+    #[test]
+    fn falls_back_to_the_first_device() {
+        let builtin = camera("builtin", 0);
+        let unplugged = camera("usb", 1);
+
+        assert_eq!(
+            resolved(&[builtin], Some("external-id"), Some(&unplugged)),
+            Some("builtin-id".into())
+        );
+        assert_eq!(resolved(&[], Some("external-id"), Some(&unplugged)), None);
+    }
+}
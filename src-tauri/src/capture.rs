@@ -0,0 +1,336 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{database::Database, face_check::FacePresence, gen_rand_string, with_local_timezone};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CaptureKind {
+    Screenshot,
+    Webcam,
+}
+
+/// Why a capture was taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureTrigger {
+    #[default]
+    Scheduled,
+    OnDemand,
+    AppSwitch,
+    CapsuleStart,
+    SessionStop,
+}
+
+impl CaptureTrigger {
+    /// Tag added to file names, scheduled captures keep the plain name
+    pub fn tag(&self) -> Option<&'static str> {
+        match self {
+            CaptureTrigger::Scheduled => None,
+            CaptureTrigger::OnDemand => Some("on_demand"),
+            CaptureTrigger::AppSwitch => Some("app_switch"),
+            CaptureTrigger::CapsuleStart => Some("capsule_start"),
+            CaptureTrigger::SessionStop => Some("session_stop"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CaptureOutcome {
+    Ok,
+    SkippedByPolicy(String),
+    PermissionDenied,
+    DeviceBusy,
+    Error(String),
+    /// The capsule ended before the planned capture ran
+    Cancelled,
+    /// Discarded by the user from the capture notice
+    UserVetoed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum CaptureError {
+    SkippedByPolicy(String),
+    PermissionDenied,
+    DeviceBusy,
+    /// No webcam connected or selected
+    NoCamera,
+    Failed(String),
+}
+
+impl CaptureError {
+    /// Failures worth trying again later in the capsule
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, CaptureError::DeviceBusy | CaptureError::Failed(_))
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::SkippedByPolicy(reason) => write!(f, "Capture skipped: {reason}"),
+            CaptureError::PermissionDenied => write!(f, "Permission required!"),
+            CaptureError::DeviceBusy => write!(f, "Capture device is busy"),
+            CaptureError::NoCamera => write!(f, "No camera available"),
+            CaptureError::Failed(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<String> for CaptureError {
+    fn from(err: String) -> Self {
+        CaptureError::Failed(err)
+    }
+}
+
+impl From<&CaptureError> for CaptureOutcome {
+    fn from(err: &CaptureError) -> Self {
+        match err {
+            CaptureError::SkippedByPolicy(reason) => CaptureOutcome::SkippedByPolicy(reason.clone()),
+            CaptureError::PermissionDenied => CaptureOutcome::PermissionDenied,
+            CaptureError::DeviceBusy => CaptureOutcome::DeviceBusy,
+            // nothing to capture from, counted like a policy skip rather than a failure
+            CaptureError::NoCamera => CaptureOutcome::SkippedByPolicy(err.to_string()),
+            CaptureError::Failed(err) => CaptureOutcome::Error(err.clone()),
+        }
+    }
+}
+
+/// A planned capture and what happened to it, saved in the capsule metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    #[serde(default)]
+    pub id: String,
+    pub kind: CaptureKind,
+    #[serde(default)]
+    pub trigger: CaptureTrigger,
+    pub planned_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub attempts: u32,
+    pub outcome: CaptureOutcome,
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub clip: Option<ClipMetadata>,
+    #[serde(default)]
+    pub face: Option<FacePresence>,
+}
+
+impl CaptureRecord {
+    pub fn new(kind: CaptureKind, planned_at: String) -> Self {
+        Self {
+            id: gen_rand_string(16),
+            kind,
+            trigger: CaptureTrigger::Scheduled,
+            planned_at,
+            started_at: None,
+            finished_at: None,
+            attempts: 0,
+            outcome: CaptureOutcome::Cancelled,
+            files: vec![],
+            clip: None,
+            face: None,
+        }
+    }
+}
+
+pub type CaptureLog = Arc<RwLock<Vec<CaptureRecord>>>;
+
+/// Details of a recorded webcam clip
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClipMetadata {
+    pub duration_in_seconds: f64,
+    pub size_in_bytes: u64,
+    pub width: u32,
+}
+
+/// Files saved by a capture, plus clip details and face check results for webcam captures
+#[derive(Debug, Clone, Default)]
+pub struct CaptureOutput {
+    pub files: Vec<PathBuf>,
+    pub clip: Option<ClipMetadata>,
+    pub face: Option<FacePresence>,
+}
+
+impl From<Vec<PathBuf>> for CaptureOutput {
+    fn from(files: Vec<PathBuf>) -> Self {
+        Self {
+            files,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn file_names(files: &[PathBuf]) -> Vec<String> {
+    files
+        .iter()
+        .filter_map(|file| file.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CaptureStats {
+    pub planned: u32,
+    pub ok: u32,
+    pub skipped: u32,
+    pub permission_denied: u32,
+    pub device_busy: u32,
+    pub errors: u32,
+    pub cancelled: u32,
+    pub vetoed: u32,
+    /// Share of attempted captures that succeeded, skipped and cancelled ones excluded
+    pub success_rate: Option<f64>,
+}
+
+impl CaptureStats {
+    fn add(&mut self, outcome: &CaptureOutcome) {
+        self.planned += 1;
+        match outcome {
+            CaptureOutcome::Ok => self.ok += 1,
+            CaptureOutcome::SkippedByPolicy(_) => self.skipped += 1,
+            CaptureOutcome::PermissionDenied => self.permission_denied += 1,
+            CaptureOutcome::DeviceBusy => self.device_busy += 1,
+            CaptureOutcome::Error(_) => self.errors += 1,
+            CaptureOutcome::Cancelled => self.cancelled += 1,
+            CaptureOutcome::UserVetoed => self.vetoed += 1,
+        }
+    }
+
+    fn finish(&mut self) {
+        let attempted = self.planned - self.skipped - self.cancelled;
+        self.success_rate = if attempted == 0 {
+            None
+        } else {
+            // vetoed captures were taken successfully before being discarded
+            Some((self.ok + self.vetoed) as f64 / attempted as f64)
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CaptureHealthReport {
+    pub from: String,
+    pub to: String,
+    pub capsules: u32,
+    pub screenshot: CaptureStats,
+    pub webcam: CaptureStats,
+    /// Set when the database file couldn't be opened, the report then only covers this run
+    pub database_error: Option<String>,
+}
+
+impl CaptureHealthReport {
+    /// Aggregate capture outcomes of all capsules started between `from` and `to` (inclusive, local dates)
+    pub fn collect(database: &Database, from: NaiveDate, to: NaiveDate) -> crate::Result<Self> {
+        let mut report = Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            database_error: database.open_error().map(str::to_string),
+            ..Default::default()
+        };
+
+        // a day either side covers every timezone, the local date is checked below
+        let day = 24 * 60 * 60;
+        let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc().timestamp();
+        for capsule in database.capsules_between(midnight(from) - day, midnight(to) + 2 * day)? {
+            let Ok(started_at) = DateTime::parse_from_rfc2822(&capsule.started_at) else {
+                continue;
+            };
+            let date = with_local_timezone(started_at.with_timezone(&Utc)).date_naive();
+            if date < from || date > to {
+                continue;
+            }
+
+            report.capsules += 1;
+            for record in &database.captures(&capsule.id)? {
+                match record.kind {
+                    CaptureKind::Screenshot => report.screenshot.add(&record.outcome),
+                    CaptureKind::Webcam => report.webcam.add(&record.outcome),
+                }
+            }
+        }
+
+        report.screenshot.finish();
+        report.webcam.finish();
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::session::StorageTimeCapsule;
+
+    fn capsule(id: &str, started_at: &str, outcomes: &[(CaptureKind, CaptureOutcome)]) -> StorageTimeCapsule {
+        StorageTimeCapsule {
+            id: id.into(),
+            session_id: "session".into(),
+            mouse_clicks: vec![],
+            keystrokes: vec![],
+            windows: vec![],
+            started_at: started_at.into(),
+            ended_at: None,
+            captures: outcomes
+                .iter()
+                .map(|(kind, outcome)| {
+                    let mut record = CaptureRecord::new(*kind, started_at.into());
+                    record.outcome = outcome.clone();
+                    record
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn health_comes_from_the_database() {
+        let mut database = Database::open_in_memory().unwrap();
+        let outcomes = [
+            (CaptureKind::Screenshot, CaptureOutcome::Ok),
+            (CaptureKind::Screenshot, CaptureOutcome::Error("failed".into())),
+            (CaptureKind::Screenshot, CaptureOutcome::Cancelled),
+            (CaptureKind::Webcam, CaptureOutcome::UserVetoed),
+            (CaptureKind::Webcam, CaptureOutcome::SkippedByPolicy("Camera is disabled".into())),
+        ];
+        // noon keeps the capsule on the same local date in every timezone
+        database
+            .save_capsule(&capsule("c1", "Wed, 10 Jan 2024 12:00:00 +0000", &outcomes), Path::new("/capsules/c1"))
+            .unwrap();
+        database
+            .save_capsule(&capsule("c2", "Wed, 20 Mar 2024 12:00:00 +0000", &outcomes), Path::new("/capsules/c2"))
+            .unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+
+        let report = CaptureHealthReport::collect(&database, day, day).unwrap();
+
+        assert_eq!(report.capsules, 1);
+        assert_eq!((report.screenshot.planned, report.screenshot.ok, report.screenshot.errors), (3, 1, 1));
+        assert_eq!(report.screenshot.success_rate, Some(0.5));
+        assert_eq!((report.webcam.vetoed, report.webcam.skipped), (1, 1));
+        assert_eq!(report.webcam.success_rate, Some(1.0));
+    }
+
+    #[test]
+    fn health_reports_a_database_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::open_or_in_memory(&dir.path().join("missing").join("worksmart.db"));
+        let day = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+
+        let report = CaptureHealthReport::collect(&database, day, day).unwrap();
+
+        assert!(report.database_error.is_some());
+        assert_eq!(report.capsules, 0);
+        assert!(CaptureHealthReport::collect(&Database::open_in_memory().unwrap(), day, day)
+            .unwrap()
+            .database_error
+            .is_none());
+    }
+}
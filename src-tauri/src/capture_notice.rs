@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tracing::error;
+
+use crate::{
+    capture::{CaptureKind, CaptureLog, CaptureOutcome, CaptureRecord},
+    get_current_date, path_exists,
+    session::StorageTimeCapsule,
+    storage, windows, DatabaseState, GeneralConfig,
+};
+
+/// Capture waiting for its veto window to pass
+#[derive(Debug, Clone)]
+pub struct PendingCapture {
+    pub id: String,
+    pub kind: CaptureKind,
+    pub files: Vec<PathBuf>,
+    pub log: CaptureLog,
+}
+
+pub type PendingCaptures = Arc<Mutex<HashMap<String, PendingCapture>>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureNoticePayload {
+    pub id: String,
+    pub kind: CaptureKind,
+    pub preview: Option<PathBuf>,
+    pub veto_window_in_seconds: u64,
+    pub vetoes_left: u32,
+}
+
+/// Shows a preview of every capture and lets the user discard it within the veto window
+#[derive(Clone)]
+pub struct CaptureNotice {
+    app: AppHandle,
+    veto_window: Duration,
+    max_vetoes_per_day: u32,
+}
+
+impl fmt::Debug for CaptureNotice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CaptureNotice")
+            .field("veto_window", &self.veto_window)
+            .field("max_vetoes_per_day", &self.max_vetoes_per_day)
+            .finish()
+    }
+}
+
+impl CaptureNotice {
+    /// Returns None when capture notices are disabled in preferences
+    pub fn from_app(app: &AppHandle) -> Option<Self> {
+        let preferences = app.state::<GeneralConfig>().lock().unwrap().preferences.clone();
+        if !preferences.capture_notice {
+            return None;
+        }
+
+        Some(Self {
+            app: app.clone(),
+            veto_window: Duration::from_secs(preferences.veto_window_in_seconds),
+            max_vetoes_per_day: preferences.max_vetoes_per_day,
+        })
+    }
+
+    /// Register a finished capture as pending and show the notice window
+    ///
+    /// `record` must already be in `log` so a veto can find it
+    pub fn notify(&self, record: &CaptureRecord, files: &[PathBuf], log: &CaptureLog) {
+        if record.outcome != CaptureOutcome::Ok || files.is_empty() {
+            return;
+        }
+
+        let vetoes_left = self
+            .max_vetoes_per_day
+            .saturating_sub(self.app.state::<VetoLedgerState>().lock().unwrap().get_vetoes_for_today());
+
+        self.app.state::<PendingCaptures>().lock().unwrap().insert(
+            record.id.clone(),
+            PendingCapture {
+                id: record.id.clone(),
+                kind: record.kind,
+                files: files.to_vec(),
+                log: Arc::clone(log),
+            },
+        );
+
+        let payload = CaptureNoticePayload {
+            id: record.id.clone(),
+            kind: record.kind,
+            preview: files.first().cloned(),
+            veto_window_in_seconds: self.veto_window.as_secs(),
+            vetoes_left,
+        };
+
+        windows::show_capture_notice(&self.app);
+        if let Err(err) = self.app.emit_all("CaptureTaken", payload) {
+            error!("Error emitting capture notice: {:?}", err);
+        }
+
+        let app = self.app.clone();
+        let id = record.id.clone();
+        let veto_window = self.veto_window;
+        tokio::spawn(async move {
+            tokio::time::sleep(veto_window).await;
+
+            let expired = app.state::<PendingCaptures>().lock().unwrap().remove(&id);
+            if expired.is_some() {
+                app.emit_all("CaptureNoticeExpired", id).ok();
+            }
+
+            if app.state::<PendingCaptures>().lock().unwrap().is_empty() {
+                windows::close_capture_notice(&app);
+            }
+        });
+    }
+}
+
+/// Discard a pending capture: delete its files and mark it as vetoed
+pub fn veto(app: &AppHandle, id: &str) -> Result<(), String> {
+    let max_vetoes_per_day = app
+        .state::<GeneralConfig>()
+        .lock()
+        .unwrap()
+        .preferences
+        .max_vetoes_per_day;
+
+    let ledger = app.state::<VetoLedgerState>();
+    let mut ledger = ledger.lock().unwrap();
+    let pending = take_pending(&ledger, &app.state::<PendingCaptures>(), max_vetoes_per_day, id)?;
+
+    // the capsule may have been saved before the veto came in
+    if let Some(capsule_dir) = discard(&pending) {
+        if let Some(capsule_id) = capsule_dir.file_name().and_then(|name| name.to_str()) {
+            if let Err(err) = app.state::<DatabaseState>().lock().unwrap().veto_capture(capsule_id, id) {
+                error!("Error updating vetoed capture in database: {:?}", err);
+            }
+        }
+    }
+
+    ledger.increment_vetoes_for_today();
+    ledger.save();
+
+    Ok(())
+}
+
+/// Remove a capture from the pending ones unless the daily cap is reached or its window has passed
+fn take_pending(
+    ledger: &VetoLedger,
+    pending_captures: &PendingCaptures,
+    max_vetoes_per_day: u32,
+    id: &str,
+) -> Result<PendingCapture, String> {
+    if ledger.get_vetoes_for_today() >= max_vetoes_per_day {
+        return Err(format!(
+            "Daily limit of {max_vetoes_per_day} discarded captures reached"
+        ));
+    }
+
+    pending_captures
+        .lock()
+        .unwrap()
+        .remove(id)
+        .ok_or_else(|| "Capture can no longer be discarded".into())
+}
+
+/// Delete the capture's files and mark it vetoed in the log and saved metadata
+///
+/// Returns the capsule folder the files were in
+fn discard(pending: &PendingCapture) -> Option<PathBuf> {
+    for file in &pending.files {
+        if let Err(err) = std::fs::remove_file(file) {
+            error!("Error removing vetoed capture {:?}: {:?}", file, err);
+        }
+    }
+
+    if let Some(record) = pending.log.write().unwrap().iter_mut().find(|record| record.id == pending.id) {
+        record.outcome = CaptureOutcome::UserVetoed;
+        record.files.clear();
+    }
+
+    let capsule_dir = pending.files.first()?.parent()?.to_path_buf();
+    mark_vetoed_in_metadata(capsule_dir.join("metadata.json"), &pending.id);
+
+    Some(capsule_dir)
+}
+
+fn mark_vetoed_in_metadata(path: PathBuf, id: &str) {
+    if !path_exists(&path) {
+        return;
+    }
+
+    match storage::load_capsule_json::<StorageTimeCapsule>(&path) {
+        Ok(mut capsule) => {
+            if let Some(record) = capsule.captures.iter_mut().find(|record| record.id == id) {
+                record.outcome = CaptureOutcome::UserVetoed;
+                record.files.clear();
+                if let Err(err) = storage::save_capsule_json(&capsule, &path) {
+                    error!("Error updating capsule metadata {:?}: {:?}", path, err);
+                }
+            }
+        }
+        Err(err) => error!("Error updating capsule metadata {:?}: {:?}", path, err),
+    }
+}
+
+/// File in the data directory counting today's vetoes
+const VETO_LEDGER_FILE: &str = "vetoes.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VetoLedger {
+    pub history: HashMap<String, u32>,
+}
+
+impl VetoLedger {
+    pub fn load() -> Self {
+        storage::load_json_from_path(storage::data_path().join(VETO_LEDGER_FILE)).unwrap_or_default()
+    }
+
+    pub fn get_vetoes_for_today(&self) -> u32 {
+        let key = get_current_date();
+        self.history.get(&key).map_or(0, |value| value.to_owned())
+    }
+
+    pub fn increment_vetoes_for_today(&mut self) {
+        let key = get_current_date();
+        // older days don't matter for the daily cap
+        self.history.retain(|k, _| k == &key);
+        self.history.entry(key).and_modify(|count| *count += 1).or_insert(1);
+    }
+
+    pub fn save(&self) {
+        if let Err(err) = storage::save_to_data_path(self, VETO_LEDGER_FILE.into()) {
+            error!("Error saving veto ledger: {:?}", err);
+        }
+    }
+}
+
+pub type VetoLedgerState = Arc<Mutex<VetoLedger>>;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use super::*;
+
+    fn pending_capture(dir: &std::path::Path) -> (PendingCaptures, PendingCapture) {
+        let file = dir.join("screenshot.png");
+        std::fs::write(&file, b"png").unwrap();
+
+        let mut record = CaptureRecord::new(CaptureKind::Screenshot, "2024-01-01T09:00:00Z".into());
+        record.id = "capture-1".into();
+        record.outcome = CaptureOutcome::Ok;
+        record.files = vec![file.to_string_lossy().into_owned()];
+
+        let pending = PendingCapture {
+            id: record.id.clone(),
+            kind: record.kind,
+            files: vec![file],
+            log: Arc::new(RwLock::new(vec![record])),
+        };
+        let pending_captures: PendingCaptures = Arc::new(Mutex::new(HashMap::new()));
+        pending_captures.lock().unwrap().insert(pending.id.clone(), pending.clone());
+
+        (pending_captures, pending)
+    }
+
+    #[test]
+    fn daily_cap_keeps_the_capture() {
+        let dir = tempfile::tempdir().unwrap();
+        let (pending_captures, _) = pending_capture(dir.path());
+        let ledger = VetoLedger {
+            history: HashMap::from([(get_current_date(), 3)]),
+        };
+
+        assert!(take_pending(&ledger, &pending_captures, 3, "capture-1").is_err());
+        assert!(pending_captures.lock().unwrap().contains_key("capture-1"));
+        assert!(take_pending(&ledger, &pending_captures, 4, "capture-1").is_ok());
+    }
+
+    #[test]
+    fn veto_after_the_window_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (pending_captures, _) = pending_capture(dir.path());
+        // the notice timer drops the capture once the window closes
+        pending_captures.lock().unwrap().remove("capture-1");
+
+        assert!(take_pending(&VetoLedger::default(), &pending_captures, 3, "capture-1").is_err());
+    }
+
+    #[test]
+    fn discard_deletes_files_and_marks_the_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, pending) = pending_capture(dir.path());
+        let capsule = StorageTimeCapsule {
+            id: "capsule".into(),
+            session_id: "session".into(),
+            mouse_clicks: vec![],
+            keystrokes: vec![],
+            windows: vec![],
+            started_at: "2024-01-01T09:00:00Z".into(),
+            ended_at: None,
+            captures: pending.log.read().unwrap().clone(),
+        };
+        let metadata = dir.path().join("metadata.json");
+        std::fs::write(&metadata, serde_json::to_vec(&capsule).unwrap()).unwrap();
+
+        assert_eq!(discard(&pending).as_deref(), Some(dir.path()));
+
+        assert!(!pending.files[0].exists());
+        let record = &pending.log.read().unwrap()[0];
+        assert_eq!(record.outcome, CaptureOutcome::UserVetoed);
+        assert!(record.files.is_empty());
+
+        let capsule: StorageTimeCapsule = storage::load_capsule_json(&metadata).unwrap();
+        assert_eq!(capsule.captures[0].outcome, CaptureOutcome::UserVetoed);
+        assert!(capsule.captures[0].files.is_empty());
+    }
+
+    #[test]
+    fn ledger_counts_today_only() {
+        let mut ledger = VetoLedger {
+            history: HashMap::from([("2000-1-1".into(), 5)]),
+        };
+
+        ledger.increment_vetoes_for_today();
+        ledger.increment_vetoes_for_today();
+
+        assert_eq!(ledger.get_vetoes_for_today(), 2);
+        assert_eq!(ledger.history.len(), 1);
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(unused_imports)]
 
+use base64::Engine;
 use chrono::Utc;
 use core_graphics::access::ScreenCaptureAccess;
 
@@ -33,10 +34,22 @@ use tokio::{fs, sync::broadcast, time};
 #[allow(unused_imports)]
 use xcap::{Monitor, Window as XcapWindow};
 use yuv::convert::ToRGB;
-
-use crate::session::SessionControllerState;
+use tracing::{debug, error, info, warn};
+
+use crate::capture_notice::{self, CaptureNoticePayload, PendingCaptures, VetoLedgerState};
+use crate::capture::{CaptureError, CaptureRecord, CaptureTrigger};
+use crate::data_move::{self, DataMove};
+use crate::encryption::{self, EncryptionStatus};
+use crate::database::{DatabaseState, StoredSession};
+use crate::diagnostics::{self, Diagnostics, DiagnosticsExport, MonitorInfo};
+use crate::ffmpeg_resolver::{self, FfmpegDiagnostics};
+use crate::logging::{self, LogEntry, LogLevel, LoggerState};
+use crate::storage_manager::{self, RetentionReport, StorageUsage};
+use crate::validation::ConfigError;
+use crate::WorksmartError;
+use crate::session::{CurrentCapsule, SessionControllerState};
 use crate::time_map::{TimeTrackerMap, TrackHistory};
-use crate::{path_exists, storage, windows, AppWindow, Auth, AuthConfig, CameraController, Configuration, PermisssionsStatus, SelectedDevice};
+use crate::{camera_id, find_camera, load_formats, path_exists, query_cameras, CameraDevice, storage, windows, AppWindow, Auth, AuthConfig, CameraController, CaptureHealthReport, Configuration, PermisssionsStatus, SelectedDevice};
 
 use crate::{
     configuration, gen_rand_string, get_current_datetime,
@@ -46,44 +59,6 @@ use crate::{
 
 use scap::capturer::{Area, Capturer, Options, Point, Size};
 
-#[tauri::command]
-pub async fn record_screen(
-    // window: Window,
-    record_channel: State<'_, RecordChannel>,
-) -> Result<(), ()> {
-    let supported = scap::is_supported();
-    if !supported {
-        eprintln!("Platform is not supported ❌");
-    } else {
-        println!("✅ Platform is supported");
-    }
-
-    if !scap::has_permission() {
-        eprintln!("❌ Permission not granted. Requesting permission...");
-        if !scap::request_permission() {
-            println!("❌ Permission denied");
-            // return Err("Permission denied".into());
-            return Err(());
-        }
-    }
-
-    println!("✅ Permission granted");
-
-    record_channel
-        .try_send(crate::RecordCommand::Start)
-        .expect("Can't start recording");
-
-    tokio::time::sleep(Duration::from_secs(10)).await;
-
-    println!("Send stop siganl");
-
-    record_channel
-        .try_send(crate::RecordCommand::Stop)
-        .expect("Can't start recording");
-
-    Ok(())
-}
-
 #[allow(dead_code)]
 fn normalized(filename: &str) -> String {
     filename
@@ -101,16 +76,35 @@ pub struct SessionDetail {
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub async fn start_session(
     window: Window,
     session_rx: State<'_, SessionChannel>,
     session: State<'_, SessionState>,
     session_controller: State<'_, SessionControllerState>,
-) -> Result<Option<SessionDetail>, ()> {
+    database: State<'_, DatabaseState>,
+    general_config: State<'_, GeneralConfig>,
+) -> Result<Option<SessionDetail>, WorksmartError> {
     let sesh = session.lock().unwrap().clone();
     if sesh.is_running {
         return Ok(None);
     }
+    if data_move::in_progress() {
+        return Err(WorksmartError::Session(
+            "Data is being moved, try again once it's done or retry it in Settings".into(),
+        ));
+    }
+    // capsules would land on the system disk where the other disk is mounted
+    if let Some(dir) = general_config.lock().unwrap().missing_storage_dirs().first() {
+        return Err(WorksmartError::Session(format!(
+            "{:?} isn't available, connect its disk or move the data in Settings",
+            dir
+        )));
+    }
+    // capsule metadata couldn't be saved without the key
+    if encryption::writer_key().is_err() {
+        return Err(WorksmartError::Session("Enter the capsule passphrase before starting a session".into()));
+    }
 
     let app_handle = window.app_handle();
 
@@ -128,17 +122,35 @@ pub async fn start_session(
 
     session_controller.lock().unwrap().start();
 
+    let mut stored_session = StoredSession {
+        id: id.clone(),
+        started_at: started_at.clone(),
+        ended_at: None,
+    };
+    if let Err(err) = database.lock().unwrap().save_session(&stored_session) {
+        error!("Error saving session: {:?}", err);
+    }
+
     let active_session = session.lock().unwrap().clone();
 
     let handle = app_handle.clone();
+    let database = database.inner().clone();
     tokio::spawn(async move {
         if let Err(err) = active_session.start(app_handle).await {
-            println!("Session Error: {:?}", err);
+            error!("Session Error: {:?}", err);
         }
+        let ended_at = get_current_datetime().to_rfc3339();
         handle.state::<SessionState>().lock().unwrap().is_running = false;
-        handle.state::<SessionState>().lock().unwrap().ended_at = Some(get_current_datetime().to_rfc3339());
-        handle.emit_all("SessionEnded", ()).unwrap();
-        println!("Close session#start thread");
+        handle.state::<SessionState>().lock().unwrap().ended_at = Some(ended_at.clone());
+
+        stored_session.ended_at = Some(ended_at);
+        if let Err(err) = database.lock().unwrap().save_session(&stored_session) {
+            error!("Error saving session: {:?}", err);
+        }
+        if let Err(err) = handle.emit_all("SessionEnded", ()) {
+            error!("Error emitting session end: {:?}", err);
+        }
+        info!("Close session#start thread");
     });
 
     Ok(Some(SessionDetail {
@@ -150,12 +162,13 @@ pub async fn start_session(
 
 use tauri::api::dialog::blocking::{confirm, ask};
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub async fn stop_session(
     window: Window,
     session: State<'_, SessionState>,
     session_rx: State<'_, SessionChannel>,
     session_controller: State<'_, SessionControllerState>,
-) -> Result<(), ()> {
+) -> Result<(), WorksmartError> {
     if !session.lock().unwrap().is_running {
         return Ok(());
     }
@@ -164,16 +177,18 @@ pub async fn stop_session(
 
     if end_after_current_session {
         session_controller.lock().unwrap().shutdown();
-        dbg!(session_controller.lock().unwrap().is_shutdown());
+        debug!(is_shutdown = session_controller.lock().unwrap().is_shutdown(), "Session will end after this capsule");
     } else {
-        session_rx.send(()).unwrap();
+        session_rx
+            .send(())
+            .map_err(|err| WorksmartError::Session(format!("Can't stop session: {err}")))?;
     }
 
     Ok(())
 }
 
 #[tauri::command]
-pub async fn get_session(session: State<'_, SessionState>) -> Result<Option<SessionDetail>, ()> {
+pub async fn get_session(session: State<'_, SessionState>) -> Result<Option<SessionDetail>, WorksmartError> {
     if session.lock().unwrap().started_at.is_none() {
         return Ok(None);
     }
@@ -225,33 +240,53 @@ pub fn on_permissions_granted(window: Window, auth: State<'_, AuthConfig>) {
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub fn set_preferences(
     general_config: State<'_, GeneralConfig>,
+    logger: State<'_, LoggerState>,
     preferences: Configuration,
-) -> Result<(), String> {
-     dbg!(&preferences);
+) -> Result<(), WorksmartError> {
+    debug!(?preferences, "Setting preferences");
+    preferences.validate().map_err(ConfigError::Invalid)?;
+    let current = general_config.lock().unwrap().clone();
+    // capsules sealed with the old key couldn't be read with the new one
+    if preferences.encryption.key_source != current.encryption.key_source
+        && encryption::has_encrypted_capsules(&current.capsule_dir()).map_err(WorksmartError::storage)?
+    {
+        return Err(WorksmartError::InvalidInput(
+            "Capsules are encrypted with the current key, the key source can't change while they exist".into(),
+        ));
+    }
+    encryption::configure(&preferences.encryption).map_err(WorksmartError::storage)?;
+    if preferences.log_level != current.log_level {
+        logger.set_level(preferences.log_level)?;
+    }
     *general_config.lock().unwrap() = preferences;
 
-    storage::save(&general_config.lock().unwrap().clone());
+    general_config
+        .lock()
+        .unwrap()
+        .save()
+        .map_err(|err| ConfigError::SaveFailed(err.to_string()))?;
 
     Ok(())
 }
 
 #[tauri::command]
-pub fn get_preferences(general_config: State<'_, GeneralConfig>) -> Result<Configuration, String> {
+pub fn get_preferences(general_config: State<'_, GeneralConfig>) -> Result<Configuration, WorksmartError> {
     let config = general_config.lock().unwrap().clone();
-    dbg!(&config);
+    debug!(?config, "Getting preferences");
     Ok(config)
 }
 
 #[tauri::command]
-pub fn get_auth(auth_config: State<'_, AuthConfig>) -> Result<Option<Auth>, String> {
+pub fn get_auth(auth_config: State<'_, AuthConfig>) -> Result<Option<Auth>, WorksmartError> {
     // ideally take general config struct from client and save first
     // then assign it as new general_config: config_state.lock().unwrap() = configuration
 
     let auth = auth_config.lock().unwrap().clone();
 
-    println!("Get auth: {:?}", &auth);
+    debug!(signed_in = auth.is_some(), "Get auth");
 
     Ok(auth)
 }
@@ -261,36 +296,43 @@ pub fn get_auth(auth_config: State<'_, AuthConfig>) -> Result<Option<Auth>, Stri
 pub async fn webcam_capture(
     general_config: State<'_, GeneralConfig>,
     selected_device: State<'_, SelectedDevice>,
-) -> Result<String, String> {
-    let device = selected_device.lock().unwrap().clone();
+) -> Result<String, WorksmartError> {
     let config = general_config.lock().unwrap().clone();
+    if !config.enable_camera {
+        return Err(CaptureError::SkippedByPolicy("Camera is disabled".into()).into());
+    }
 
-    let save_path = storage::data_path().join(config.media_storage_dir);
+    let device = selected_device
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or(CaptureError::NoCamera)?;
+
+    let save_path = config.media_dir();
     let file_path = save_path.join("preview.png");
 
     if path_exists(&file_path) {
-        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(&file_path)?;
     }
 
     if !path_exists(&save_path) {
-        std::fs::create_dir_all(&save_path).unwrap();
+        std::fs::create_dir_all(&save_path)?;
     }
 
-    // let preview = file_path.clone().to_str().unwrap().to_owned();
-
     let img_data = CameraController::
             take_snapshot(
                 crate::CameraSnapshotOptions {
                     delay: config.preferences.webcam_delay,
+                    settings: config.preferences.camera.clone(),
+                    mode: crate::WebcamCaptureMode::Still,
+                    backend: config.preferences.capture_backend,
                     save_path: file_path,
                     compress: false,
-                    selected_device: device.human_name() }
+                    selected_device: device.index().as_string() }
             ).await.map_err(|err| {
-                 eprint!("CameraController Error: {:?}", err);
-                 err.to_string()
+                 error!(?err, "Camera test failed");
+                 err
             })?;
-    // tauri::async_runtime::spawn(async move {
-    // });
 
     Ok(img_data)
 }
@@ -300,13 +342,13 @@ pub fn login(
     auth_config: State<'_, AuthConfig>,
     window: Window,
     payload: Auth,
-) -> Result<(), String> {
-    println!("Login: {:?}", &payload);
+) -> Result<(), WorksmartError> {
+    debug!("Logging in");
     let handle = window.app_handle();
     windows::close_login(&handle);
 
     *auth_config.lock().unwrap() = Some(payload.clone());
-    storage::save_to_path(&payload, storage::auth_path::<Auth>()).map_err(|err| err.to_string())?;
+    storage::save_to_path(&payload, storage::auth_path::<Auth>()).map_err(WorksmartError::storage)?;
 
     windows::show_tracker(&handle);
 
@@ -319,7 +361,7 @@ pub struct ShowWindowPayload {
 }
 
 #[tauri::command]
-pub fn show_window(app: AppHandle, name: String) -> Result<(), String> {
+pub fn show_window(app: AppHandle, name: String) -> Result<(), WorksmartError> {
     let window = AppWindow::from_label(&name);
     window.show(&app).ok();
     Ok(())
@@ -332,11 +374,9 @@ pub fn hide_window(window: Window, _name: String) {
 }
 
 #[tauri::command]
-pub fn minimize_window(window: Window, name: String) -> Result<(), String> {
+pub fn minimize_window(window: Window, name: String) -> Result<(), WorksmartError> {
     if let Some(window) = window.app_handle().get_window(&name) {
-        window
-            .minimize()
-            .map_err(|err| format!("Error minimizing window: {err}"))?;
+        window.minimize()?;
         return Ok(());
     }
 
@@ -344,56 +384,64 @@ pub fn minimize_window(window: Window, name: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn list_camera_devices() -> Result<Vec<String>, String> {
-    let backend = native_api_backend().unwrap();
-    let devices = nokhwa::query(backend).map_err(|err| {
-        println!("nokhwa::query(backend) error: {:?}", err);
-        format!("Error listing camera devices: {:?}", err)
+pub fn list_camera_devices() -> Result<Vec<CameraDevice>, WorksmartError> {
+    let devices = query_cameras().map_err(|err| {
+        error!("{}", err);
+        WorksmartError::Camera(format!("Error listing camera devices: {}", err))
     })?;
-    println!("[list_camera_devices] {:?}", devices);
-    Ok(devices.iter().map(|camera| camera.human_name()).collect())
+    info!("[list_camera_devices] {:?}", devices);
+    Ok(devices.iter().map(CameraDevice::from_info).collect())
 }
 
 #[tauri::command]
-pub fn select_camera_device(selected_device: State<'_, SelectedDevice>, name: String) {
-    let backend = native_api_backend().unwrap();
-    match nokhwa::query(backend) {
-        Ok(devices) => {
-            let mut devices = devices;
-            devices.retain(|camera| camera.human_name() == name.as_str());
+pub fn select_camera_device(
+    general_config: State<'_, GeneralConfig>,
+    selected_device: State<'_, SelectedDevice>,
+    id: String,
+) -> Result<CameraDevice, WorksmartError> {
+    let camera = find_camera(&id).map_err(|err| {
+        error!("[select_camera_device] {}", err);
+        CaptureError::NoCamera
+    })?;
 
-            println!("[camera_devices] {:?}", devices);
+    info!("Selected device: {:?}", camera);
+    let camera_id = camera_id(&camera);
+    // only the camera picked by the user is opened to read its formats
+    load_formats(&camera);
+    let device = CameraDevice::from_info(&camera);
+    *selected_device.lock().unwrap() = Some(camera);
 
-            #[allow(clippy::get_first)]
-            if let Some(camera) = devices.get(0) {
-                println!("Selected device: {:?}", camera);
-                *selected_device.lock().unwrap() = camera.to_owned();
-            }
-        }
-        Err(err) => {
-            println!("nokhwa::query(backend) error: {:?}", err);
-        }
-    };
+    // restored at startup and when the camera is plugged back in
+    let mut config = general_config.lock().unwrap();
+    config.preferred_camera_id = Some(camera_id);
+    config.save().map_err(|err| ConfigError::SaveFailed(err.to_string()))?;
+
+    Ok(device)
 }
 
 #[tauri::command]
 pub fn get_selected_camera_device(
     selected_device: State<'_, SelectedDevice>,
-) -> Result<CameraInfo, String> {
-    Ok(selected_device.lock().unwrap().clone())
+) -> Result<CameraDevice, WorksmartError> {
+    selected_device
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(CameraDevice::from_info)
+        .ok_or(WorksmartError::Capture(CaptureError::NoCamera))
 }
 
 #[tauri::command]
 pub fn get_track_history(
     time_tracker: State<'_, TimeTrackerMap>,
-) -> Result<TrackHistory, String> {
+) -> Result<TrackHistory, WorksmartError> {
     Ok(time_tracker.lock().unwrap().clone())
 }
 
 #[tauri::command]
 pub fn get_time_tracked_today(
     time_tracker: State<'_, TimeTrackerMap>,
-) -> Result<u64, String> {
+) -> Result<u64, WorksmartError> {
     Ok(time_tracker.lock().unwrap().get_track_for_today())
 }
 
@@ -402,3 +450,328 @@ pub fn quit_app(
 ) {
     std::process::exit(0);
 }
+
+#[tauri::command]
+pub fn get_capture_health(
+    database: State<'_, DatabaseState>,
+    from: String,
+    to: String,
+) -> Result<CaptureHealthReport, WorksmartError> {
+    let parse_date = |date: &str| {
+        chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|err| WorksmartError::InvalidInput(format!("Invalid date {date}: {err}")))
+    };
+    let (from, to) = (parse_date(&from)?, parse_date(&to)?);
+
+    CaptureHealthReport::collect(&database.lock().unwrap(), from, to).map_err(WorksmartError::storage)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app), err)]
+pub fn veto_capture(app: AppHandle, id: String) -> Result<(), WorksmartError> {
+    capture_notice::veto(&app, &id).map_err(WorksmartError::VetoRejected)
+}
+
+#[tauri::command]
+pub fn get_pending_captures(
+    general_config: State<'_, GeneralConfig>,
+    pending_captures: State<'_, PendingCaptures>,
+    veto_ledger: State<'_, VetoLedgerState>,
+) -> Result<Vec<CaptureNoticePayload>, WorksmartError> {
+    let preferences = general_config.lock().unwrap().preferences.clone();
+    let vetoes_left = preferences
+        .max_vetoes_per_day
+        .saturating_sub(veto_ledger.lock().unwrap().get_vetoes_for_today());
+
+    Ok(pending_captures
+        .lock()
+        .unwrap()
+        .values()
+        .map(|pending| CaptureNoticePayload {
+            id: pending.id.clone(),
+            kind: pending.kind,
+            preview: pending.files.first().cloned(),
+            veto_window_in_seconds: preferences.veto_window_in_seconds,
+            vetoes_left,
+        })
+        .collect())
+}
+
+/// A pending capture's file as a data URL, decrypted when capsules are encrypted
+///
+/// Only files inside the capsule folders are read
+#[tauri::command]
+#[tracing::instrument(skip(general_config), err)]
+pub async fn get_capture_preview(general_config: State<'_, GeneralConfig>, path: PathBuf) -> Result<String, WorksmartError> {
+    let capsule_dir = general_config.lock().unwrap().capsule_dir();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let inside = path
+            .canonicalize()
+            .ok()
+            .zip(capsule_dir.canonicalize().ok())
+            .is_some_and(|(path, capsule_dir)| path.starts_with(capsule_dir));
+        if !inside {
+            return Err(WorksmartError::InvalidInput(format!("{:?} is not a capsule file", path)));
+        }
+
+        let mime = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("mp4") => "video/mp4",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            _ => "image/png",
+        };
+        let data = encryption::read_file(&path).map_err(WorksmartError::storage)?;
+        Ok(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(data)))
+    })
+    .await
+    .map_err(|err| WorksmartError::Internal(format!("Error reading preview: {err}")))?
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn capture_now(
+    app: AppHandle,
+    current_capsule: State<'_, CurrentCapsule>,
+) -> Result<CaptureRecord, WorksmartError> {
+    let Some(capsule) = current_capsule.lock().unwrap().clone() else {
+        return Err(WorksmartError::NoSession);
+    };
+
+    Ok(capsule.trigger_screenshot(&app, CaptureTrigger::OnDemand).await)
+}
+
+/// Re-check where ffmpeg comes from and whether it has the encoders captures need
+#[tauri::command]
+pub async fn get_ffmpeg_diagnostics(
+    general_config: State<'_, GeneralConfig>,
+) -> Result<FfmpegDiagnostics, WorksmartError> {
+    let config_path = general_config.lock().unwrap().ffmpeg_path.clone();
+
+    tauri::async_runtime::spawn_blocking(move || ffmpeg_resolver::diagnose(config_path.as_deref()))
+        .await
+        .map_err(|err| WorksmartError::Internal(format!("Error checking ffmpeg: {err}")))
+}
+
+#[tauri::command]
+pub fn set_log_level(
+    logger: State<'_, LoggerState>,
+    general_config: State<'_, GeneralConfig>,
+    level: LogLevel,
+) -> Result<(), WorksmartError> {
+    logger.set_level(level)?;
+
+    let mut config = general_config.lock().unwrap();
+    config.log_level = level;
+    config.save().map_err(|err| ConfigError::SaveFailed(err.to_string()))?;
+
+    Ok(())
+}
+
+/// Newest log entries first, `level` drops anything less severe
+#[tauri::command]
+pub async fn get_recent_logs(limit: usize, level: Option<LogLevel>) -> Result<Vec<LogEntry>, WorksmartError> {
+    tauri::async_runtime::spawn_blocking(move || logging::recent_entries(limit, level.unwrap_or(LogLevel::Trace)))
+        .await
+        .map_err(|err| WorksmartError::Internal(format!("Error reading logs: {err}")))?
+        .map_err(WorksmartError::storage)
+}
+
+/// Zip logs, redacted settings, devices and recent capsule manifests for a bug report
+///
+/// Written to `path`, or under the data directory when none is given
+#[tauri::command]
+#[tracing::instrument(skip(app, general_config, auth_config), err)]
+pub async fn export_diagnostics(
+    app: AppHandle,
+    general_config: State<'_, GeneralConfig>,
+    auth_config: State<'_, AuthConfig>,
+    path: Option<PathBuf>,
+    capsule_count: Option<usize>,
+) -> Result<DiagnosticsExport, WorksmartError> {
+    let configuration = general_config.lock().unwrap().clone();
+    let secrets = auth_config
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|auth| auth.token.clone())
+        .chain(encryption::key_material_paths().iter().map(|path| path.to_string_lossy().to_string()))
+        .collect();
+    let version = app.package_info().version.to_string();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let capsule_dir = configuration.capsule_dir();
+        let logs = logging::recent_entries(diagnostics::LOG_ENTRY_LIMIT, LogLevel::Trace).unwrap_or_else(|err| {
+            error!("Error reading logs for diagnostics: {}", err);
+            vec![]
+        });
+        let diagnostics = Diagnostics {
+            app: diagnostics::AppInfo::new(version),
+            permissions: PermisssionsStatus::get_status(),
+            cameras: query_cameras().map(|devices| devices.iter().map(CameraDevice::from_info).collect()),
+            monitors: MonitorInfo::list(),
+            ffmpeg: ffmpeg_resolver::diagnose(configuration.ffmpeg_path.as_deref()),
+            configuration,
+            logs,
+            secrets,
+        };
+
+        let path = path.unwrap_or_else(diagnostics::default_export_path);
+        diagnostics.export(&path, &capsule_dir, capsule_count.unwrap_or(diagnostics::DEFAULT_CAPSULE_COUNT))
+    })
+    .await
+    .map_err(|err| WorksmartError::Internal(format!("Error exporting diagnostics: {err}")))?
+    .map_err(WorksmartError::storage)
+}
+
+/// Move capsules and media into `target`, reporting `DataMoveProgress` events
+///
+/// An interrupted move is resumed on the next launch, a failed one waits for
+/// `retry_data_move` or `abandon_data_move`
+#[tauri::command]
+#[tracing::instrument(skip(app, general_config, session, database), err)]
+pub async fn move_data_directory(
+    app: AppHandle,
+    general_config: State<'_, GeneralConfig>,
+    session: State<'_, SessionState>,
+    database: State<'_, DatabaseState>,
+    target: PathBuf,
+) -> Result<(), WorksmartError> {
+    if session.lock().unwrap().is_running {
+        return Err(WorksmartError::Session("Stop the session before moving data".into()));
+    }
+    if data_move::in_progress() {
+        return Err(WorksmartError::InvalidInput("Data is already being moved".into()));
+    }
+
+    let (capsule_dir, media_dir) = {
+        let config = general_config.lock().unwrap();
+        (config.capsule_dir(), config.media_dir())
+    };
+    let data_move =
+        DataMove::plan(capsule_dir, media_dir, &target).map_err(|err| WorksmartError::InvalidInput(err.to_string()))?;
+
+    run_data_move(app, &general_config, &database, data_move).await
+}
+
+/// The unfinished data move with the error that stopped it, if any
+#[tauri::command]
+pub fn get_data_move() -> Result<Option<DataMove>, WorksmartError> {
+    Ok(DataMove::unfinished())
+}
+
+/// Run the failed data move again from where it stopped
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn retry_data_move(
+    app: AppHandle,
+    general_config: State<'_, GeneralConfig>,
+    database: State<'_, DatabaseState>,
+) -> Result<(), WorksmartError> {
+    let data_move = failed_data_move()?;
+
+    run_data_move(app, &general_config, &database, data_move).await
+}
+
+/// Move everything the failed data move already moved back to the old folders
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn abandon_data_move(
+    app: AppHandle,
+    general_config: State<'_, GeneralConfig>,
+    database: State<'_, DatabaseState>,
+) -> Result<(), WorksmartError> {
+    let data_move = failed_data_move()?.abandon().map_err(WorksmartError::storage)?;
+
+    run_data_move(app, &general_config, &database, data_move).await
+}
+
+/// Moves still running have no error yet and can't be retried or abandoned
+fn failed_data_move() -> Result<DataMove, WorksmartError> {
+    DataMove::unfinished()
+        .filter(|data_move| data_move.error.is_some())
+        .ok_or(WorksmartError::InvalidInput("No failed data move".into()))
+}
+
+async fn run_data_move(
+    app: AppHandle,
+    general_config: &GeneralConfig,
+    database: &DatabaseState,
+    data_move: DataMove,
+) -> Result<(), WorksmartError> {
+    let general_config = general_config.clone();
+    let database = database.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        data_move.run(&general_config, &database, |progress| {
+            if let Err(err) = app.emit_all("DataMoveProgress", progress) {
+                error!("Error emitting data move progress: {:?}", err);
+            }
+        })
+    })
+    .await
+    .map_err(|err| WorksmartError::Internal(format!("Error moving data: {err}")))?
+    .map_err(WorksmartError::storage)
+}
+
+/// Disk usage of capsules per day, for the storage settings
+#[tauri::command]
+pub async fn get_storage_usage(
+    general_config: State<'_, GeneralConfig>,
+    database: State<'_, DatabaseState>,
+) -> Result<StorageUsage, WorksmartError> {
+    let (capsule_dir, retention) = {
+        let config = general_config.lock().unwrap();
+        (config.capsule_dir(), config.preferences.retention.clone())
+    };
+
+    let database = database.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || storage_manager::usage(&capsule_dir, &retention, &database.lock().unwrap()))
+        .await
+        .map_err(|err| WorksmartError::Internal(format!("Error reading storage usage: {err}")))?
+        .map_err(WorksmartError::storage)
+}
+
+/// Apply the retention settings now instead of waiting for the next sweep
+#[tauri::command]
+#[tracing::instrument(skip(general_config, database), err)]
+pub async fn enforce_retention(
+    general_config: State<'_, GeneralConfig>,
+    database: State<'_, DatabaseState>,
+) -> Result<RetentionReport, WorksmartError> {
+    // capsules are half way between two folders
+    if data_move::in_progress() {
+        return Err(WorksmartError::InvalidInput(
+            "Data is being moved, try again once it's done or retry it in Settings".into(),
+        ));
+    }
+
+    let (capsule_dir, retention) = {
+        let config = general_config.lock().unwrap();
+        (config.capsule_dir(), config.preferences.retention.clone())
+    };
+
+    let database = database.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        storage_manager::enforce_retention(&capsule_dir, &retention, &database, get_current_datetime())
+    })
+    .await
+    .map_err(|err| WorksmartError::Internal(format!("Error enforcing retention: {err}")))?
+    .map_err(WorksmartError::storage)
+}
+
+#[tauri::command]
+pub fn get_encryption_status() -> Result<EncryptionStatus, WorksmartError> {
+    Ok(encryption::status())
+}
+
+/// Enter the passphrase capsules are encrypted with, the first one entered sets it
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn unlock_encryption(passphrase: String) -> Result<EncryptionStatus, WorksmartError> {
+    // key derivation is slow on purpose
+    tauri::async_runtime::spawn_blocking(move || encryption::unlock(&passphrase))
+        .await
+        .map_err(|err| WorksmartError::Internal(format!("Error unlocking encryption: {err}")))?
+        .map_err(|err| WorksmartError::InvalidInput(err.to_string()))?;
+
+    Ok(encryption::status())
+}
@@ -1,18 +1,50 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
 use image_compressor::compressor::Compressor;
 use image_compressor::Factor;
 use std::fs;
+use tracing::error;
 
-pub fn compress_image(source:PathBuf, dest: PathBuf) {
-    let mut comp = Compressor::new(source.clone(), dest);
-    comp.set_factor(Factor::new(80., 0.8));
-    if let Err(err) = comp.compress_to_jpg() {
-        // todo: trace error
-        eprintln!("Failed to compress image {:?}", err);
+use crate::encryption;
+
+const JPEG_QUALITY: u8 = 80;
+
+/// Compress `source` to a jpg inside `dest` and remove the source
+///
+/// Returns the path of the file left on disk, which is the source if compression failed
+pub fn compress_image(source:PathBuf, dest: PathBuf) -> PathBuf {
+    let compressed = dest
+        .join(source.file_stem().unwrap_or_default())
+        .with_extension("jpg");
+    let result = if matches!(encryption::writer_key(), Ok(Some(_))) {
+        // the compressor reads and writes plain files
+        compress_sealed(&source, &compressed)
     } else {
-        fs::remove_file(source.clone()).unwrap_or_else(|err| {
-            // todo: trace error
-            eprintln!("Failed to remove source file after compression. source: {:?} {:?}", source.to_str(), err);
-        });
+        let mut comp = Compressor::new(source.clone(), dest);
+        comp.set_factor(Factor::new(JPEG_QUALITY as f32, 0.8));
+        comp.compress_to_jpg().map(|_| ()).map_err(|err| format!("{:?}", err).into())
+    };
+    match result {
+        Err(err) => {
+            error!("Failed to compress image {:?}", err);
+            source
+        }
+        Ok(_) => {
+            fs::remove_file(source.clone()).unwrap_or_else(|err| {
+                error!("Failed to remove source file after compression. source: {:?} {:?}", source.to_str(), err);
+            });
+            compressed
+        }
     }
 }
+
+/// Same quality and scale as the compressor, without the plaintext ever leaving memory
+fn compress_sealed(source: &Path, compressed: &Path) -> crate::Result<()> {
+    let image = encryption::load_image(source)?;
+    let image = image.resize(image.width() * 4 / 5, image.height() * 4 / 5, FilterType::Triangle);
+
+    let mut data = vec![];
+    JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode_image(&image.to_rgb8())?;
+    encryption::write_file(compressed, &data)
+}
@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, info};
+
+use crate::{storage, Configuration};
+
+/// Schema version written to `config.json`, bump it and add a migration on breaking changes
+///
+/// 1. Layout of the bincode files from before `config.json`
+/// 2. `config.json`, fields added since version 1 take their defaults
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Layout of `config.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct VersionedConfig {
+    version: u32,
+    config: Value,
+}
+
+/// Load `config.json`, importing the bincode configuration on first run
+///
+/// Invalid fields are reset to their defaults. Returns None when neither file
+/// exists. When neither `config.json` nor its backup can be read the file is
+/// moved aside to `config.json.corrupt` so the defaults can replace it
+pub fn load() -> crate::Result<Option<Configuration>> {
+    let path = storage::config_file_path();
+    if !path.exists() && !storage::sibling_path(&path, "bak").exists() {
+        return import_legacy();
+    }
+
+    match storage::read_with_backup(&path, parse) {
+        Ok(config) => Ok(Some(config.repaired())),
+        Err(err) => {
+            move_aside(&path, "corrupt");
+            Err(format!("Error reading {:?}: {}", path, err).into())
+        }
+    }
+}
+
+pub fn save(config: &Configuration) -> crate::Result<()> {
+    let versioned = VersionedConfig {
+        version: CONFIG_VERSION,
+        config: serde_json::to_value(config)?,
+    };
+
+    storage::write_with_backup(&storage::config_file_path(), &serde_json::to_vec_pretty(&versioned)?, |current| {
+        parse(current).is_ok()
+    })
+}
+
+/// Missing fields take their default value and unknown fields are dropped
+fn parse(data: &[u8]) -> crate::Result<Configuration> {
+    let versioned: VersionedConfig = serde_json::from_slice(data)?;
+    if versioned.version > CONFIG_VERSION {
+        return Err(format!(
+            "Configuration version {} is newer than {}, update the app",
+            versioned.version, CONFIG_VERSION
+        )
+        .into());
+    }
+
+    let config = migrate(versioned.config, versioned.version)?;
+    Ok(serde_json::from_value(config)?)
+}
+
+/// Bring a configuration written by an older version up to `CONFIG_VERSION`
+pub fn migrate(mut config: Value, version: u32) -> crate::Result<Value> {
+    for from in version..CONFIG_VERSION {
+        config = match from {
+            1 => migrate_v1(config),
+            _ => return Err(format!("No migration from configuration version {}", from).into()),
+        };
+    }
+
+    Ok(config)
+}
+
+/// Nothing was renamed or removed, `parse` fills in the fields added since
+fn migrate_v1(config: Value) -> Value {
+    config
+}
+
+/// Configuration as first released, bincode has no field names so it needs the exact layout
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyConfiguration {
+    capsule_storage_dir: PathBuf,
+    media_storage_dir: PathBuf,
+    launch_on_startup: bool,
+    signin_on_launch: bool,
+    track_on_signin: bool,
+    enable_camera: bool,
+    preferences: LegacyPreferences,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyPreferences {
+    time_gap_duration_in_seconds: u64,
+    webcam_delay: u64,
+}
+
+/// Move the bincode configuration over to `config.json`, once
+fn import_legacy() -> crate::Result<Option<Configuration>> {
+    let legacy_path = storage::config_path::<Configuration>();
+    if !legacy_path.exists() {
+        return Ok(None);
+    }
+
+    let Some(config) = read_legacy(&legacy_path).map(Configuration::repaired) else {
+        return Ok(None);
+    };
+    save(&config)?;
+    info!("Imported configuration from {:?}", legacy_path);
+
+    Ok(Some(config))
+}
+
+/// Decode the bincode configuration and move the file aside, even when it can't be read
+fn read_legacy(legacy_path: &Path) -> Option<Configuration> {
+    let config = std::fs::read(legacy_path)
+        .map_err(crate::Error::from)
+        .and_then(|data| Ok(bincode::deserialize::<LegacyConfiguration>(&data)?))
+        .and_then(|legacy| migrate(serde_json::to_value(legacy)?, 1))
+        .and_then(|config| Ok(serde_json::from_value::<Configuration>(config)?))
+        .map_err(|err| error!("Error importing {:?}: {}", legacy_path, err))
+        .ok();
+
+    move_aside(legacy_path, "bak");
+
+    config
+}
+
+fn move_aside(path: &Path, extension: &str) {
+    if let Err(err) = std::fs::rename(path, storage::sibling_path(path, extension)) {
+        error!("Error moving {:?} aside: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Preferences;
+
+    fn legacy() -> LegacyConfiguration {
+        LegacyConfiguration {
+            capsule_storage_dir: PathBuf::from("capsules"),
+            media_storage_dir: PathBuf::from("media"),
+            launch_on_startup: true,
+            signin_on_launch: false,
+            track_on_signin: true,
+            enable_camera: true,
+            preferences: LegacyPreferences {
+                time_gap_duration_in_seconds: 300,
+                webcam_delay: 7,
+            },
+        }
+    }
+
+    fn versioned(version: u32, config: Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({ "version": version, "config": config })).unwrap()
+    }
+
+    #[test]
+    fn migrates_version_1_to_current() {
+        let config = parse(&versioned(1, serde_json::to_value(legacy()).unwrap())).unwrap();
+
+        assert!(config.launch_on_startup && config.track_on_signin && config.enable_camera);
+        assert_eq!(config.preferences.time_gap_duration_in_seconds, 300);
+        assert_eq!(config.preferences.webcam_delay, 7);
+        assert_eq!(config.preferred_camera_id, None);
+        assert_eq!(config.preferences.max_vetoes_per_day, Preferences::default().max_vetoes_per_day);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let err = parse(&versioned(CONFIG_VERSION + 1, json!({}))).unwrap_err();
+
+        assert!(err.to_string().contains("newer"), "{err}");
+    }
+
+    #[test]
+    fn drops_unknown_fields_and_defaults_missing_ones() {
+        let config = parse(&versioned(
+            CONFIG_VERSION,
+            json!({ "enable_camera": true, "bogus": 1, "preferences": { "webcam_delay": 5, "unknown": "x" } }),
+        ))
+        .unwrap();
+
+        assert!(config.enable_camera);
+        assert_eq!(config.capsule_storage_dir, Configuration::initial().capsule_storage_dir);
+        assert_eq!(config.preferences.webcam_delay, 5);
+        assert_eq!(
+            config.preferences.time_gap_duration_in_seconds,
+            Preferences::default().time_gap_duration_in_seconds
+        );
+    }
+
+    #[test]
+    fn imports_legacy_bincode_and_moves_it_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join("worksmart-configuration-Configuration.bin");
+        std::fs::write(&legacy_path, bincode::serialize(&legacy()).unwrap()).unwrap();
+
+        let config = read_legacy(&legacy_path).unwrap();
+
+        assert_eq!(config.preferences.time_gap_duration_in_seconds, 300);
+        assert_eq!(config.preferences.webcam_delay, 7);
+        assert!(config.enable_camera);
+        assert!(!legacy_path.exists());
+        assert!(storage::sibling_path(&legacy_path, "bak").exists());
+    }
+
+    #[test]
+    fn unreadable_legacy_files_are_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join("worksmart-configuration-Configuration.bin");
+        std::fs::write(&legacy_path, [1, 2]).unwrap();
+
+        assert!(read_legacy(&legacy_path).is_none());
+        assert!(storage::sibling_path(&legacy_path, "bak").exists());
+    }
+}
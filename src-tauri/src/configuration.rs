@@ -6,46 +6,108 @@ use std::{
 
 use nokhwa::utils::CameraInfo;
 use serde::{Deserialize, Serialize};
+use tracing::error;
 
-use crate::storage;
+use crate::{config_migration, logging::LogLevel, storage, CaptureBackend, CaptureStrategy, WebcamCaptureMode};
 
+/// Saved to `config.json`, missing fields fall back to the defaults
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default = "Configuration::initial")]
 pub struct Configuration {
+    /// Relative paths are inside the data directory, absolute ones are used as is
     pub capsule_storage_dir: PathBuf,
     pub media_storage_dir: PathBuf,
     pub launch_on_startup: bool,
     pub signin_on_launch: bool,
     pub track_on_signin: bool,
     pub enable_camera: bool,
+    /// Id of the camera picked by the user, used whenever it is connected
+    pub preferred_camera_id: Option<String>,
+    /// ffmpeg to use before the bundled sidecar and `$PATH`
+    pub ffmpeg_path: Option<PathBuf>,
+    /// Least severe level written to the log files
+    pub log_level: LogLevel,
+    pub encryption: EncryptionSettings,
     pub preferences: Preferences,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
-        if let Ok(this) = storage::load::<Self>() {
-            return this;
+        match config_migration::load() {
+            Ok(Some(this)) => return this,
+            Ok(None) => {}
+            Err(err) => error!("{}", err),
         }
 
-        let this = Self {
+        let this = Self::initial();
+        if let Err(err) = this.save() {
+            error!("Error saving configuration: {}", err);
+        }
+
+        this
+    }
+}
+
+impl Configuration {
+    /// Defaults for a fresh install, unlike `default` nothing is read from disk
+    pub fn initial() -> Self {
+        Self {
             capsule_storage_dir: PathBuf::from_str("capsules").unwrap(),
             media_storage_dir: PathBuf::from_str("media").unwrap(),
             launch_on_startup: false,
             signin_on_launch: false,
             track_on_signin: false,
             enable_camera: false,
+            preferred_camera_id: None,
+            ffmpeg_path: None,
+            log_level: LogLevel::default(),
+            encryption: EncryptionSettings::default(),
             preferences: Preferences::default(),
-        };
+        }
+    }
 
-        storage::save(&this);
+    pub fn save(&self) -> crate::Result<()> {
+        config_migration::save(self)
+    }
 
-        this
+    pub fn capsule_dir(&self) -> PathBuf {
+        storage::data_path().join(&self.capsule_storage_dir)
+    }
+
+    pub fn media_dir(&self) -> PathBuf {
+        storage::data_path().join(&self.media_storage_dir)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Preferences {
     pub time_gap_duration_in_seconds: u64,
     pub webcam_delay: u64,
+    pub enable_screen_recording: bool,
+    pub screen_recording_fps: u32,
+    pub capture_strategy: CaptureStrategy,
+    pub screenshot_count: u32,
+    pub webcam_shot_count: u32,
+    pub capture_retries: u32,
+    pub capture_notice: bool,
+    pub veto_window_in_seconds: u64,
+    pub max_vetoes_per_day: u32,
+    pub capture_triggers: CaptureTriggers,
+    pub camera: CameraSettings,
+    pub webcam_capture_mode: WebcamCaptureMode,
+    pub capture_backend: CaptureBackend,
+    /// Check webcam snapshots for a face with the bundled offline model
+    pub face_check: bool,
+    /// Retry a busy webcam later in the capsule instead of skipping the capture
+    pub retry_busy_camera: bool,
+    pub busy_camera_retry_delay_in_seconds: u64,
+    /// Apps that use the camera, webcam captures count as busy while one is focused
+    ///
+    /// A heuristic on the focused window, not device-level detection: a camera
+    /// held by a background app is only noticed when opening it fails
+    pub camera_apps: Vec<String>,
+    pub retention: RetentionSettings,
 }
 
 impl Default for Preferences {
@@ -53,10 +115,101 @@ impl Default for Preferences {
         Self {
             time_gap_duration_in_seconds: 60,
             webcam_delay: 3,
+            enable_screen_recording: false,
+            screen_recording_fps: 1,
+            capture_strategy: CaptureStrategy::default(),
+            screenshot_count: 1,
+            webcam_shot_count: 1,
+            capture_retries: 1,
+            capture_notice: false,
+            veto_window_in_seconds: 10,
+            max_vetoes_per_day: 3,
+            capture_triggers: CaptureTriggers::default(),
+            camera: CameraSettings::default(),
+            webcam_capture_mode: WebcamCaptureMode::default(),
+            capture_backend: CaptureBackend::default(),
+            face_check: false,
+            retry_busy_camera: true,
+            busy_camera_retry_delay_in_seconds: 60,
+            camera_apps: ["zoom.us", "Microsoft Teams", "FaceTime", "Webex", "Google Meet"]
+                .iter()
+                .map(|app| app.to_string())
+                .collect(),
+            retention: RetentionSettings::default(),
+        }
+    }
+}
+
+/// Events that take an extra screenshot on top of the capture plan
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureTriggers {
+    pub on_capsule_start: bool,
+    pub before_session_stop: bool,
+    /// App names that trigger a screenshot when switched to
+    pub watched_apps: Vec<String>,
+}
+
+/// Webcam capture settings, applied on every backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+    /// Frames discarded after the camera opens so auto exposure can settle
+    pub warmup_frames: u32,
+    /// Width of the saved image, the height keeps the aspect ratio. None keeps the captured size
+    pub output_width: Option<u32>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            frame_rate: 30,
+            warmup_frames: 15,
+            output_width: Some(720),
         }
     }
 }
 
 pub type GeneralConfig = Arc<Mutex<Configuration>>;
 
-pub type SelectedDevice = Arc<Mutex<CameraInfo>>;
+/// None when no camera is connected
+pub type SelectedDevice = Arc<Mutex<Option<CameraInfo>>>;
+
+/// How long capsule data is kept and how much of the disk it may use, 0 disables a limit
+///
+/// Every limit is off until the user sets one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionSettings {
+    /// Media of the oldest capsules is deleted first once the capsule folders grow past this
+    pub max_storage_in_mb: u64,
+    /// Screenshots and screen recordings
+    pub screenshot_days: u32,
+    pub webcam_days: u32,
+    /// The whole capsule folder is deleted once its metadata expires
+    pub metadata_days: u32,
+    /// Media captures pause while the disk has less free space than this
+    pub min_free_disk_in_mb: u64,
+}
+
+/// Encryption of everything written into capsule folders
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionSettings {
+    pub enabled: bool,
+    pub key_source: KeySource,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeySource {
+    /// Random key kept next to the configuration, nothing to type
+    #[default]
+    KeyFile,
+    /// Key derived from a passphrase the user enters after every launch
+    Passphrase,
+}
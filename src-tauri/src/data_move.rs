@@ -0,0 +1,300 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{storage, DatabaseState, GeneralConfig};
+
+/// Journal of a move in progress, kept in the data directory which never moves
+pub const DATA_MOVE_FILE: &str = "data_move.json";
+
+/// A folder or file moving to its new parent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DataMoveProgress {
+    pub moved: usize,
+    pub total: usize,
+    /// Entry being moved, None once everything is in place
+    pub current: Option<PathBuf>,
+    pub done: bool,
+}
+
+/// Moves capsules and media into a new directory
+///
+/// Every entry is moved on its own and the journal is saved after each one,
+/// so a move cut short by a crash or quit picks up where it stopped. Entries
+/// are renamed when possible, across disks they are copied to a `.partial`
+/// sibling first and only renamed into place once complete. A failed move
+/// keeps its error in the journal until the user retries or abandons it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataMove {
+    pub capsule_dir: MoveEntry,
+    pub media_dir: MoveEntry,
+    pub pending: Vec<MoveEntry>,
+    /// Entries already in their new place, moved back when the move is abandoned
+    #[serde(default)]
+    pub moved: Vec<MoveEntry>,
+    pub total: usize,
+    /// Why the last run stopped
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl DataMove {
+    /// Plan moving the capsule and media folders into `target`, keeping their names
+    pub fn plan(capsule_dir: PathBuf, media_dir: PathBuf, target: &Path) -> crate::Result<Self> {
+        if !target.is_absolute() {
+            return Err(format!("{:?} is not an absolute path", target).into());
+        }
+        if target.starts_with(&capsule_dir) || target.starts_with(&media_dir) {
+            return Err(format!("Can't move data into itself: {:?}", target).into());
+        }
+
+        let dir_name = |dir: &Path, default: &str| target.join(dir.file_name().unwrap_or(OsStr::new(default)));
+        let capsule_dir = MoveEntry {
+            to: dir_name(&capsule_dir, "capsules"),
+            from: capsule_dir,
+        };
+        let media_dir = MoveEntry {
+            to: dir_name(&media_dir, "media"),
+            from: media_dir,
+        };
+        if capsule_dir.to == media_dir.to {
+            return Err(format!("Capsules and media would both move to {:?}", capsule_dir.to).into());
+        }
+
+        let mut pending = vec![];
+        for dir in [&capsule_dir, &media_dir] {
+            fs::create_dir_all(&dir.to).map_err(|err| format!("Error creating {:?}: {}", dir.to, err))?;
+            if dir.from == dir.to || !dir.from.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir.from)? {
+                let from = entry?.path();
+                let Some(name) = from.file_name() else {
+                    continue;
+                };
+                // an existing target would be taken for an entry already moved
+                let to = dir.to.join(name);
+                if to.exists() {
+                    return Err(format!("{:?} already exists", to).into());
+                }
+                pending.push(MoveEntry { from, to });
+            }
+        }
+
+        Ok(Self {
+            total: pending.len(),
+            capsule_dir,
+            media_dir,
+            pending,
+            moved: vec![],
+            error: None,
+        })
+    }
+
+    /// The move left unfinished by the last run
+    pub fn unfinished() -> Option<Self> {
+        let path = journal_path();
+        if !path.exists() {
+            return None;
+        }
+
+        match storage::load_json_from_path(path) {
+            Ok(this) => Some(this),
+            Err(err) => {
+                error!("Error reading data move journal: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Move the entries already moved back where they came from
+    ///
+    /// The reversed move is run like any other, so it can be resumed or retried too
+    pub fn abandon(self) -> crate::Result<Self> {
+        if let Some(entry) = self.pending.first() {
+            let partial = storage::sibling_path(&entry.to, "partial");
+            if partial.exists() {
+                remove(&partial)?;
+            }
+        }
+
+        // the old folders are removed once everything has moved out
+        for dir in [&self.capsule_dir.from, &self.media_dir.from] {
+            fs::create_dir_all(dir)?;
+        }
+
+        let reverse = |entry: &MoveEntry| MoveEntry {
+            from: entry.to.clone(),
+            to: entry.from.clone(),
+        };
+        let pending: Vec<MoveEntry> = self.moved.iter().rev().map(reverse).collect();
+        info!("Abandoning data move to {:?}, moving {} entries back", self.capsule_dir.to, pending.len());
+
+        Ok(Self {
+            capsule_dir: reverse(&self.capsule_dir),
+            media_dir: reverse(&self.media_dir),
+            total: pending.len(),
+            pending,
+            moved: vec![],
+            error: None,
+        })
+    }
+
+    /// Move everything left, then point the configuration and database at the new folders
+    pub fn run(
+        mut self,
+        config: &GeneralConfig,
+        database: &DatabaseState,
+        mut progress: impl FnMut(DataMoveProgress),
+    ) -> crate::Result<()> {
+        self.error = None;
+        self.save()?;
+
+        let relocated = match self.move_all(config, database, &mut progress) {
+            Ok(relocated) => relocated,
+            Err(err) => {
+                self.error = Some(err.to_string());
+                self.save()?;
+                return Err(err);
+            }
+        };
+        for path in [journal_path(), storage::sibling_path(&journal_path(), "bak")] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        info!(
+            moved = self.total,
+            relocated,
+            "Moved data to {:?} and {:?}",
+            self.capsule_dir.to,
+            self.media_dir.to
+        );
+        progress(self.progress(None));
+
+        Ok(())
+    }
+
+    fn move_all(
+        &mut self,
+        config: &GeneralConfig,
+        database: &DatabaseState,
+        progress: &mut impl FnMut(DataMoveProgress),
+    ) -> crate::Result<usize> {
+        while let Some(entry) = self.pending.first().cloned() {
+            progress(self.progress(Some(entry.from.clone())));
+            move_entry(&entry).map_err(|err| format!("Error moving {:?} to {:?}: {}", entry.from, entry.to, err))?;
+            self.moved.push(self.pending.remove(0));
+            self.save()?;
+        }
+
+        {
+            let mut config = config.lock().unwrap();
+            config.capsule_storage_dir = self.capsule_dir.to.clone();
+            config.media_storage_dir = self.media_dir.to.clone();
+            config.save()?;
+        }
+        let relocated = database
+            .lock()
+            .unwrap()
+            .relocate_capsules(&self.capsule_dir.from, &self.capsule_dir.to)?;
+
+        for dir in [&self.capsule_dir, &self.media_dir] {
+            // only removed when empty, anything written meanwhile stays put
+            if dir.from != dir.to {
+                fs::remove_dir(&dir.from).ok();
+            }
+        }
+
+        Ok(relocated)
+    }
+
+    fn progress(&self, current: Option<PathBuf>) -> DataMoveProgress {
+        DataMoveProgress {
+            moved: self.total - self.pending.len(),
+            total: self.total,
+            done: current.is_none(),
+            current,
+        }
+    }
+
+    fn save(&self) -> crate::Result<()> {
+        storage::write_with_backup(&journal_path(), &serde_json::to_vec_pretty(self)?, |current| {
+            serde_json::from_slice::<Self>(current).is_ok()
+        })
+    }
+}
+
+/// Whether a move is underway or waiting to be resumed
+pub fn in_progress() -> bool {
+    journal_path().exists()
+}
+
+fn journal_path() -> PathBuf {
+    storage::data_path().join(DATA_MOVE_FILE)
+}
+
+/// Safe to repeat after an interruption at any point
+fn move_entry(entry: &MoveEntry) -> std::io::Result<()> {
+    let MoveEntry { from, to } = entry;
+
+    if to.exists() {
+        // the copy was renamed into place but the source wasn't removed yet
+        if from.exists() {
+            remove(from)?;
+        }
+        return Ok(());
+    }
+    if !from.exists() {
+        warn!("{:?} is gone, nothing to move", from);
+        return Ok(());
+    }
+
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    // different disks, copy then swap in
+    let partial = storage::sibling_path(to, "partial");
+    if partial.exists() {
+        remove(&partial)?;
+    }
+    copy(from, &partial)?;
+    fs::rename(&partial, to)?;
+    remove(from)
+}
+
+fn copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !from.is_dir() {
+        fs::copy(from, to)?;
+        return fs::File::open(to)?.sync_all();
+    }
+
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+fn remove(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
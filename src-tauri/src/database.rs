@@ -0,0 +1,815 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    capture::{CaptureKind, CaptureOutcome, CaptureRecord},
+    session::{StorageTimeCapsule, WindowEntry},
+    storage,
+};
+
+/// File name of the database inside the data directory
+pub const DATABASE_FILE: &str = "worksmart.db";
+
+/// Schema changes, `PRAGMA user_version` holds how many have been applied
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        started_at TEXT NOT NULL,
+        ended_at TEXT
+    );
+
+    CREATE TABLE capsules (
+        id TEXT PRIMARY KEY,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        started_at TEXT NOT NULL,
+        started_ts INTEGER NOT NULL,
+        ended_at TEXT,
+        storage_path TEXT NOT NULL,
+        mouse_clicks INTEGER NOT NULL,
+        keystrokes INTEGER NOT NULL
+    );
+    CREATE INDEX capsules_started_ts ON capsules(started_ts);
+    CREATE INDEX capsules_session_id ON capsules(session_id);
+
+    CREATE TABLE window_entries (
+        id INTEGER PRIMARY KEY,
+        capsule_id TEXT NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        title TEXT NOT NULL,
+        time TEXT NOT NULL
+    );
+    CREATE INDEX window_entries_capsule_id ON window_entries(capsule_id);
+
+    CREATE TABLE input_aggregates (
+        capsule_id TEXT NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+        minute_ts INTEGER NOT NULL,
+        mouse_clicks INTEGER NOT NULL,
+        keystrokes INTEGER NOT NULL,
+        PRIMARY KEY (capsule_id, minute_ts)
+    );
+
+    CREATE TABLE captures (
+        id INTEGER PRIMARY KEY,
+        capsule_id TEXT NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+        capture_id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        outcome TEXT NOT NULL,
+        planned_at TEXT NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE INDEX captures_capsule_id ON captures(capsule_id);
+
+    CREATE TABLE media (
+        id INTEGER PRIMARY KEY,
+        capsule_id TEXT NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+        capture_id TEXT,
+        kind TEXT NOT NULL,
+        path TEXT NOT NULL
+    );
+    CREATE INDEX media_capsule_id ON media(capsule_id);
+
+    CREATE TABLE daily_totals (
+        date TEXT PRIMARY KEY,
+        tracked_seconds INTEGER NOT NULL
+    );
+"#];
+
+pub type DatabaseState = Arc<Mutex<Database>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub id: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredCapsule {
+    pub id: String,
+    pub session_id: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    /// Folder holding the capsule's media files
+    pub storage_path: PathBuf,
+    pub mouse_clicks: u32,
+    pub keystrokes: u32,
+}
+
+/// Input counts for one minute of a capsule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAggregate {
+    /// Unix timestamp of the start of the minute
+    pub minute_ts: i64,
+    pub mouse_clicks: u32,
+    pub keystrokes: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MediaKind {
+    Screenshot,
+    Webcam,
+    ScreenRecording,
+}
+
+impl MediaKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MediaKind::Screenshot => "Screenshot",
+            MediaKind::Webcam => "Webcam",
+            MediaKind::ScreenRecording => "ScreenRecording",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "Screenshot" => Some(MediaKind::Screenshot),
+            "Webcam" => Some(MediaKind::Webcam),
+            "ScreenRecording" => Some(MediaKind::ScreenRecording),
+            _ => None,
+        }
+    }
+}
+
+/// A media file kept in the capsule folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaRef {
+    /// The capture that saved the file, None for screen recordings
+    pub capture_id: Option<String>,
+    pub kind: MediaKind,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub imported: u32,
+    /// Capsules already in the database
+    pub skipped: u32,
+    pub failed: u32,
+}
+
+/// SQLite store for sessions, capsules and everything recorded in them
+///
+/// Media files stay in the capsule folders, the database only references them
+pub struct Database {
+    connection: Connection,
+    /// Why the database file couldn't be opened when running in memory instead
+    open_error: Option<String>,
+}
+
+impl Database {
+    /// Open `worksmart.db` in the data directory
+    pub fn open_default() -> crate::Result<Self> {
+        Self::open(&storage::data_path().join(DATABASE_FILE))
+    }
+
+    pub fn open(path: &Path) -> crate::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> crate::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// `open_default`, falling back to memory when the file can't be opened
+    pub fn open_default_or_in_memory() -> Self {
+        Self::open_or_in_memory(&storage::data_path().join(DATABASE_FILE))
+    }
+
+    /// Open `path`, falling back to memory when it can't be opened
+    ///
+    /// Capsules recorded meanwhile still have their folders, which are
+    /// imported once the file opens again
+    pub fn open_or_in_memory(path: &Path) -> Self {
+        let err = match Self::open(path) {
+            Ok(this) => return this,
+            Err(err) => err,
+        };
+        error!("Error opening database {:?}, keeping it in memory until restart: {}", path, err);
+
+        let mut this = Self::open_in_memory().expect("Can't open an in-memory database");
+        this.open_error = Some(err.to_string());
+        this
+    }
+
+    /// Why the database file couldn't be opened, None when it is in use
+    pub fn open_error(&self) -> Option<&str> {
+        self.open_error.as_deref()
+    }
+
+    fn init(connection: Connection) -> crate::Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+
+        let mut this = Self {
+            connection,
+            open_error: None,
+        };
+        this.migrate()?;
+
+        Ok(this)
+    }
+
+    fn migrate(&mut self) -> crate::Result<()> {
+        let version: usize = self.connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            return Err(format!("Database version {} is newer than {}, update the app", version, MIGRATIONS.len()).into());
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = self.connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+            info!("Migrated database to version {}", index + 1);
+        }
+
+        Ok(())
+    }
+
+    pub fn save_session(&self, session: &StoredSession) -> crate::Result<()> {
+        self.connection.execute(
+            "INSERT INTO sessions (id, started_at, ended_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET ended_at = excluded.ended_at",
+            params![session.id, session.started_at, session.ended_at],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn sessions(&self) -> crate::Result<Vec<StoredSession>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, started_at, ended_at FROM sessions ORDER BY started_at")?;
+        let sessions = statement
+            .query_map([], |row| {
+                Ok(StoredSession {
+                    id: row.get(0)?,
+                    started_at: row.get(1)?,
+                    ended_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(sessions)
+    }
+
+    /// Insert or replace a capsule with its windows, input, captures and media
+    pub fn save_capsule(&mut self, capsule: &StorageTimeCapsule, storage_path: &Path) -> crate::Result<()> {
+        // a capsule without a start time would never be found by date nor expire
+        let started_ts = timestamp(&capsule.started_at)
+            .ok_or_else(|| format!("Capsule {} has an unreadable start time {:?}", capsule.id, capsule.started_at))?;
+        let transaction = self.connection.transaction()?;
+
+        // capsules saved before the session row existed, or imported from disk
+        transaction.execute(
+            "INSERT OR IGNORE INTO sessions (id, started_at) VALUES (?1, ?2)",
+            params![capsule.session_id, capsule.started_at],
+        )?;
+        transaction.execute("DELETE FROM capsules WHERE id = ?1", params![capsule.id])?;
+        transaction.execute(
+            "INSERT INTO capsules (id, session_id, started_at, started_ts, ended_at, storage_path, mouse_clicks, keystrokes)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                capsule.id,
+                capsule.session_id,
+                capsule.started_at,
+                started_ts,
+                capsule.ended_at,
+                storage_path.to_string_lossy(),
+                capsule.mouse_clicks.len(),
+                capsule.keystrokes.len(),
+            ],
+        )?;
+
+        for window in &capsule.windows {
+            transaction.execute(
+                "INSERT INTO window_entries (capsule_id, name, title, time) VALUES (?1, ?2, ?3, ?4)",
+                params![capsule.id, window.name, window.title, window.time],
+            )?;
+        }
+
+        for aggregate in aggregate_input(&capsule.mouse_clicks, &capsule.keystrokes) {
+            transaction.execute(
+                "INSERT INTO input_aggregates (capsule_id, minute_ts, mouse_clicks, keystrokes) VALUES (?1, ?2, ?3, ?4)",
+                params![capsule.id, aggregate.minute_ts, aggregate.mouse_clicks, aggregate.keystrokes],
+            )?;
+        }
+
+        for record in &capsule.captures {
+            transaction.execute(
+                "INSERT INTO captures (capsule_id, capture_id, kind, outcome, planned_at, record)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    capsule.id,
+                    record.id,
+                    format!("{:?}", record.kind),
+                    outcome_name(&record.outcome),
+                    record.planned_at,
+                    serde_json::to_string(record)?,
+                ],
+            )?;
+
+            let kind = match record.kind {
+                CaptureKind::Screenshot => MediaKind::Screenshot,
+                CaptureKind::Webcam => MediaKind::Webcam,
+            };
+            for file in &record.files {
+                insert_media(&transaction, &capsule.id, Some(&record.id), kind, file)?;
+            }
+        }
+
+        for recording in screen_recordings(storage_path) {
+            insert_media(&transaction, &capsule.id, None, MediaKind::ScreenRecording, &recording)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn has_capsule(&self, id: &str) -> crate::Result<bool> {
+        let found = self
+            .connection
+            .query_row("SELECT 1 FROM capsules WHERE id = ?1", params![id], |_| Ok(()))
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    /// Capsules started between the two unix timestamps, `to` excluded
+    pub fn capsules_between(&self, from: i64, to: i64) -> crate::Result<Vec<StoredCapsule>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, session_id, started_at, ended_at, storage_path, mouse_clicks, keystrokes
+             FROM capsules WHERE started_ts >= ?1 AND started_ts < ?2 ORDER BY started_ts",
+        )?;
+        let capsules = statement
+            .query_map(params![from, to], |row| {
+                Ok(StoredCapsule {
+                    id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    started_at: row.get(2)?,
+                    ended_at: row.get(3)?,
+                    storage_path: PathBuf::from(row.get::<_, String>(4)?),
+                    mouse_clicks: row.get(5)?,
+                    keystrokes: row.get(6)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(capsules)
+    }
+
+    pub fn windows(&self, capsule_id: &str) -> crate::Result<Vec<WindowEntry>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, title, time FROM window_entries WHERE capsule_id = ?1 ORDER BY id")?;
+        let windows = statement
+            .query_map(params![capsule_id], |row| {
+                Ok(WindowEntry {
+                    name: row.get(0)?,
+                    title: row.get(1)?,
+                    time: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(windows)
+    }
+
+    pub fn input_aggregates(&self, capsule_id: &str) -> crate::Result<Vec<InputAggregate>> {
+        let mut statement = self.connection.prepare(
+            "SELECT minute_ts, mouse_clicks, keystrokes FROM input_aggregates WHERE capsule_id = ?1 ORDER BY minute_ts",
+        )?;
+        let aggregates = statement
+            .query_map(params![capsule_id], |row| {
+                Ok(InputAggregate {
+                    minute_ts: row.get(0)?,
+                    mouse_clicks: row.get(1)?,
+                    keystrokes: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(aggregates)
+    }
+
+    pub fn captures(&self, capsule_id: &str) -> crate::Result<Vec<CaptureRecord>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT record FROM captures WHERE capsule_id = ?1 ORDER BY id")?;
+        let records = statement
+            .query_map(params![capsule_id], |row| row.get::<_, String>(0))?
+            .map(|record| Ok(serde_json::from_str(&record?)?))
+            .collect::<crate::Result<_>>()?;
+
+        Ok(records)
+    }
+
+    /// Media files with their full path
+    pub fn media(&self, capsule_id: &str) -> crate::Result<Vec<MediaRef>> {
+        let mut statement = self.connection.prepare(
+            "SELECT media.capture_id, media.kind, capsules.storage_path, media.path
+             FROM media JOIN capsules ON capsules.id = media.capsule_id
+             WHERE media.capsule_id = ?1 ORDER BY media.id",
+        )?;
+        let media = statement
+            .query_map(params![capsule_id], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .filter_map(|row| match row {
+                Ok((capture_id, kind, storage_path, path)) => Some(Ok(MediaRef {
+                    capture_id,
+                    kind: MediaKind::parse(&kind)?,
+                    path: Path::new(&storage_path).join(path),
+                })),
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(media)
+    }
+
+    /// Forget the capsule's files of these kinds, used once they are deleted
+    pub fn forget_media(&self, capsule_id: &str, kinds: &[MediaKind]) -> crate::Result<()> {
+        for kind in kinds {
+            self.connection.execute(
+                "DELETE FROM media WHERE capsule_id = ?1 AND kind = ?2",
+                params![capsule_id, kind.as_str()],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove a capsule and everything recorded in it
+    pub fn delete_capsule(&self, capsule_id: &str) -> crate::Result<()> {
+        self.connection
+            .execute("DELETE FROM capsules WHERE id = ?1", params![capsule_id])?;
+
+        Ok(())
+    }
+
+    /// Point capsules stored under `from` to the same folders under `to`
+    pub fn relocate_capsules(&mut self, from: &Path, to: &Path) -> crate::Result<usize> {
+        let transaction = self.connection.transaction()?;
+
+        let capsules: Vec<(String, String)> = transaction
+            .prepare("SELECT id, storage_path FROM capsules")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+
+        let mut relocated = 0;
+        for (id, storage_path) in capsules {
+            let Ok(relative) = Path::new(&storage_path).strip_prefix(from) else {
+                continue;
+            };
+            transaction.execute(
+                "UPDATE capsules SET storage_path = ?1 WHERE id = ?2",
+                params![to.join(relative).to_string_lossy(), id],
+            )?;
+            relocated += 1;
+        }
+        transaction.commit()?;
+
+        Ok(relocated)
+    }
+
+    /// Mark a capture as vetoed and forget its files
+    pub fn veto_capture(&mut self, capsule_id: &str, capture_id: &str) -> crate::Result<()> {
+        let transaction = self.connection.transaction()?;
+        let record: Option<String> = transaction
+            .query_row(
+                "SELECT record FROM captures WHERE capsule_id = ?1 AND capture_id = ?2",
+                params![capsule_id, capture_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(record) = record else {
+            return Ok(());
+        };
+
+        let mut record: CaptureRecord = serde_json::from_str(&record)?;
+        record.outcome = CaptureOutcome::UserVetoed;
+        record.files.clear();
+
+        transaction.execute(
+            "UPDATE captures SET outcome = ?3, record = ?4 WHERE capsule_id = ?1 AND capture_id = ?2",
+            params![capsule_id, capture_id, outcome_name(&record.outcome), serde_json::to_string(&record)?],
+        )?;
+        transaction.execute(
+            "DELETE FROM media WHERE capsule_id = ?1 AND capture_id = ?2",
+            params![capsule_id, capture_id],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn add_daily_total(&self, date: NaiveDate, seconds: u64) -> crate::Result<()> {
+        self.connection.execute(
+            "INSERT INTO daily_totals (date, tracked_seconds) VALUES (?1, ?2)
+             ON CONFLICT(date) DO UPDATE SET tracked_seconds = tracked_seconds + excluded.tracked_seconds",
+            params![date.to_string(), seconds],
+        )?;
+
+        Ok(())
+    }
+
+    /// Tracked seconds per day between `from` and `to` inclusive, days without tracking are left out
+    pub fn daily_totals(&self, from: NaiveDate, to: NaiveDate) -> crate::Result<Vec<(NaiveDate, u64)>> {
+        let mut statement = self.connection.prepare(
+            "SELECT date, tracked_seconds FROM daily_totals WHERE date >= ?1 AND date <= ?2 ORDER BY date",
+        )?;
+        let totals = statement
+            .query_map(params![from.to_string(), to.to_string()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })?
+            .filter_map(|row| match row {
+                Ok((date, seconds)) => date.parse().ok().map(|date| Ok((date, seconds))),
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(totals)
+    }
+
+    /// Import every capsule folder under `capsule_dir` not yet in the database
+    pub fn import_capsule_dirs(&mut self, capsule_dir: &Path) -> crate::Result<ImportReport> {
+        let mut report = ImportReport::default();
+        if !capsule_dir.exists() {
+            return Ok(report);
+        }
+
+        for entry in std::fs::read_dir(capsule_dir)? {
+            let storage_path = entry?.path();
+            let metadata_path = storage_path.join("metadata.json");
+            if !metadata_path.exists() {
+                continue;
+            }
+
+            let imported = storage::load_capsule_json::<StorageTimeCapsule>(&metadata_path).and_then(|capsule| {
+                if self.has_capsule(&capsule.id)? {
+                    return Ok(false);
+                }
+                self.save_capsule(&capsule, &storage_path)?;
+                Ok(true)
+            });
+
+            match imported {
+                Ok(true) => report.imported += 1,
+                Ok(false) => report.skipped += 1,
+                Err(err) => {
+                    error!("Error importing capsule {:?}: {:?}", metadata_path, err);
+                    report.failed += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+fn insert_media(
+    transaction: &rusqlite::Transaction,
+    capsule_id: &str,
+    capture_id: Option<&str>,
+    kind: MediaKind,
+    path: &str,
+) -> crate::Result<()> {
+    transaction.execute(
+        "INSERT INTO media (capsule_id, capture_id, kind, path) VALUES (?1, ?2, ?3, ?4)",
+        params![capsule_id, capture_id, kind.as_str(), path],
+    )?;
+
+    Ok(())
+}
+
+/// Screen recording segments written by the recorder into the capsule folder
+fn screen_recordings(storage_path: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(storage_path) else {
+        return vec![];
+    };
+
+    let mut recordings: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("screen_recording_") && name.ends_with(".mp4"))
+        .collect();
+    recordings.sort();
+
+    recordings
+}
+
+/// Bucket rfc2822 input timestamps by minute
+fn aggregate_input(mouse_clicks: &[String], keystrokes: &[String]) -> Vec<InputAggregate> {
+    let mut aggregates: BTreeMap<i64, InputAggregate> = BTreeMap::new();
+    for (times, is_click) in [(mouse_clicks, true), (keystrokes, false)] {
+        for time in times {
+            let Some(minute_ts) = timestamp(time).map(|ts| ts / 60 * 60) else {
+                continue;
+            };
+            let aggregate = aggregates.entry(minute_ts).or_insert(InputAggregate {
+                minute_ts,
+                mouse_clicks: 0,
+                keystrokes: 0,
+            });
+            if is_click {
+                aggregate.mouse_clicks += 1;
+            } else {
+                aggregate.keystrokes += 1;
+            }
+        }
+    }
+
+    aggregates.into_values().collect()
+}
+
+fn timestamp(rfc2822: &str) -> Option<i64> {
+    DateTime::parse_from_rfc2822(rfc2822).ok().map(|time| time.timestamp())
+}
+
+fn outcome_name(outcome: &CaptureOutcome) -> &'static str {
+    match outcome {
+        CaptureOutcome::Ok => "Ok",
+        CaptureOutcome::SkippedByPolicy(_) => "SkippedByPolicy",
+        CaptureOutcome::PermissionDenied => "PermissionDenied",
+        CaptureOutcome::DeviceBusy => "DeviceBusy",
+        CaptureOutcome::Error(_) => "Error",
+        CaptureOutcome::Cancelled => "Cancelled",
+        CaptureOutcome::UserVetoed => "UserVetoed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STARTED_AT: &str = "Mon, 1 Jan 2024 10:00:00 +0000";
+
+    fn capsule(id: &str) -> StorageTimeCapsule {
+        let mut screenshot = CaptureRecord::new(CaptureKind::Screenshot, STARTED_AT.into());
+        screenshot.outcome = CaptureOutcome::Ok;
+        screenshot.files = vec!["screenshot_1.png".into()];
+
+        StorageTimeCapsule {
+            id: id.into(),
+            session_id: "session".into(),
+            mouse_clicks: vec!["Mon, 1 Jan 2024 10:00:10 +0000".into(), "Mon, 1 Jan 2024 10:01:10 +0000".into()],
+            keystrokes: vec!["Mon, 1 Jan 2024 10:00:20 +0000".into()],
+            windows: vec![WindowEntry {
+                name: "Code".into(),
+                title: "main.rs".into(),
+                time: "2024-01-01T10:00:30+00:00".into(),
+            }],
+            started_at: STARTED_AT.into(),
+            ended_at: Some("Mon, 1 Jan 2024 10:05:00 +0000".into()),
+            captures: vec![screenshot, CaptureRecord::new(CaptureKind::Webcam, STARTED_AT.into())],
+        }
+    }
+
+    #[test]
+    fn migrations_run_once_and_refuse_newer_schemas() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DATABASE_FILE);
+
+        drop(Database::open(&path).unwrap());
+        let database = Database::open(&path).unwrap();
+        let version: usize = database
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        database.connection.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        drop(database);
+        assert!(Database::open(&path).is_err());
+    }
+
+    #[test]
+    fn capsules_round_trip() {
+        let mut database = Database::open_in_memory().unwrap();
+        let storage_path = Path::new("/data/capsules/c1");
+
+        database.save_capsule(&capsule("c1"), storage_path).unwrap();
+        // saving again replaces the capsule instead of adding to it
+        database.save_capsule(&capsule("c1"), storage_path).unwrap();
+
+        assert!(database.has_capsule("c1").unwrap());
+        let capsules = database.capsules_between(0, i64::MAX).unwrap();
+        assert_eq!(capsules.len(), 1);
+        assert_eq!(capsules[0].started_at, STARTED_AT);
+        assert_eq!(capsules[0].storage_path, storage_path);
+        assert_eq!((capsules[0].mouse_clicks, capsules[0].keystrokes), (2, 1));
+        assert_eq!(database.sessions().unwrap().len(), 1);
+
+        let windows = database.windows("c1").unwrap();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].title, "main.rs");
+
+        let minute = timestamp(STARTED_AT).unwrap();
+        assert_eq!(
+            database.input_aggregates("c1").unwrap(),
+            [
+                InputAggregate { minute_ts: minute, mouse_clicks: 1, keystrokes: 1 },
+                InputAggregate { minute_ts: minute + 60, mouse_clicks: 1, keystrokes: 0 },
+            ]
+        );
+
+        let captures = database.captures("c1").unwrap();
+        let outcomes: Vec<_> = captures.iter().map(|record| record.outcome.clone()).collect();
+        assert_eq!(outcomes, [CaptureOutcome::Ok, CaptureOutcome::Cancelled]);
+
+        let media = database.media("c1").unwrap();
+        assert_eq!(media.len(), 1);
+        assert_eq!(media[0].kind, MediaKind::Screenshot);
+        assert_eq!(media[0].path, storage_path.join("screenshot_1.png"));
+    }
+
+    #[test]
+    fn rejects_capsules_without_a_start_time() {
+        let mut database = Database::open_in_memory().unwrap();
+        let mut capsule = capsule("c1");
+        capsule.started_at = "yesterday".into();
+
+        assert!(database.save_capsule(&capsule, Path::new("/data/capsules/c1")).is_err());
+        assert!(!database.has_capsule("c1").unwrap());
+    }
+
+    #[test]
+    fn vetoes_and_deletes_cascade() {
+        let mut database = Database::open_in_memory().unwrap();
+        let capsule = capsule("c1");
+        database.save_capsule(&capsule, Path::new("/data/capsules/c1")).unwrap();
+
+        database.veto_capture("c1", &capsule.captures[0].id).unwrap();
+        let vetoed = &database.captures("c1").unwrap()[0];
+        assert_eq!(vetoed.outcome, CaptureOutcome::UserVetoed);
+        assert!(vetoed.files.is_empty());
+        assert!(database.media("c1").unwrap().is_empty());
+
+        database.delete_capsule("c1").unwrap();
+        assert!(!database.has_capsule("c1").unwrap());
+        assert!(database.windows("c1").unwrap().is_empty());
+        assert!(database.captures("c1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn imports_capsule_folders_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let capsule_dir = dir.path().join("capsules");
+        let valid = capsule_dir.join("c1");
+        std::fs::create_dir_all(&valid).unwrap();
+        storage::save_capsule_json(&capsule("c1"), &valid.join("metadata.json")).unwrap();
+        std::fs::write(valid.join("screen_recording_0.mp4"), b"").unwrap();
+        std::fs::create_dir_all(capsule_dir.join("broken")).unwrap();
+        std::fs::write(capsule_dir.join("broken").join("metadata.json"), b"{").unwrap();
+        std::fs::create_dir_all(capsule_dir.join("recording")).unwrap();
+        let mut database = Database::open_in_memory().unwrap();
+
+        let first = database.import_capsule_dirs(&capsule_dir).unwrap();
+        let second = database.import_capsule_dirs(&capsule_dir).unwrap();
+
+        assert_eq!((first.imported, first.skipped, first.failed), (1, 0, 1));
+        assert_eq!((second.imported, second.skipped, second.failed), (0, 1, 1));
+        let kinds: Vec<_> = database.media("c1").unwrap().into_iter().map(|media| media.kind).collect();
+        assert_eq!(kinds, [MediaKind::Screenshot, MediaKind::ScreenRecording]);
+    }
+
+    #[test]
+    fn relocates_capsules_under_a_new_folder() {
+        let mut database = Database::open_in_memory().unwrap();
+        database.save_capsule(&capsule("c1"), Path::new("/old/capsules/c1")).unwrap();
+        database.save_capsule(&capsule("c2"), Path::new("/elsewhere/c2")).unwrap();
+
+        let relocated = database.relocate_capsules(Path::new("/old"), Path::new("/new")).unwrap();
+
+        assert_eq!(relocated, 1);
+        let paths: Vec<_> = database.capsules_between(0, i64::MAX).unwrap().into_iter().map(|capsule| capsule.storage_path).collect();
+        assert!(paths.contains(&PathBuf::from("/new/capsules/c1")));
+        assert!(paths.contains(&PathBuf::from("/elsewhere/c2")));
+    }
+
+    #[test]
+    fn daily_totals_add_up() {
+        let database = Database::open_in_memory().unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+
+        database.add_daily_total(day, 60).unwrap();
+        database.add_daily_total(day, 30).unwrap();
+        database.add_daily_total(day.succ_opt().unwrap(), 10).unwrap();
+
+        assert_eq!(database.daily_totals(day, day).unwrap(), [(day, 90)]);
+    }
+}
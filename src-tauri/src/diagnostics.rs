@@ -0,0 +1,340 @@
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::error;
+use xcap::Monitor;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    ffmpeg_resolver::FfmpegDiagnostics, get_current_datetime, logging::LogEntry, session::StorageTimeCapsule, storage,
+    CameraDevice, Configuration, PermisssionsStatus,
+};
+
+/// Capsule manifests exported when the caller doesn't ask for a number
+pub const DEFAULT_CAPSULE_COUNT: usize = 20;
+/// Newest log entries exported
+pub const LOG_ENTRY_LIMIT: usize = 5000;
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppInfo {
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    pub exported_at: String,
+}
+
+impl AppInfo {
+    pub fn new(version: String) -> Self {
+        Self {
+            version,
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            exported_at: get_current_datetime().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+impl MonitorInfo {
+    pub fn list() -> Result<Vec<Self>, String> {
+        let monitors = Monitor::all().map_err(|err| format!("Error listing monitors: {:?}", err))?;
+
+        Ok(monitors
+            .iter()
+            .map(|monitor| Self {
+                name: monitor.name().to_string(),
+                width: monitor.width(),
+                height: monitor.height(),
+                scale_factor: monitor.scale_factor(),
+                is_primary: monitor.is_primary(),
+            })
+            .collect())
+    }
+}
+
+/// Everything written to a diagnostics export besides the capsule manifests
+///
+/// Device lookups that failed keep their error so the export still shows why
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub app: AppInfo,
+    pub configuration: Configuration,
+    pub permissions: PermisssionsStatus,
+    pub cameras: Result<Vec<CameraDevice>, String>,
+    pub monitors: Result<Vec<MonitorInfo>, String>,
+    pub ffmpeg: FfmpegDiagnostics,
+    /// Newest first, as `logging::recent_entries` returns them
+    pub logs: Vec<LogEntry>,
+    /// Replaced in every exported file, auth tokens and key file paths for example
+    pub secrets: Vec<String>,
+}
+
+/// Capsule manifest with window titles and input times replaced
+///
+/// Apps, capture outcomes and the number of clicks and keystrokes are kept
+pub fn redacted_manifest(mut capsule: StorageTimeCapsule) -> StorageTimeCapsule {
+    for time in capsule.mouse_clicks.iter_mut().chain(capsule.keystrokes.iter_mut()) {
+        *time = REDACTED.to_string();
+    }
+    for window in &mut capsule.windows {
+        window.title = REDACTED.to_string();
+    }
+
+    capsule
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiagnosticsExport {
+    pub path: PathBuf,
+    /// Names of the files inside the zip
+    pub files: Vec<String>,
+}
+
+impl Diagnostics {
+    /// Zip the diagnostics with the manifests of the newest `capsule_count` capsules
+    ///
+    /// Manifests go through `redacted_manifest`, screenshots and webcam images
+    /// never make it into the zip
+    pub fn export(&self, path: &Path, capsule_dir: &Path, capsule_count: usize) -> crate::Result<DiagnosticsExport> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut files = vec![];
+        let mut add = |name: String, content: String| -> crate::Result<()> {
+            let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(self.redact(&content).as_bytes())?;
+            files.push(name);
+            Ok(())
+        };
+
+        add("app.json".into(), to_json(&self.app)?)?;
+        add("configuration.json".into(), to_json(&self.configuration)?)?;
+        add("permissions.json".into(), to_json(&self.permissions)?)?;
+        add("cameras.json".into(), to_json(&or_error(&self.cameras))?)?;
+        add("monitors.json".into(), to_json(&or_error(&self.monitors))?)?;
+        add("ffmpeg.json".into(), to_json(&self.ffmpeg)?)?;
+
+        // oldest first like the log files themselves
+        let logs = self
+            .logs
+            .iter()
+            .rev()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
+        add("logs.jsonl".into(), logs)?;
+
+        let manifests: Vec<StorageTimeCapsule> = recent_manifests(capsule_dir, capsule_count)?
+            .iter()
+            .filter_map(|manifest| match storage::load_capsule_json::<StorageTimeCapsule>(manifest) {
+                Ok(capsule) => Some(redacted_manifest(capsule)),
+                Err(err) => {
+                    error!("Error reading capsule manifest {:?}: {}", manifest, err);
+                    None
+                }
+            })
+            .collect();
+        add("capsules.json".into(), to_json(&manifests)?)?;
+
+        let data = zip.finish()?.into_inner();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        storage::write_atomic(path, &data)?;
+
+        Ok(DiagnosticsExport {
+            path: path.to_path_buf(),
+            files,
+        })
+    }
+
+    /// Hide secrets and the user's home directory, which usually holds their name
+    fn redact(&self, content: &str) -> String {
+        let mut content = content.to_string();
+        for secret in self.secrets.iter().filter(|secret| !secret.is_empty()) {
+            for secret in json_escaped(secret) {
+                content = content.replace(secret.as_str(), REDACTED);
+            }
+        }
+
+        if let Some(home) = directories::BaseDirs::new().map(|dirs| dirs.home_dir().to_string_lossy().to_string()) {
+            for home in json_escaped(&home).iter().filter(|home| !home.is_empty()) {
+                content = content.replace(home.as_str(), "~");
+            }
+        }
+
+        content
+    }
+}
+
+/// Default location of an export, inside the data directory
+pub fn default_export_path() -> PathBuf {
+    let name = format!("worksmart-diagnostics-{}.zip", get_current_datetime().format("%Y%m%d-%H%M%S"));
+    storage::data_path().join("diagnostics").join(name)
+}
+
+/// `metadata.json` of the newest capsules, newest first
+fn recent_manifests(capsule_dir: &Path, count: usize) -> crate::Result<Vec<PathBuf>> {
+    if !capsule_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut manifests: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(capsule_dir)?
+        .filter_map(|entry| {
+            let manifest = entry.ok()?.path().join("metadata.json");
+            let modified = manifest.metadata().ok()?.modified().ok()?;
+            Some((modified, manifest))
+        })
+        .collect();
+    manifests.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    Ok(manifests.into_iter().take(count).map(|(_, manifest)| manifest).collect())
+}
+
+/// `value` as written in json, which escapes the backslashes of windows paths, then as is
+fn json_escaped(value: &str) -> [String; 2] {
+    [value.replace('\\', "\\\\"), value.to_string()]
+}
+
+fn or_error<T: Serialize>(result: &Result<T, String>) -> Value {
+    match result {
+        Ok(value) => json!(value),
+        Err(err) => json!({ "error": err }),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> crate::Result<String> {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use serde_json::Map;
+
+    use super::*;
+    use crate::{
+        capture::{CaptureKind, CaptureOutcome, CaptureRecord},
+        session::WindowEntry,
+    };
+
+    const TOKEN: &str = "secret-auth-token";
+    const KEY_FILE: &str = "/data/worksmart/capsule.key";
+
+    fn diagnostics(configuration: Configuration, logs: Vec<LogEntry>) -> Diagnostics {
+        Diagnostics {
+            app: AppInfo::new("1.0.0".into()),
+            configuration,
+            permissions: PermisssionsStatus {
+                camera: true,
+                accessibility: true,
+                screen_capture: true,
+            },
+            cameras: Err("no cameras".into()),
+            monitors: Ok(vec![]),
+            ffmpeg: Default::default(),
+            logs,
+            secrets: vec![TOKEN.into(), KEY_FILE.into()],
+        }
+    }
+
+    fn read_all(path: &Path) -> Vec<(String, String)> {
+        let mut zip = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+        (0..zip.len())
+            .map(|index| {
+                let mut file = zip.by_index(index).unwrap();
+                let mut content = String::new();
+                file.read_to_string(&mut content).unwrap();
+                (file.name().to_string(), content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exports_redacted_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let capsule_dir = dir.path().join("capsules");
+        let mut screenshot = CaptureRecord::new(CaptureKind::Screenshot, "now".into());
+        screenshot.outcome = CaptureOutcome::Ok;
+        let capsule = StorageTimeCapsule {
+            id: "c1".into(),
+            session_id: "session".into(),
+            mouse_clicks: vec!["Mon, 1 Jan 2024 10:00:10 +0000".into()],
+            keystrokes: vec!["Mon, 1 Jan 2024 10:00:20 +0000".into()],
+            windows: vec![WindowEntry {
+                name: "Mail".into(),
+                title: "Private conversation".into(),
+                time: "Mon, 1 Jan 2024 10:00:00 +0000".into(),
+            }],
+            started_at: "Mon, 1 Jan 2024 10:00:00 +0000".into(),
+            ended_at: None,
+            captures: vec![screenshot],
+        };
+        fs::create_dir_all(capsule_dir.join("c1")).unwrap();
+        storage::save_capsule_json(&capsule, &capsule_dir.join("c1").join("metadata.json")).unwrap();
+
+        let path = dir.path().join("diagnostics.zip");
+        let export = diagnostics(Configuration::initial(), vec![])
+            .export(&path, &capsule_dir, DEFAULT_CAPSULE_COUNT)
+            .unwrap();
+
+        assert!(!export.files.iter().any(|file| file.ends_with("metadata.json")));
+        let (_, content) = read_all(&path).into_iter().find(|(name, _)| name == "capsules.json").unwrap();
+        assert!(!content.contains("Private conversation"));
+        assert!(!content.contains("10:00:10"));
+        assert!(!content.contains("10:00:20"));
+
+        let manifests: Vec<StorageTimeCapsule> = serde_json::from_str(&content).unwrap();
+        assert_eq!(manifests.len(), 1);
+        assert_eq!(manifests[0].windows[0].name, "Mail");
+        assert_eq!((manifests[0].mouse_clicks.len(), manifests[0].keystrokes.len()), (1, 1));
+        assert_eq!(manifests[0].captures[0].outcome, CaptureOutcome::Ok);
+    }
+
+    #[test]
+    fn secrets_are_redacted_from_configuration_and_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut configuration = Configuration::initial();
+        configuration.ffmpeg_path = Some(PathBuf::from(format!("/opt/{TOKEN}/ffmpeg")));
+        let log = |message: String| LogEntry {
+            timestamp: "2024-01-01T10:00:00Z".into(),
+            level: "INFO".into(),
+            target: "worksmart".into(),
+            fields: Map::from_iter([("message".to_string(), Value::String(message))]),
+            span: None,
+        };
+        let logs = vec![
+            log(format!("Signed in with {TOKEN}")),
+            log(format!("Created capsule key {KEY_FILE:?}")),
+        ];
+
+        let path = dir.path().join("diagnostics.zip");
+        diagnostics(configuration, logs)
+            .export(&path, &dir.path().join("capsules"), DEFAULT_CAPSULE_COUNT)
+            .unwrap();
+
+        let files = read_all(&path);
+        for (name, content) in &files {
+            assert!(!content.contains(TOKEN), "{name} leaks the token");
+            assert!(!content.contains(KEY_FILE), "{name} leaks the key file");
+        }
+        let redacted = |file: &str| files.iter().any(|(name, content)| name == file && content.contains(REDACTED));
+        assert!(redacted("configuration.json"));
+        assert!(redacted("logs.jsonl"));
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::stream::{DecryptorBE32, EncryptorBE32},
+    KeyInit, XChaCha20Poly1305,
+};
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{storage, CaptureError, EncryptionSettings, KeySource};
+
+/// Random key used with `KeySource::KeyFile`, inside the data directory
+pub const KEY_FILE: &str = "capsule.key";
+/// Salt and check value of `KeySource::Passphrase`, the passphrase itself is never saved
+pub const PASSPHRASE_FILE: &str = "capsule_passphrase.json";
+
+/// Starts every encrypted file, anything else is read as plaintext
+const MAGIC: &[u8; 8] = b"WSCRYPT1";
+/// XChaCha20's 24 byte nonce minus the 5 bytes of the STREAM counter
+const NONCE_LEN: usize = 19;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+/// Plaintext bytes per sealed chunk
+const CHUNK_LEN: usize = 64 * 1024;
+/// Encrypted with the passphrase key to tell a wrong passphrase from damaged files
+const CHECK_VALUE: &[u8] = b"worksmart capsule key";
+
+static STATE: RwLock<KeyState> = RwLock::new(KeyState {
+    enabled: false,
+    source: KeySource::KeyFile,
+    key: None,
+});
+
+struct KeyState {
+    enabled: bool,
+    source: KeySource,
+    /// Kept while encryption is off so files written before can still be read
+    key: Option<CapsuleKey>,
+}
+
+#[derive(Clone)]
+pub struct CapsuleKey([u8; KEY_LEN]);
+
+impl fmt::Debug for CapsuleKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CapsuleKey([redacted])")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub key_source: KeySource,
+    /// False while a passphrase is needed, captures are skipped meanwhile
+    pub unlocked: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PassphraseFile {
+    salt: Vec<u8>,
+    /// `CHECK_VALUE` sealed with the derived key
+    check: Vec<u8>,
+}
+
+/// Apply the settings, loading or creating the key file when it is the key source
+///
+/// A passphrase key stays unlocked as long as the source doesn't change
+pub fn configure(settings: &EncryptionSettings) -> crate::Result<()> {
+    let key = match settings.key_source {
+        KeySource::KeyFile if settings.enabled || key_file_path().exists() => Some(load_or_create_key_file()?),
+        KeySource::KeyFile => None,
+        KeySource::Passphrase => {
+            let state = STATE.read().unwrap();
+            state.key.clone().filter(|_| state.source == KeySource::Passphrase)
+        }
+    };
+
+    let mut state = STATE.write().unwrap();
+    state.enabled = settings.enabled;
+    state.source = settings.key_source;
+    state.key = key;
+
+    Ok(())
+}
+
+/// Derive the key from `passphrase`, the first passphrase entered sets it
+///
+/// Only used while the configured key source is `KeySource::Passphrase`
+pub fn unlock(passphrase: &str) -> crate::Result<()> {
+    if STATE.read().unwrap().source != KeySource::Passphrase {
+        return Err("Capsules use the key file, there is no passphrase to enter".into());
+    }
+    if passphrase.is_empty() {
+        return Err("The passphrase must not be empty".into());
+    }
+
+    let path = passphrase_file_path();
+    let key = if path.exists() {
+        let file: PassphraseFile = storage::load_json_from_path(path)?;
+        let key = derive_key(passphrase, &file.salt)?;
+        if open_with(&key, &file.check).ok().as_deref() != Some(CHECK_VALUE) {
+            return Err("Wrong passphrase".into());
+        }
+        key
+    } else {
+        let salt = rand::random::<[u8; 16]>().to_vec();
+        let key = derive_key(passphrase, &salt)?;
+        let file = PassphraseFile {
+            check: seal_with(&key, CHECK_VALUE)?,
+            salt,
+        };
+        storage::write_atomic(&path, &serde_json::to_vec_pretty(&file)?)?;
+        info!("Capsule passphrase set");
+        key
+    };
+
+    let mut state = STATE.write().unwrap();
+    state.source = KeySource::Passphrase;
+    state.key = Some(key);
+
+    Ok(())
+}
+
+pub fn status() -> EncryptionStatus {
+    let state = STATE.read().unwrap();
+    EncryptionStatus {
+        enabled: state.enabled,
+        key_source: state.source,
+        unlocked: state.key.is_some(),
+    }
+}
+
+/// Key new capsule files are sealed with, None writes plaintext
+///
+/// Errors while encryption is on but the passphrase wasn't entered yet, so
+/// nothing is written in the clear by mistake
+pub fn writer_key() -> Result<Option<CapsuleKey>, CaptureError> {
+    let state = STATE.read().unwrap();
+    match (&state.key, state.enabled) {
+        (_, false) => Ok(None),
+        (Some(key), true) => Ok(Some(key.clone())),
+        (None, true) => Err(CaptureError::SkippedByPolicy("Capsule encryption is locked".into())),
+    }
+}
+
+/// Seal `data` when encryption is on, otherwise return it unchanged
+pub fn seal(data: &[u8]) -> crate::Result<Vec<u8>> {
+    match writer_key()? {
+        Some(key) => seal_with(&key, data),
+        None => Ok(data.to_vec()),
+    }
+}
+
+/// Decrypt `data` written by `seal`, plaintext passes through
+pub fn open(data: &[u8]) -> crate::Result<Vec<u8>> {
+    if !is_encrypted(data) {
+        return Ok(data.to_vec());
+    }
+
+    let key = STATE.read().unwrap().key.clone().ok_or("Capsule encryption is locked")?;
+    open_with(&key, data)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Whether any file in the capsule folders is encrypted, switching the key source would lock them out
+pub fn has_encrypted_capsules(capsule_dir: &Path) -> io::Result<bool> {
+    if !capsule_dir.exists() {
+        return Ok(false);
+    }
+
+    for capsule in fs::read_dir(capsule_dir)? {
+        let capsule = capsule?.path();
+        if !capsule.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&capsule)? {
+            let file = file?.path();
+            let mut header = [0; MAGIC.len()];
+            let read = File::open(&file).and_then(|mut file| io::Read::read_exact(&mut file, &mut header));
+            if read.is_ok() && is_encrypted(&header) {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Read a capsule file, decrypting it if needed
+pub fn read_file(path: &Path) -> crate::Result<Vec<u8>> {
+    let data = fs::read(path).map_err(|err| format!("Error reading {:?}: {}", path, err))?;
+    open(&data).map_err(|err| format!("Error decrypting {:?}: {}", path, err).into())
+}
+
+/// Write a capsule file, sealed when encryption is on
+pub fn write_file(path: &Path, data: &[u8]) -> crate::Result<()> {
+    storage::write_atomic(path, &seal(data)?)
+}
+
+/// Encode `image` in the format of the path's extension and write it as a capsule file
+pub fn save_image(image: &DynamicImage, path: &Path) -> crate::Result<()> {
+    let format = ImageFormat::from_path(path)?;
+    let mut data = io::Cursor::new(Vec::new());
+    image.write_to(&mut data, format)?;
+    write_file(path, &data.into_inner())
+}
+
+pub fn load_image(path: &Path) -> crate::Result<DynamicImage> {
+    Ok(image::load_from_memory(&read_file(path)?)?)
+}
+
+/// Streams a capsule file to disk, sealing it chunk by chunk when a key is given
+///
+/// Data goes to a `.tmp` sibling that `finish` renames into place, so a file
+/// cut short never shows up under its final name
+pub struct CapsuleWriter {
+    file: File,
+    path: PathBuf,
+    temp_path: PathBuf,
+    encryptor: Option<(EncryptorBE32<XChaCha20Poly1305>, Vec<u8>)>,
+}
+
+impl CapsuleWriter {
+    pub fn create(path: &Path, key: Option<&CapsuleKey>) -> io::Result<Self> {
+        let temp_path = storage::sibling_path(path, "tmp");
+        let mut file = File::create(&temp_path)?;
+        let encryptor = match key {
+            Some(key) => {
+                let nonce = rand::random::<[u8; NONCE_LEN]>();
+                file.write_all(MAGIC)?;
+                file.write_all(&nonce)?;
+                Some((EncryptorBE32::from_aead(cipher(key), nonce.as_ref().into()), Vec::with_capacity(CHUNK_LEN)))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            temp_path,
+            encryptor,
+        })
+    }
+
+    pub fn finish(self) -> io::Result<PathBuf> {
+        let Self {
+            mut file,
+            path,
+            temp_path,
+            encryptor,
+        } = self;
+        if let Some((encryptor, buffer)) = encryptor {
+            let chunk = encryptor.encrypt_last(buffer.as_slice()).map_err(aead_error)?;
+            file.write_all(&chunk)?;
+        }
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, &path)?;
+        #[cfg(unix)]
+        if let Some(dir) = path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+
+        Ok(path)
+    }
+}
+
+impl Write for CapsuleWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let Some((encryptor, buffer)) = self.encryptor.as_mut() else {
+            return self.file.write(data);
+        };
+
+        // a full chunk is only sealed once more data follows, the last one is sealed by `finish`
+        buffer.extend_from_slice(data);
+        while buffer.len() > CHUNK_LEN {
+            let chunk = encryptor.encrypt_next(&buffer[..CHUNK_LEN]).map_err(aead_error)?;
+            self.file.write_all(&chunk)?;
+            buffer.drain(..CHUNK_LEN);
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn seal_with(key: &CapsuleKey, data: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut writer = Vec::with_capacity(MAGIC.len() + NONCE_LEN + data.len() + TAG_LEN);
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    writer.extend_from_slice(MAGIC);
+    writer.extend_from_slice(&nonce);
+
+    let mut encryptor = EncryptorBE32::from_aead(cipher(key), nonce.as_ref().into());
+    let mut chunks: Vec<&[u8]> = data.chunks(CHUNK_LEN).collect();
+    // empty data still gets a last chunk, its tag marks the end of the file
+    let last = chunks.pop().unwrap_or_default();
+    for chunk in chunks {
+        writer.extend(encryptor.encrypt_next(chunk).map_err(aead_error)?);
+    }
+    writer.extend(encryptor.encrypt_last(last).map_err(aead_error)?);
+
+    Ok(writer)
+}
+
+fn open_with(key: &CapsuleKey, data: &[u8]) -> crate::Result<Vec<u8>> {
+    let header_len = MAGIC.len() + NONCE_LEN;
+    if data.len() < header_len + TAG_LEN || !is_encrypted(data) {
+        return Err("Encrypted file is truncated".into());
+    }
+
+    let nonce = &data[MAGIC.len()..header_len];
+    let mut decryptor = DecryptorBE32::from_aead(cipher(key), nonce.into());
+    let mut chunks = data[header_len..].chunks(CHUNK_LEN + TAG_LEN).peekable();
+    let mut plaintext = Vec::with_capacity(data.len());
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            plaintext.extend(decryptor.decrypt_last(chunk).map_err(|_| "Encrypted file is damaged or the key is wrong")?);
+            break;
+        }
+        plaintext.extend(decryptor.decrypt_next(chunk).map_err(|_| "Encrypted file is damaged or the key is wrong")?);
+    }
+
+    Ok(plaintext)
+}
+
+fn cipher(key: &CapsuleKey) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(key.0.as_ref().into())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> crate::Result<CapsuleKey> {
+    let mut key = [0; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| format!("Error deriving capsule key: {}", err))?;
+
+    Ok(CapsuleKey(key))
+}
+
+fn key_file_path() -> PathBuf {
+    storage::data_path().join(KEY_FILE)
+}
+
+fn passphrase_file_path() -> PathBuf {
+    storage::data_path().join(PASSPHRASE_FILE)
+}
+
+/// Files the capsule key can be recovered from, kept out of diagnostics
+pub fn key_material_paths() -> [PathBuf; 2] {
+    [key_file_path(), passphrase_file_path()]
+}
+
+fn load_or_create_key_file() -> crate::Result<CapsuleKey> {
+    let path = key_file_path();
+    if path.exists() {
+        let data = fs::read(&path).map_err(|err| format!("Error reading {:?}: {}", path, err))?;
+        let key: [u8; KEY_LEN] = data
+            .try_into()
+            .map_err(|_| format!("{:?} is not a capsule key", path))?;
+        return Ok(CapsuleKey(key));
+    }
+
+    let key = rand::random::<[u8; KEY_LEN]>();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // only the user may read the key
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&path)
+        .map_err(|err| format!("Error creating {:?}: {}", path, err))?;
+    file.write_all(&key)?;
+    file.sync_all()?;
+    info!("Created capsule key {:?}", path);
+
+    Ok(CapsuleKey(key))
+}
+
+fn aead_error(_: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::other("Error encrypting capsule data")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: CapsuleKey = CapsuleKey([7; KEY_LEN]);
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|index| (index % 251) as u8).collect()
+    }
+
+    #[test]
+    fn seals_and_opens_every_chunk_size() {
+        for len in [0, 1, CHUNK_LEN, 2 * CHUNK_LEN + 5] {
+            let sealed = seal_with(&KEY, &data(len)).unwrap();
+
+            assert!(is_encrypted(&sealed));
+            assert_eq!(open_with(&KEY, &sealed).unwrap(), data(len), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn wrong_keys_and_damage_are_caught() {
+        let sealed = seal_with(&KEY, &data(CHUNK_LEN + 10)).unwrap();
+
+        assert!(open_with(&CapsuleKey([8; KEY_LEN]), &sealed).is_err());
+        assert!(open_with(&KEY, &sealed[..sealed.len() - 1]).is_err());
+        // a file cut at a chunk boundary must not pass for a shorter one
+        assert!(open_with(&KEY, &sealed[..MAGIC.len() + NONCE_LEN + CHUNK_LEN + TAG_LEN]).is_err());
+        let mut flipped = sealed.clone();
+        flipped[MAGIC.len() + NONCE_LEN] ^= 1;
+        assert!(open_with(&KEY, &flipped).is_err());
+    }
+
+    #[test]
+    fn capsule_writer_renames_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = data(2 * CHUNK_LEN + 10);
+
+        for (name, key) in [("sealed.mp4", Some(&KEY)), ("plain.mp4", None)] {
+            let path = dir.path().join(name);
+            let mut writer = CapsuleWriter::create(&path, key).unwrap();
+            for part in data.chunks(1000) {
+                writer.write_all(part).unwrap();
+            }
+            assert!(!path.exists());
+
+            assert_eq!(writer.finish().unwrap(), path);
+            assert!(!storage::sibling_path(&path, "tmp").exists());
+            let written = fs::read(&path).unwrap();
+            match key {
+                Some(key) => assert_eq!(open_with(key, &written).unwrap(), data),
+                None => assert_eq!(written, data),
+            }
+        }
+    }
+
+    #[test]
+    fn finds_encrypted_capsules() {
+        let dir = tempfile::tempdir().unwrap();
+        let capsule_dir = dir.path().join("capsules");
+        assert!(!has_encrypted_capsules(&capsule_dir).unwrap());
+
+        fs::create_dir_all(capsule_dir.join("c1")).unwrap();
+        fs::write(capsule_dir.join("c1").join("metadata.json"), b"{}").unwrap();
+        fs::write(capsule_dir.join("notes.txt"), MAGIC).unwrap();
+        assert!(!has_encrypted_capsules(&capsule_dir).unwrap());
+
+        fs::create_dir_all(capsule_dir.join("c2")).unwrap();
+        fs::write(capsule_dir.join("c2").join("metadata.json"), seal_with(&KEY, b"{}").unwrap()).unwrap();
+        assert!(has_encrypted_capsules(&capsule_dir).unwrap());
+    }
+}
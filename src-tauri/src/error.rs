@@ -1,3 +1,203 @@
+use std::fmt;
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use serde_json::{json, Value};
+
+use crate::{capture::CaptureError, validation::ConfigError, PermissionType};
+
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error returned by every command
+///
+/// Reaches the frontend as `{ code, message, details }`. `code` is stable and
+/// meant to be matched on, `message` is for people
+#[derive(Debug, Clone)]
+pub enum WorksmartError {
+    /// Reading or writing files or the database failed
+    Storage(String),
+    Capture(CaptureError),
+    /// Camera devices couldn't be listed or opened
+    Camera(String),
+    Permission(PermissionType),
+    Config(ConfigError),
+    /// The action needs a running session
+    NoSession,
+    Session(String),
+    /// The capture can't be discarded anymore or the daily limit is reached
+    VetoRejected(String),
+    InvalidInput(String),
+    Internal(String),
+}
+
+impl WorksmartError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WorksmartError::Storage(_) => "storage_failed",
+            WorksmartError::Capture(CaptureError::SkippedByPolicy(_)) => "capture_skipped",
+            WorksmartError::Capture(CaptureError::PermissionDenied) => "permission_denied",
+            WorksmartError::Capture(CaptureError::DeviceBusy) => "camera_busy",
+            WorksmartError::Capture(CaptureError::NoCamera) => "camera_not_found",
+            WorksmartError::Capture(CaptureError::Failed(_)) => "capture_failed",
+            WorksmartError::Camera(_) => "camera_failed",
+            WorksmartError::Permission(_) => "permission_denied",
+            WorksmartError::Config(ConfigError::Invalid(_)) => "config_invalid",
+            WorksmartError::Config(ConfigError::SaveFailed(_)) => "config_save_failed",
+            WorksmartError::NoSession => "session_not_running",
+            WorksmartError::Session(_) => "session_failed",
+            WorksmartError::VetoRejected(_) => "veto_rejected",
+            WorksmartError::InvalidInput(_) => "invalid_input",
+            WorksmartError::Internal(_) => "internal",
+        }
+    }
+
+    /// Data the frontend needs beyond the message, null when there is none
+    pub fn details(&self) -> Value {
+        match self {
+            WorksmartError::Capture(CaptureError::SkippedByPolicy(reason)) => json!({ "reason": reason }),
+            WorksmartError::Permission(permission) => json!({ "permission": permission }),
+            WorksmartError::Config(ConfigError::Invalid(fields)) => json!({ "fields": fields }),
+            _ => Value::Null,
+        }
+    }
+
+    pub fn storage(err: impl ToString) -> Self {
+        WorksmartError::Storage(err.to_string())
+    }
+}
+
+impl fmt::Display for WorksmartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorksmartError::Storage(err) => write!(f, "Storage error: {err}"),
+            WorksmartError::Capture(err) => write!(f, "{err}"),
+            WorksmartError::Camera(err) => write!(f, "Camera error: {err}"),
+            WorksmartError::Permission(permission) => write!(f, "{:?} permission is required", permission),
+            WorksmartError::Config(err) => write!(f, "{err}"),
+            WorksmartError::NoSession => write!(f, "No session is running"),
+            WorksmartError::Session(err) => write!(f, "Session error: {err}"),
+            WorksmartError::VetoRejected(err) => write!(f, "{err}"),
+            WorksmartError::InvalidInput(err) => write!(f, "{err}"),
+            WorksmartError::Internal(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for WorksmartError {}
+
+impl Serialize for WorksmartError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("WorksmartError", 3)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("details", &self.details())?;
+        error.end()
+    }
+}
+
+impl From<CaptureError> for WorksmartError {
+    fn from(err: CaptureError) -> Self {
+        WorksmartError::Capture(err)
+    }
+}
+
+impl From<ConfigError> for WorksmartError {
+    fn from(err: ConfigError) -> Self {
+        WorksmartError::Config(err)
+    }
+}
+
+impl From<std::io::Error> for WorksmartError {
+    fn from(err: std::io::Error) -> Self {
+        WorksmartError::storage(err)
+    }
+}
+
+impl From<tauri::Error> for WorksmartError {
+    fn from(err: tauri::Error) -> Self {
+        WorksmartError::Internal(err.to_string())
+    }
+}
+
+impl From<Error> for WorksmartError {
+    fn from(err: Error) -> Self {
+        WorksmartError::Internal(err.to_string())
+    }
+}
+
+/// The `{ code, message, details }` shape is the `WorksmartError` type in `src/types.ts`
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FieldError, ValidationRule};
+
+    fn serialized(err: WorksmartError) -> Value {
+        serde_json::to_value(err).unwrap()
+    }
+
+    #[test]
+    fn serializes_every_variant() {
+        let field = FieldError {
+            field: "preferences.screenshot_count".into(),
+            rule: ValidationRule::Range { min: 0, max: 20 },
+            message: "must be between 0 and 20".into(),
+        };
+        let cases = [
+            (WorksmartError::Storage("disk full".into()), "storage_failed", "Storage error: disk full", Value::Null),
+            (
+                CaptureError::SkippedByPolicy("Camera is disabled".into()).into(),
+                "capture_skipped",
+                "Capture skipped: Camera is disabled",
+                json!({ "reason": "Camera is disabled" }),
+            ),
+            (CaptureError::PermissionDenied.into(), "permission_denied", "Permission required!", Value::Null),
+            (CaptureError::DeviceBusy.into(), "camera_busy", "Capture device is busy", Value::Null),
+            (CaptureError::NoCamera.into(), "camera_not_found", "No camera available", Value::Null),
+            (CaptureError::Failed("timeout".into()).into(), "capture_failed", "timeout", Value::Null),
+            (WorksmartError::Camera("unplugged".into()), "camera_failed", "Camera error: unplugged", Value::Null),
+            (
+                WorksmartError::Permission(PermissionType::ScreenCapture),
+                "permission_denied",
+                "ScreenCapture permission is required",
+                json!({ "permission": "ScreenCapture" }),
+            ),
+            (
+                ConfigError::Invalid(vec![field.clone()]).into(),
+                "config_invalid",
+                "Invalid configuration: preferences.screenshot_count: must be between 0 and 20",
+                json!({ "fields": [{
+                    "field": "preferences.screenshot_count",
+                    "rule": { "Range": { "min": 0, "max": 20 } },
+                    "message": "must be between 0 and 20",
+                }] }),
+            ),
+            (
+                ConfigError::SaveFailed("read only".into()).into(),
+                "config_save_failed",
+                "Error saving configuration: read only",
+                Value::Null,
+            ),
+            (WorksmartError::NoSession, "session_not_running", "No session is running", Value::Null),
+            (WorksmartError::Session("stopped".into()), "session_failed", "Session error: stopped", Value::Null),
+            (WorksmartError::VetoRejected("too late".into()), "veto_rejected", "too late", Value::Null),
+            (WorksmartError::InvalidInput("bad date".into()), "invalid_input", "bad date", Value::Null),
+            (WorksmartError::Internal("panicked".into()), "internal", "panicked", Value::Null),
+        ];
+
+        for (err, code, message, details) in cases {
+            assert_eq!(
+                serialized(err),
+                json!({ "code": code, "message": message, "details": details }),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn io_errors_are_storage_errors() {
+        let err: WorksmartError = std::io::Error::other("disk full").into();
+
+        assert_eq!(serialized(err)["code"], "storage_failed");
+    }
+}
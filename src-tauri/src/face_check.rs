@@ -0,0 +1,119 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use rustface::{Detector, ImageData};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::encryption;
+
+/// Bundled SeetaFace frontal face model, see `scripts/prepareSidecars.js`
+pub const FACE_MODEL_RESOURCE: &str = "../target/models/seeta_fd_frontal_v1.0.bin";
+
+/// Detector score that maps to a confidence of about 0.63
+const SCORE_SCALE: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FaceResult {
+    Present,
+    Absent,
+    Multiple,
+}
+
+/// Outcome of the face presence check on a webcam snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FacePresence {
+    pub result: FaceResult,
+    pub faces: u32,
+    /// Strength of the best detection between 0 and 1, 0 when no face was found
+    pub confidence: f64,
+}
+
+/// Shared detector, None when the model isn't bundled with this build
+pub type FaceDetectorState = Option<FaceDetector>;
+
+/// Image to analyse and where to send the result
+type FaceJob = (PathBuf, mpsc::Sender<crate::Result<FacePresence>>);
+
+/// Detects faces locally with the bundled model, nothing leaves the machine
+///
+/// The model is loaded once, by a thread that owns the detector and analyses one snapshot at a time
+#[derive(Debug, Clone)]
+pub struct FaceDetector {
+    jobs: mpsc::Sender<FaceJob>,
+}
+
+impl FaceDetector {
+    pub fn new(model_path: PathBuf) -> Self {
+        let (jobs, receiver) = mpsc::channel::<FaceJob>();
+        std::thread::spawn(move || {
+            let mut detector = None;
+            for (image_path, reply) in receiver {
+                let result = load_detector(&model_path, &mut detector).and_then(|detector| detect(detector, &image_path));
+                let _ = reply.send(result);
+            }
+        });
+
+        Self { jobs }
+    }
+
+    /// Returns None when the model isn't bundled with this build
+    pub fn from_app(app: &tauri::AppHandle) -> Option<Self> {
+        let model_path = app.path_resolver().resolve_resource(FACE_MODEL_RESOURCE)?;
+        if !model_path.exists() {
+            error!("Face model not found at {:?}", model_path);
+            return None;
+        }
+
+        Some(Self::new(model_path))
+    }
+
+    /// Blocking, run it off the async runtime
+    pub fn analyze(&self, image_path: &Path) -> crate::Result<FacePresence> {
+        let (reply, result) = mpsc::channel();
+        self.jobs
+            .send((image_path.to_path_buf(), reply))
+            .map_err(|_| "Face detector stopped")?;
+        result.recv().map_err(|_| "Face detector stopped")?
+    }
+}
+
+/// Loads the model on first use, a failed load is tried again with the next snapshot
+fn load_detector<'a>(
+    model_path: &Path,
+    detector: &'a mut Option<Box<dyn Detector>>,
+) -> crate::Result<&'a mut dyn Detector> {
+    if detector.is_none() {
+        let model_path = model_path.to_str().ok_or("Invalid face model path")?;
+        let mut loaded = rustface::create_detector(model_path)
+            .map_err(|err| format!("Error loading face model: {:?}", err))?;
+        loaded.set_min_face_size(40);
+        loaded.set_score_thresh(2.0);
+        loaded.set_pyramid_scale_factor(0.8);
+        loaded.set_slide_window_step(4, 4);
+        *detector = Some(loaded);
+    }
+
+    Ok(detector.as_deref_mut().expect("detector was just loaded"))
+}
+
+fn detect(detector: &mut dyn Detector, image_path: &Path) -> crate::Result<FacePresence> {
+    let image = encryption::load_image(image_path)?.to_luma8();
+    let mut image_data = ImageData::new(image.as_raw(), image.width(), image.height());
+    let faces = detector.detect(&mut image_data);
+
+    let best_score = faces.iter().map(|face| face.score()).fold(0.0, f64::max);
+    let result = match faces.len() {
+        0 => FaceResult::Absent,
+        1 => FaceResult::Present,
+        _ => FaceResult::Multiple,
+    };
+
+    Ok(FacePresence {
+        result,
+        faces: faces.len() as u32,
+        confidence: 1.0 - (-best_score / SCORE_SCALE).exp(),
+    })
+}
@@ -0,0 +1,357 @@
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    camera::camera_error,
+    capture::{CaptureError, ClipMetadata},
+    encryption::{self, CapsuleWriter},
+    ffmpeg_resolver::ffmpeg_path,
+    CameraSettings,
+};
+#[cfg(target_os = "windows")]
+use crate::find_camera;
+
+/// Which implementation grabs webcam stills and screenshots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureBackend {
+    /// nokhwa for cameras and xcap for screens. macOS cameras always go through ffmpeg
+    #[default]
+    Native,
+    /// ffmpeg sidecar: avfoundation on macOS, v4l2 and x11grab on Linux, dshow and gdigrab on Windows
+    Ffmpeg,
+}
+
+/// Where ffmpeg reads webcam frames from
+#[derive(Debug, Clone, PartialEq)]
+pub enum FfmpegInput {
+    /// Camera index or name as understood by the platform's capture device
+    Device(String),
+    /// lavfi test pattern, stands in for a real camera
+    TestSource,
+}
+
+impl FfmpegInput {
+    fn args(&self, settings: &CameraSettings) -> Result<Vec<String>, CaptureError> {
+        let size = format!("{}x{}", settings.width, settings.height);
+        let frame_rate = settings.frame_rate.to_string();
+
+        let device = match self {
+            FfmpegInput::TestSource => {
+                return Ok(vec![
+                    "-f".into(),
+                    "lavfi".into(),
+                    "-i".into(),
+                    format!("testsrc=size={size}:rate={frame_rate}"),
+                ]);
+            }
+            FfmpegInput::Device(device) => device,
+        };
+
+        #[cfg(target_os = "macos")]
+        let (format, device) = ("avfoundation", device.clone());
+        #[cfg(target_os = "windows")]
+        let (format, device) = (
+            "dshow",
+            format!("video={}", find_camera(device).map_err(camera_error)?.human_name()),
+        );
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        let (format, device) = (
+            "v4l2",
+            if device.starts_with("/dev/") {
+                device.clone()
+            } else {
+                format!("/dev/video{device}")
+            },
+        );
+
+        Ok(vec![
+            "-f".into(),
+            format.into(),
+            "-framerate".into(),
+            frame_rate,
+            "-video_size".into(),
+            size,
+            "-i".into(),
+            device,
+        ])
+    }
+}
+
+/// Where ffmpeg grabs the screen from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScreenInput {
+    /// X11 display such as `:0.0`, the screen index on macOS, ignored on Windows
+    Display(String),
+    /// lavfi test pattern, stands in for a real screen
+    TestSource,
+}
+
+impl ScreenInput {
+    /// The display this process runs on, `$DISPLAY` on Linux so Xvfb works too
+    pub fn current() -> Self {
+        ScreenInput::Display(std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string()))
+    }
+
+    fn args(&self) -> Vec<String> {
+        let display = match self {
+            ScreenInput::TestSource => {
+                return vec!["-f".into(), "lavfi".into(), "-i".into(), "testsrc2=size=1280x720".into()];
+            }
+            ScreenInput::Display(display) => display,
+        };
+
+        #[cfg(target_os = "macos")]
+        let (format, display) = ("avfoundation", format!("Capture screen {}", display.trim_start_matches(':')));
+        // gdigrab always grabs the whole desktop
+        #[cfg(target_os = "windows")]
+        let _ = display;
+        #[cfg(target_os = "windows")]
+        let (format, display) = ("gdigrab", "desktop".to_string());
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        let (format, display) = ("x11grab", display.clone());
+
+        vec!["-f".into(), format.into(), "-i".into(), display]
+    }
+}
+
+/// Captures through the ffmpeg sidecar
+#[derive(Debug, Clone)]
+pub struct FfmpegBackend {
+    ffmpeg: PathBuf,
+}
+
+impl FfmpegBackend {
+    pub fn new(ffmpeg: PathBuf) -> Self {
+        Self { ffmpeg }
+    }
+
+    /// Uses the ffmpeg found by `ffmpeg_resolver`
+    pub fn with_default_ffmpeg() -> Self {
+        Self::new(ffmpeg_path())
+    }
+
+    /// Save a single webcam frame, skipping the warm-up frames
+    pub async fn still(
+        &self,
+        input: &FfmpegInput,
+        save_path: &Path,
+        settings: &CameraSettings,
+    ) -> Result<(), CaptureError> {
+        let scale = match settings.output_width {
+            Some(width) => format!("scale={width}:-2"),
+            None => "scale=iw:ih".to_string(),
+        };
+
+        let mut args = input.args(settings)?;
+        args.extend(["-ss".into(), warmup_seconds(settings), "-vf".into(), scale]);
+        args.extend(["-vframes".into(), "1".into(), "-y".into()]);
+
+        self.run(args, save_path).await.map(|_| ())
+    }
+
+    /// Record a short clip, pass `FfmpegInput::TestSource` to record without a camera
+    pub async fn clip(
+        &self,
+        input: &FfmpegInput,
+        save_path: &Path,
+        settings: &CameraSettings,
+        duration_in_seconds: u32,
+        width: u32,
+    ) -> Result<ClipMetadata, CaptureError> {
+        let mut args = input.args(settings)?;
+        args.extend(["-ss".into(), warmup_seconds(settings)]);
+        args.extend(["-t".into(), duration_in_seconds.to_string()]);
+        args.extend(["-vf".into(), format!("scale={width}:-2")]);
+        args.extend(
+            ["-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p", "-an", "-y"].map(String::from),
+        );
+
+        let stderr = self.run(args, save_path).await?;
+
+        let size_in_bytes = tokio::fs::metadata(save_path)
+            .await
+            .map_err(|err| format!("Error reading clip {:?}: {}", save_path, err))?
+            .len();
+
+        Ok(ClipMetadata {
+            duration_in_seconds: encoded_duration(&stderr).unwrap_or(duration_in_seconds as f64),
+            size_in_bytes,
+            width,
+        })
+    }
+
+    /// Grab one frame of the screen
+    pub async fn screenshot(&self, input: &ScreenInput, save_path: &Path) -> Result<(), CaptureError> {
+        let mut args = input.args();
+        args.extend(["-vframes".into(), "1".into(), "-y".into()]);
+
+        self.run(args, save_path).await.map(|_| ())
+    }
+
+    /// Returns ffmpeg's stderr
+    async fn run(&self, args: Vec<String>, save_path: &Path) -> Result<String, CaptureError> {
+        let key = encryption::writer_key()?;
+        let mut args: Vec<OsString> = args.into_iter().map(OsString::from).collect();
+        match key {
+            None => args.push(save_path.as_os_str().to_owned()),
+            // read back from stdout and sealed, ffmpeg never writes the capture in the clear
+            Some(_) => args.extend(pipe_args(save_path).into_iter().map(OsString::from)),
+        }
+
+        let output = tokio::process::Command::new(&self.ffmpeg)
+            .args(args)
+            .stdout(if key.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .map_err(|err| CaptureError::Failed(format!("Ffmpeg command not found {:?}", err)))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        if !output.status.success() {
+            error!("exited with: {}\n{}", output.status, stderr);
+            return Err(camera_error(format!("ffmpeg exited with: {}: {}", output.status, stderr.trim())));
+        }
+
+        if let Some(key) = key {
+            CapsuleWriter::create(save_path, Some(&key))
+                .and_then(|mut file| {
+                    file.write_all(&output.stdout)?;
+                    file.finish()
+                })
+                .map_err(|err| format!("Error saving {:?}: {}", save_path, err))?;
+        }
+
+        info!("exited with: {}", output.status);
+        Ok(stderr)
+    }
+}
+
+/// Output arguments writing to stdout in the format the extension of `save_path` asks for
+fn pipe_args(save_path: &Path) -> Vec<String> {
+    let args = match save_path.extension().and_then(|extension| extension.to_str()) {
+        // a plain mp4 needs a seekable output, fragments don't
+        Some("mp4") => ["-f", "mp4", "-movflags", "frag_keyframe+empty_moov", "pipe:1"],
+        Some("jpg" | "jpeg") => ["-f", "image2pipe", "-c:v", "mjpeg", "pipe:1"],
+        _ => ["-f", "image2pipe", "-c:v", "png", "pipe:1"],
+    };
+    args.map(String::from).to_vec()
+}
+
+/// Seeking past the warm-up frames lets auto exposure settle
+fn warmup_seconds(settings: &CameraSettings) -> String {
+    format!("{:.2}", settings.warmup_frames as f64 / settings.frame_rate.max(1) as f64)
+}
+
+/// Last `time=` progress value ffmpeg printed, in seconds
+fn encoded_duration(stderr: &str) -> Option<f64> {
+    let time = stderr.rsplit("time=").next()?.split_whitespace().next()?;
+    let mut seconds = 0.0;
+    for part in time.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Tests marked `#[ignore]` run a real ffmpeg, `FFMPEG` picks one other than the one on PATH:
+/// `cargo test ffmpeg_backend -- --ignored`
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> FfmpegBackend {
+        FfmpegBackend::new(std::env::var_os("FFMPEG").map_or_else(|| "ffmpeg".into(), PathBuf::from))
+    }
+
+    #[test]
+    fn reads_the_encoded_duration() {
+        let stderr = "frame=   10 fps=0.0 q=-1.0 size=0kB time=00:00:01.50 bitrate=N/A\nframe=   20 time=00:01:02.25 bitrate=N/A";
+
+        assert_eq!(encoded_duration(stderr), Some(62.25));
+        assert_eq!(encoded_duration("no progress"), None);
+    }
+
+    #[test]
+    fn warmup_skips_frames_at_the_frame_rate() {
+        let settings = CameraSettings {
+            warmup_frames: 15,
+            frame_rate: 30,
+            ..Default::default()
+        };
+
+        assert_eq!(warmup_seconds(&settings), "0.50");
+    }
+
+    #[test]
+    fn test_source_needs_no_camera() {
+        let settings = CameraSettings::default();
+        let args = FfmpegInput::TestSource.args(&settings).unwrap();
+
+        assert_eq!(args[..3], ["-f", "lavfi", "-i"]);
+        assert!(args[3].starts_with(&format!("testsrc=size={}x{}", settings.width, settings.height)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg"]
+    async fn records_a_clip_from_lavfi() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portrait.mp4");
+
+        let clip = backend()
+            .clip(&FfmpegInput::TestSource, &path, &CameraSettings::default(), 1, 320)
+            .await
+            .unwrap();
+
+        let video = std::fs::read(&path).unwrap();
+        assert_eq!(&video[4..8], b"ftyp");
+        assert_eq!(clip.width, 320);
+        assert_eq!(clip.size_in_bytes, video.len() as u64);
+        assert!((clip.duration_in_seconds - 1.0).abs() < 0.5, "{:?}", clip);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg"]
+    async fn saves_a_scaled_still_from_lavfi() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portrait.png");
+        let settings = CameraSettings {
+            output_width: Some(320),
+            ..Default::default()
+        };
+
+        backend().still(&FfmpegInput::TestSource, &path, &settings).await.unwrap();
+
+        assert_eq!(image::image_dimensions(&path).unwrap(), (320, 180));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg"]
+    async fn grabs_a_lavfi_screen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("screenshot.png");
+
+        backend().screenshot(&ScreenInput::TestSource, &path).await.unwrap();
+
+        assert_eq!(image::image_dimensions(&path).unwrap(), (1280, 720));
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    #[tokio::test]
+    #[ignore = "needs ffmpeg and an X display, run under xvfb-run"]
+    async fn grabs_the_x11_display() {
+        assert!(std::env::var("DISPLAY").is_ok(), "DISPLAY is not set");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("screenshot.png");
+
+        backend().screenshot(&ScreenInput::current(), &path).await.unwrap();
+
+        let (width, height) = image::image_dimensions(&path).unwrap();
+        assert!(width > 0 && height > 0);
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::relative_command_path;
+
+/// Encoders captures rely on: libx264 for clips and recordings, png for stills
+pub const REQUIRED_ENCODERS: [&str; 2] = ["libx264", "png"];
+
+/// Oldest ffmpeg release captures are known to work with, as (major, minor)
+pub const MIN_VERSION: (u32, u32) = (4, 0);
+
+/// ffmpeg picked by the last diagnosis
+static RESOLVED: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FfmpegSource {
+    /// `Configuration.ffmpeg_path`
+    Config,
+    /// Bundled next to the app executable
+    Sidecar,
+    SystemPath,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FfmpegCandidate {
+    pub source: FfmpegSource,
+    pub path: PathBuf,
+    pub version: Option<String>,
+    /// Release older than `MIN_VERSION`
+    pub outdated: bool,
+    pub missing_encoders: Vec<String>,
+    /// Why the candidate can't run at all
+    pub error: Option<String>,
+}
+
+impl FfmpegCandidate {
+    pub fn is_usable(&self) -> bool {
+        self.error.is_none() && !self.outdated && self.missing_encoders.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FfmpegDiagnostics {
+    /// The ffmpeg captures run, None when no candidate works
+    pub selected: Option<FfmpegCandidate>,
+    pub candidates: Vec<FfmpegCandidate>,
+}
+
+/// Check the configured path, the bundled sidecar and `$PATH` in that order
+///
+/// The first candidate with every required encoder wins, otherwise the first
+/// one that runs. Blocking, it runs every candidate
+pub fn diagnose(config_path: Option<&Path>) -> FfmpegDiagnostics {
+    let sidecar = relative_command_path("ffmpeg").ok();
+    let path_var = std::env::var_os("PATH");
+
+    let candidates: Vec<FfmpegCandidate> = candidate_paths(config_path, sidecar, path_var.as_deref())
+        .into_iter()
+        .map(|(source, path)| check(source, path))
+        .collect();
+    let selected = select(&candidates);
+
+    *RESOLVED.write().unwrap() = selected.as_ref().map(|candidate| candidate.path.clone());
+
+    FfmpegDiagnostics { selected, candidates }
+}
+
+/// Where to look for ffmpeg, in the order candidates are preferred
+fn candidate_paths(
+    config_path: Option<&Path>,
+    sidecar: Option<PathBuf>,
+    path_var: Option<&OsStr>,
+) -> Vec<(FfmpegSource, PathBuf)> {
+    let mut paths = vec![];
+
+    if let Some(path) = config_path {
+        paths.push((FfmpegSource::Config, path.to_path_buf()));
+    }
+    if let Some(path) = sidecar {
+        paths.push((FfmpegSource::Sidecar, path));
+    }
+    if let Some(path) = path_var.and_then(find_in_path) {
+        paths.push((FfmpegSource::SystemPath, path));
+    }
+
+    paths
+}
+
+/// The first usable candidate, otherwise the first one that runs
+fn select(candidates: &[FfmpegCandidate]) -> Option<FfmpegCandidate> {
+    candidates
+        .iter()
+        .find(|candidate| candidate.is_usable())
+        .or_else(|| candidates.iter().find(|candidate| candidate.error.is_none()))
+        .cloned()
+}
+
+/// ffmpeg to run captures with, never blocks
+///
+/// Falls back to the sidecar until a diagnosis has run
+pub fn ffmpeg_path() -> PathBuf {
+    if let Some(path) = RESOLVED.read().unwrap().clone() {
+        return path;
+    }
+
+    relative_command_path("ffmpeg").unwrap_or_else(|_| PathBuf::from("ffmpeg"))
+}
+
+fn check(source: FfmpegSource, path: PathBuf) -> FfmpegCandidate {
+    let mut candidate = FfmpegCandidate {
+        source,
+        path,
+        version: None,
+        outdated: false,
+        missing_encoders: vec![],
+        error: None,
+    };
+
+    if !candidate.path.is_file() {
+        candidate.error = Some("File not found".to_string());
+        return candidate;
+    }
+
+    match run(&candidate.path, &["-version"]) {
+        Ok(output) => {
+            candidate.version = parse_version(&output);
+            candidate.outdated = candidate.version.as_deref().is_some_and(is_outdated);
+        }
+        Err(err) => {
+            candidate.error = Some(err);
+            return candidate;
+        }
+    }
+
+    match run(&candidate.path, &["-hide_banner", "-encoders"]) {
+        Ok(output) => candidate.missing_encoders = missing_encoders(&output),
+        Err(err) => candidate.error = Some(err),
+    }
+
+    candidate
+}
+
+/// Version out of `ffmpeg -version`, whose first line is `ffmpeg version 7.0.1 Copyright (c) ...`
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|line| line.split_whitespace().next())
+        .map(|version| version.to_string())
+}
+
+/// Whether a release version is older than `MIN_VERSION`
+///
+/// Git builds like `N-113054-g...` carry no release number and pass
+fn is_outdated(version: &str) -> bool {
+    let mut parts = version
+        .trim_start_matches('n')
+        .split(|c: char| !c.is_ascii_digit())
+        .map(str::parse::<u32>);
+
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => (major, minor) < MIN_VERSION,
+        _ => false,
+    }
+}
+
+/// Required encoders absent from `ffmpeg -encoders`
+fn missing_encoders(output: &str) -> Vec<String> {
+    // encoder lines look like ` V....D libx264   libx264 H.264 ...`
+    let encoders: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .collect();
+
+    REQUIRED_ENCODERS
+        .iter()
+        .filter(|encoder| !encoders.contains(encoder))
+        .map(|encoder| encoder.to_string())
+        .collect()
+}
+
+fn run(ffmpeg: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(ffmpeg)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Error running {:?}: {}", ffmpeg, err))?;
+
+    if !output.status.success() {
+        return Err(format!("{:?} exited with: {}", ffmpeg, output.status));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn find_in_path(path_var: &OsStr) -> Option<PathBuf> {
+    let name = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+    std::env::split_paths(path_var)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION_7: &str = "ffmpeg version 7.0.1 Copyright (c) 2000-2024 the FFmpeg developers
+built with Apple clang version 15.0.0 (clang-1500.3.9.4)
+configuration: --prefix=/opt/homebrew/Cellar/ffmpeg/7.0.1 --enable-gpl --enable-libx264
+libavutil      59.  8.100 / 59.  8.100";
+
+    const VERSION_3: &str = "ffmpeg version 3.4.8-0ubuntu0.2 Copyright (c) 2000-2020 the FFmpeg developers
+built with gcc 7 (Ubuntu 7.5.0-3ubuntu1~18.04)";
+
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D png                  PNG (Portable Network Graphics) image
+ A....D aac                  AAC (Advanced Audio Coding)";
+
+    const ENCODERS_WITHOUT_X264: &str = "Encoders:
+ V..... = Video
+ ------
+ V....D h264_videotoolbox    VideoToolbox H.264 Encoder (codec h264)
+ V....D png                  PNG (Portable Network Graphics) image";
+
+    fn candidate(source: FfmpegSource, error: Option<&str>, missing_encoders: &[&str]) -> FfmpegCandidate {
+        FfmpegCandidate {
+            source,
+            path: PathBuf::from(format!("{source:?}")),
+            version: Some("7.0.1".into()),
+            outdated: false,
+            missing_encoders: missing_encoders.iter().map(|encoder| encoder.to_string()).collect(),
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn reads_the_version() {
+        assert_eq!(parse_version(VERSION_7).as_deref(), Some("7.0.1"));
+        assert_eq!(parse_version(VERSION_3).as_deref(), Some("3.4.8-0ubuntu0.2"));
+        assert_eq!(parse_version("not ffmpeg"), None);
+    }
+
+    #[test]
+    fn flags_releases_older_than_the_minimum() {
+        assert!(is_outdated("3.4.8-0ubuntu0.2"));
+        assert!(!is_outdated("4.4.2-0ubuntu0.22.04.1"));
+        assert!(!is_outdated("n6.1"));
+        assert!(!is_outdated("N-113054-g1b3ba0e0c5"));
+    }
+
+    #[test]
+    fn reports_missing_encoders() {
+        assert!(missing_encoders(ENCODERS).is_empty());
+        assert_eq!(missing_encoders(ENCODERS_WITHOUT_X264), ["libx264"]);
+    }
+
+    #[test]
+    fn looks_in_config_then_sidecar_then_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+        std::fs::write(dir.path().join(name), b"").unwrap();
+        let path_var = std::env::join_paths([dir.path().join("missing"), dir.path().to_path_buf()]).unwrap();
+
+        let paths = candidate_paths(
+            Some(Path::new("/opt/ffmpeg")),
+            Some(PathBuf::from("/app/ffmpeg")),
+            Some(&path_var),
+        );
+
+        assert_eq!(
+            paths,
+            [
+                (FfmpegSource::Config, PathBuf::from("/opt/ffmpeg")),
+                (FfmpegSource::Sidecar, PathBuf::from("/app/ffmpeg")),
+                (FfmpegSource::SystemPath, dir.path().join(name)),
+            ]
+        );
+        assert!(candidate_paths(None, None, Some(OsStr::new(""))).is_empty());
+    }
+
+    #[test]
+    fn selects_the_first_usable_candidate() {
+        let candidates = [
+            candidate(FfmpegSource::Config, Some("File not found"), &[]),
+            candidate(FfmpegSource::Sidecar, None, &["libx264"]),
+            candidate(FfmpegSource::SystemPath, None, &[]),
+        ];
+
+        assert_eq!(select(&candidates).map(|c| c.source), Some(FfmpegSource::SystemPath));
+        assert_eq!(select(&candidates[..2]).map(|c| c.source), Some(FfmpegSource::Sidecar));
+        assert!(select(&candidates[..1]).is_none());
+    }
+
+    #[test]
+    fn outdated_candidates_are_not_usable() {
+        let mut old = candidate(FfmpegSource::SystemPath, None, &[]);
+        old.outdated = true;
+
+        assert!(!old.is_usable());
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let candidate = check(FfmpegSource::Config, dir.path().join("ffmpeg"));
+
+        assert_eq!(candidate.error.as_deref(), Some("File not found"));
+        assert!(!candidate.is_usable());
+    }
+}
@@ -0,0 +1,230 @@
+use image::{ImageFormat, RgbImage};
+use nokhwa::utils::FrameFormat;
+use yuv::{
+    color::{MatrixCoefficients, Range},
+    convert::{RGBConvert, ToRGB},
+    YUV,
+};
+
+/// Pixel layouts webcams hand us on non-macOS backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Packed 4:2:2, bytes ordered Y0 U Y1 V
+    Yuyv,
+    /// Planar Y followed by interleaved UV at quarter resolution
+    Nv12,
+    Mjpeg,
+    /// Packed 8 bit RGB
+    Rgb,
+}
+
+impl PixelFormat {
+    pub fn from_frame_format(format: FrameFormat) -> Option<Self> {
+        match format {
+            FrameFormat::YUYV => Some(PixelFormat::Yuyv),
+            FrameFormat::NV12 => Some(PixelFormat::Nv12),
+            FrameFormat::MJPEG => Some(PixelFormat::Mjpeg),
+            FrameFormat::RAWRGB => Some(PixelFormat::Rgb),
+            _ => None,
+        }
+    }
+
+    /// Expected buffer length for uncompressed formats
+    pub fn buffer_len(&self, width: u32, height: u32) -> Option<usize> {
+        let pixels = width as usize * height as usize;
+        match self {
+            PixelFormat::Yuyv => Some(pixels * 2),
+            PixelFormat::Nv12 => Some(pixels + pixels / 2),
+            PixelFormat::Rgb => Some(pixels * 3),
+            PixelFormat::Mjpeg => None,
+        }
+    }
+}
+
+/// Convert a raw camera frame into an RGB image
+///
+/// YUV formats are treated as limited range BT.601, which is what UVC webcams send
+pub fn convert_frame(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+) -> Result<RgbImage, String> {
+    if let Some(expected) = format.buffer_len(width, height) {
+        if data.len() < expected {
+            return Err(format!(
+                "{:?} frame of {}x{} needs {} bytes, got {}",
+                format,
+                width,
+                height,
+                expected,
+                data.len()
+            ));
+        }
+    }
+
+    match format {
+        PixelFormat::Yuyv => yuyv_to_rgb(data, width, height),
+        PixelFormat::Nv12 => nv12_to_rgb(data, width, height),
+        PixelFormat::Mjpeg => mjpeg_to_rgb(data),
+        PixelFormat::Rgb => RgbImage::from_raw(width, height, data[..width as usize * height as usize * 3].to_vec())
+            .ok_or_else(|| "Invalid RGB frame buffer".to_string()),
+    }
+}
+
+fn converter() -> Result<RGBConvert<u8>, String> {
+    RGBConvert::<u8>::new(Range::Limited, MatrixCoefficients::BT601)
+        .map_err(|err| format!("Unsupported YUV conversion: {:?}", err))
+}
+
+fn to_pixel(converter: &RGBConvert<u8>, y: u8, u: u8, v: u8) -> image::Rgb<u8> {
+    let rgb = converter.to_rgb(YUV { y, u, v });
+    image::Rgb([rgb.r, rgb.g, rgb.b])
+}
+
+fn yuyv_to_rgb(data: &[u8], width: u32, height: u32) -> Result<RgbImage, String> {
+    if !width.is_multiple_of(2) {
+        return Err(format!("YUYV frames need an even width, got {width}"));
+    }
+
+    let converter = converter()?;
+    let mut image = RgbImage::new(width, height);
+
+    for (y, row) in data
+        .chunks_exact(width as usize * 2)
+        .take(height as usize)
+        .enumerate()
+    {
+        for (x, macropixel) in row.chunks_exact(4).enumerate() {
+            let x = x as u32 * 2;
+            let [y0, u, y1, v] = [macropixel[0], macropixel[1], macropixel[2], macropixel[3]];
+            image.put_pixel(x, y as u32, to_pixel(&converter, y0, u, v));
+            image.put_pixel(x + 1, y as u32, to_pixel(&converter, y1, u, v));
+        }
+    }
+
+    Ok(image)
+}
+
+fn nv12_to_rgb(data: &[u8], width: u32, height: u32) -> Result<RgbImage, String> {
+    if !width.is_multiple_of(2) || !height.is_multiple_of(2) {
+        return Err(format!("NV12 frames need even dimensions, got {width}x{height}"));
+    }
+
+    let converter = converter()?;
+    let (luma, chroma) = data.split_at(width as usize * height as usize);
+
+    Ok(RgbImage::from_fn(width, height, |x, y| {
+        let y_value = luma[(y * width + x) as usize];
+        let uv_index = ((y / 2) * width + (x / 2) * 2) as usize;
+        to_pixel(&converter, y_value, chroma[uv_index], chroma[uv_index + 1])
+    }))
+}
+
+fn mjpeg_to_rgb(data: &[u8]) -> Result<RgbImage, String> {
+    image::load_from_memory_with_format(data, ImageFormat::Jpeg)
+        .map(|image| image.to_rgb8())
+        .map_err(|err| format!("Error decoding MJPEG frame: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::jpeg::JpegEncoder, Rgb};
+
+    use super::*;
+
+    /// BT.601 limited range rounds a little differently from one implementation to the next
+    const TOLERANCE: u8 = 3;
+
+    /// Colors of the golden frame quadrants with their limited range BT.601 YUV
+    const QUADRANTS: [([u8; 3], [u8; 3]); 4] = [
+        ([255, 0, 0], [81, 90, 240]),
+        ([0, 0, 255], [41, 240, 110]),
+        ([255, 255, 255], [235, 128, 128]),
+        ([0, 0, 0], [16, 128, 128]),
+    ];
+
+    /// 4x4 frame with red, blue, white and black 2x2 quadrants so NV12 chroma never straddles two colors
+    fn quadrant(x: u32, y: u32) -> ([u8; 3], [u8; 3]) {
+        QUADRANTS[(y / 2 * 2 + x / 2) as usize]
+    }
+
+    fn golden() -> RgbImage {
+        RgbImage::from_fn(4, 4, |x, y| Rgb(quadrant(x, y).0))
+    }
+
+    fn assert_close(actual: &RgbImage, expected: &RgbImage, tolerance: u8) {
+        assert_eq!(actual.dimensions(), expected.dimensions());
+        for ((x, y, actual), expected) in actual.enumerate_pixels().zip(expected.pixels()) {
+            let close = actual.0.iter().zip(expected.0).all(|(a, e)| a.abs_diff(e) <= tolerance);
+            assert!(close, "pixel {x},{y} is {:?}, expected {:?}", actual.0, expected.0);
+        }
+    }
+
+    #[test]
+    fn yuyv_matches_golden() {
+        let mut data = vec![];
+        for y in 0..4 {
+            for x in (0..4).step_by(2) {
+                let [luma, u, v] = quadrant(x, y).1;
+                data.extend([luma, u, luma, v]);
+            }
+        }
+
+        let image = convert_frame(&data, 4, 4, PixelFormat::Yuyv).unwrap();
+
+        assert_close(&image, &golden(), TOLERANCE);
+    }
+
+    #[test]
+    fn nv12_matches_golden() {
+        let mut data: Vec<u8> = (0..16).map(|index| quadrant(index % 4, index / 4).1[0]).collect();
+        for y in (0..4).step_by(2) {
+            for x in (0..4).step_by(2) {
+                let [_, u, v] = quadrant(x, y).1;
+                data.extend([u, v]);
+            }
+        }
+
+        let image = convert_frame(&data, 4, 4, PixelFormat::Nv12).unwrap();
+
+        assert_close(&image, &golden(), TOLERANCE);
+    }
+
+    #[test]
+    fn rgb_is_copied_as_is() {
+        let golden = golden();
+
+        let image = convert_frame(golden.as_raw(), 4, 4, PixelFormat::Rgb).unwrap();
+
+        assert_eq!(image, golden);
+    }
+
+    #[test]
+    fn mjpeg_matches_golden() {
+        let golden = RgbImage::from_pixel(16, 16, Rgb([200, 40, 90]));
+        let mut data = vec![];
+        JpegEncoder::new_with_quality(&mut data, 100).encode_image(&golden).unwrap();
+
+        let image = convert_frame(&data, 16, 16, PixelFormat::Mjpeg).unwrap();
+
+        assert_close(&image, &golden, TOLERANCE);
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert!(convert_frame(&[0; 10], 4, 4, PixelFormat::Yuyv).is_err());
+        assert!(convert_frame(&[0; 12], 3, 2, PixelFormat::Yuyv).is_err());
+        assert!(convert_frame(&[0; 15], 3, 3, PixelFormat::Nv12).is_err());
+        assert!(convert_frame(&[0; 64], 4, 4, PixelFormat::Mjpeg).is_err());
+    }
+
+    #[test]
+    fn maps_nokhwa_formats() {
+        assert_eq!(PixelFormat::from_frame_format(FrameFormat::YUYV), Some(PixelFormat::Yuyv));
+        assert_eq!(PixelFormat::from_frame_format(FrameFormat::NV12), Some(PixelFormat::Nv12));
+        assert_eq!(PixelFormat::from_frame_format(FrameFormat::MJPEG), Some(PixelFormat::Mjpeg));
+        assert_eq!(PixelFormat::from_frame_format(FrameFormat::RAWRGB), Some(PixelFormat::Rgb));
+        assert_eq!(PixelFormat::from_frame_format(FrameFormat::GRAY), None);
+    }
+}
@@ -3,6 +3,8 @@ pub mod autostart;
 pub mod camera;
 pub mod commands;
 pub mod configuration;
+pub mod database;
+pub mod config_migration;
 pub mod error;
 pub mod recorder;
 pub mod session;
@@ -16,21 +18,48 @@ pub mod screen_capture;
 pub mod time_map;
 pub mod permissions;
 pub mod compressor;
+pub mod scheduler;
+pub mod capture;
+pub mod capture_notice;
+pub mod frame_convert;
+pub mod camera_watcher;
+pub mod face_check;
+pub mod ffmpeg_backend;
+pub mod ffmpeg_resolver;
+pub mod validation;
+pub mod logging;
+pub mod diagnostics;
+pub mod data_move;
+pub mod storage_manager;
+pub mod encryption;
 
 pub use auth::*;
 pub use autostart::*;
 pub use camera::*;
-pub use commands::{record_screen, start_session, stop_session};
+pub use commands::{start_session, stop_session};
 pub use configuration::*;
-pub use error::{Error, Result};
-pub use recorder::{RecordChannel, RecordCommand};
+pub use database::{Database, DatabaseState};
+pub use error::{Error, Result, WorksmartError};
+pub use recorder::{FrameSource, RecordChannel, RecordCommand, RecordReceiver, Recorder, ScreenFrameSource, SyntheticFrameSource};
 pub use session::Session;
 pub use shutdown::Shutdown;
 pub use state::AppState;
-pub use storage::{auth_path, config_path, get_storage_path, load, save, save_to_data_path};
+pub use storage::{auth_path, config_file_path, config_path, get_storage_path, load, save, save_to_data_path};
 pub use utils::*;
 pub use windows::*;
 pub use screen_capture::get_focused_window;
 pub use time_map::*;
 pub use permissions::*;
 pub use compressor::compress_image;
+pub use scheduler::{CaptureScheduler, CaptureStrategy, CaptureWindow};
+pub use capture::{CaptureError, CaptureHealthReport, CaptureKind, CaptureOutcome, CaptureOutput, CaptureRecord, CaptureTrigger, ClipMetadata};
+pub use camera_watcher::CameraWatcher;
+pub use ffmpeg_backend::{CaptureBackend, FfmpegBackend, FfmpegInput, ScreenInput};
+pub use face_check::{FaceDetector, FaceDetectorState, FacePresence, FaceResult};
+pub use validation::{ConfigError, FieldError, ValidationRule};
+pub use capture_notice::{CaptureNotice, PendingCaptures, VetoLedger, VetoLedgerState};
+pub use logging::{LogEntry, LogLevel, Logger, LoggerState};
+pub use diagnostics::{Diagnostics, DiagnosticsExport};
+pub use data_move::{DataMove, DataMoveProgress};
+pub use storage_manager::{StorageManager, StorageUsage};
+pub use encryption::{CapsuleWriter, EncryptionStatus};
@@ -0,0 +1,251 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    Registry,
+};
+
+use crate::storage;
+
+/// Log files are named `worksmart.<date>.log`
+pub const LOG_FILE_PREFIX: &str = "worksmart";
+pub const LOG_FILE_SUFFIX: &str = "log";
+/// Days of logs kept, older files are deleted on rotation
+pub const MAX_LOG_FILES: usize = 7;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+impl LogLevel {
+    /// Parse the upper case level tracing writes, `INFO` for example
+    fn parse(level: &str) -> Option<Self> {
+        match level {
+            "ERROR" => Some(LogLevel::Error),
+            "WARN" => Some(LogLevel::Warn),
+            "INFO" => Some(LogLevel::Info),
+            "DEBUG" => Some(LogLevel::Debug),
+            "TRACE" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// One line of a log file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    /// `message` plus the structured fields of the event
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+    /// Innermost span the event was recorded in
+    #[serde(default)]
+    pub span: Option<Value>,
+}
+
+pub fn log_dir() -> crate::Result<PathBuf> {
+    let log_dir = storage::data_path().join("logs");
+
+    fs::create_dir_all(&log_dir).map_err(|err| format!("Error creating log directory {:?}: {}", log_dir, err))?;
+
+    Ok(log_dir)
+}
+
+/// Writes JSON lines to a daily rotated file and plain text to stdout
+///
+/// Logs only go to stderr when the log files can't be opened
+pub struct Logger {
+    level: reload::Handle<LevelFilter, Registry>,
+    // flushes buffered lines when the app exits
+    _guard: Option<WorkerGuard>,
+}
+
+pub type LoggerState = Arc<Logger>;
+
+impl Logger {
+    /// Install the global subscriber, call once before anything logs
+    pub fn init(level: LogLevel) -> Self {
+        let (filter, handle) = reload::Layer::new(LevelFilter::from(level));
+
+        let appender = log_dir().and_then(|log_dir| {
+            Ok(RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix(LOG_FILE_PREFIX)
+                .filename_suffix(LOG_FILE_SUFFIX)
+                .max_log_files(MAX_LOG_FILES)
+                .build(log_dir)?)
+        });
+        let (file_layer, guard, console, file_error) = match appender {
+            Ok(appender) => {
+                let (writer, guard) = tracing_appender::non_blocking(appender);
+                let layer = fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false)
+                    .with_writer(writer);
+                (Some(layer), Some(guard), BoxMakeWriter::new(std::io::stdout), None)
+            }
+            Err(err) => (None, None, BoxMakeWriter::new(std::io::stderr), Some(err)),
+        };
+
+        let installed = tracing_subscriber::registry()
+            .with(filter)
+            .with(file_layer)
+            .with(fmt::layer().with_writer(console))
+            .try_init();
+        match (installed, file_error) {
+            (Err(err), _) => eprintln!("Error installing the logger: {}", err),
+            (Ok(()), Some(err)) => tracing::error!("Error opening log files, logging to stderr only: {}", err),
+            (Ok(()), None) => {}
+        }
+
+        Self {
+            level: handle,
+            _guard: guard,
+        }
+    }
+
+    pub fn set_level(&self, level: LogLevel) -> crate::Result<()> {
+        self.level.modify(|filter| *filter = level.into())?;
+        tracing::info!(?level, "Log level changed");
+        Ok(())
+    }
+}
+
+/// Newest `limit` entries at `level` or more severe, newest first
+pub fn recent_entries(limit: usize, level: LogLevel) -> crate::Result<Vec<LogEntry>> {
+    read_entries(&log_dir()?, limit, level)
+}
+
+fn read_entries(log_dir: &Path, limit: usize, level: LogLevel) -> crate::Result<Vec<LogEntry>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(log_dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(LOG_FILE_PREFIX) && name.ends_with(LOG_FILE_SUFFIX))
+        })
+        .collect();
+    // the date in the name sorts oldest first
+    files.sort();
+
+    let mut entries = vec![];
+    for file in files.iter().rev() {
+        let content = fs::read_to_string(file)?;
+        for line in content.lines().rev() {
+            // a line being written while we read can be cut short
+            let Ok(entry) = serde_json::from_str::<LogEntry>(line) else {
+                continue;
+            };
+            if LogLevel::parse(&entry.level).is_none_or(|entry_level| entry_level > level) {
+                continue;
+            }
+
+            entries.push(entry);
+            if entries.len() >= limit {
+                return Ok(entries);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(timestamp: &str, level: &str, message: &str) -> String {
+        format!(r#"{{"timestamp":"{timestamp}","level":"{level}","target":"worksmart","fields":{{"message":"{message}"}}}}"#)
+    }
+
+    fn write_log(dir: &Path, date: &str, lines: &[String]) {
+        fs::write(dir.join(format!("{LOG_FILE_PREFIX}.{date}.{LOG_FILE_SUFFIX}")), lines.join("\n")).unwrap();
+    }
+
+    fn messages(entries: &[LogEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.fields["message"].as_str().unwrap()).collect()
+    }
+
+    fn log_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        write_log(
+            dir.path(),
+            "2024-01-01",
+            &[line("2024-01-01T10:00:00Z", "INFO", "started"), line("2024-01-01T11:00:00Z", "ERROR", "camera failed")],
+        );
+        write_log(
+            dir.path(),
+            "2024-01-02",
+            &[
+                line("2024-01-02T09:00:00Z", "DEBUG", "polling"),
+                line("2024-01-02T10:00:00Z", "WARN", "camera busy"),
+                // cut short while being written
+                r#"{"timestamp":"2024-01-02T10:00:01Z","lev"#.to_string(),
+            ],
+        );
+        fs::write(dir.path().join("other.log"), line("2024-01-03T00:00:00Z", "ERROR", "not ours")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_newest_first_across_rotated_files() {
+        let dir = log_dir();
+
+        let entries = read_entries(dir.path(), 10, LogLevel::Trace).unwrap();
+
+        assert_eq!(messages(&entries), ["camera busy", "polling", "camera failed", "started"]);
+    }
+
+    #[test]
+    fn drops_less_severe_entries() {
+        let dir = log_dir();
+
+        let entries = read_entries(dir.path(), 10, LogLevel::Warn).unwrap();
+
+        assert_eq!(messages(&entries), ["camera busy", "camera failed"]);
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let dir = log_dir();
+
+        let entries = read_entries(dir.path(), 2, LogLevel::Trace).unwrap();
+
+        assert_eq!(messages(&entries), ["camera busy", "polling"]);
+    }
+}
@@ -3,17 +3,19 @@
 
 // #![allow(unused_imports)]
 
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 use chrono::{DateTime, Utc};
 // use gst::prelude::*;
 
-use ffmpeg_sidecar::command::ffmpeg_is_installed;
-
 use rdev::{listen, Event, EventType};
 use tauri::{Manager, WindowEvent};
+use tracing::{debug, error, info, warn};
 
 use worksmart::{
-    autostart, commands, gen_rand_string, get_current_datetime, get_default_camera,session::{SessionChannel, SessionController, SessionControllerState, SessionState}, state::{KeystrokeBroadCaster, MouseclickBroadCaster}, windows, AppState, Auth, AuthConfig, Configuration, GeneralConfig, PermisssionsStatus, RecordChannel, SelectedDevice, Session, Shutdown, TimeTrackerMap, TrackHistory
+    autostart, commands, encryption, ffmpeg_resolver, storage, gen_rand_string, get_current_datetime, query_cameras, camera_watcher::resolve_camera, session::{CurrentCapsule, SessionChannel, SessionController, SessionControllerState, SessionState}, state::{KeystrokeBroadCaster, MouseclickBroadCaster}, windows, AppState, Auth, AuthConfig, CameraWatcher, Configuration, DataMove, Database, DatabaseState, FaceDetector, FaceDetectorState, GeneralConfig, LogLevel, Logger, LoggerState, PendingCaptures, PermisssionsStatus, RecordChannel, Recorder, ScreenFrameSource, SelectedDevice, Session, Shutdown, StorageManager, TimeTrackerMap, TrackHistory, VetoLedger, VetoLedgerState
 };
 
 pub fn create_device_query_listener(mouseclick_rx: MouseclickBroadCaster, keystroke_rx: KeystrokeBroadCaster) {
@@ -27,7 +29,7 @@ pub fn create_device_query_listener(mouseclick_rx: MouseclickBroadCaster, keystr
                         .send(get_current_datetime())
                     {
                         // print error log or send stat to server
-                        eprintln!("Error broadcasting Mouse event: {:?}", err);
+                        error!("Error broadcasting Mouse event: {:?}", err);
                     }
                 }
                 // EventType::Wheel { delta_x, delta_y } => {
@@ -46,7 +48,7 @@ pub fn create_device_query_listener(mouseclick_rx: MouseclickBroadCaster, keystr
                         .send(get_current_datetime())
                     {
                         // print error log or send stat to server
-                        eprintln!("Error broadcasting keystroke event: {:?}", err);
+                        error!("Error broadcasting keystroke event: {:?}", err);
                     }
                 }
                 _ => (),
@@ -54,16 +56,23 @@ pub fn create_device_query_listener(mouseclick_rx: MouseclickBroadCaster, keystr
         };
 
         if let Err(error) = listen(callback) {
-            println!("Error listening for key/mouse events: {:?}", error)
+            error!("Error listening for key/mouse events: {:?}", error)
         }
     });
 }
 
 #[tokio::main]
 async fn main() {
+    // before anything reads or writes storage
+    let data_dir = storage::init_data_dir(std::env::args().skip(1));
+    let logger: LoggerState = Arc::new(Logger::init(LogLevel::default()));
+    if let Some(data_dir) = data_dir {
+        info!("Using data directory {:?}", data_dir);
+    }
+
     std::panic::set_hook(Box::new(|info| {
         let message = info.to_string();
-        eprintln!("{message}");
+        error!("{message}");
     }));
 
     // intialize tauri async runtime
@@ -86,12 +95,13 @@ async fn main() {
 
     #[cfg(target_os = "macos")]
     nokhwa::nokhwa_initialize(|granted| {
-        println!("Camera permission granted: {granted}");
+        info!("Camera permission granted: {granted}");
     });
 
-    #[allow(unused_variables)]
-    #[allow(unused_mut)]
-    let (record_tx, mut record_rx): (RecordChannel, _) = tauri::async_runtime::channel(100);
+    let (record_tx, record_rx): (RecordChannel, _) = tauri::async_runtime::channel(100);
+
+    // screen recorder runs on its own thread and is driven through the RecordChannel
+    Recorder::with_default_ffmpeg(Box::new(ScreenFrameSource::default())).spawn(record_rx);
 
     let (session_tx, _): (SessionChannel, _) = tokio::sync::broadcast::channel(1);
 
@@ -105,7 +115,14 @@ async fn main() {
     }));
 
     let general_config: GeneralConfig = Arc::new(Mutex::new(Configuration::default()));
-    println!("Config: {:?}", general_config.lock().unwrap().clone());
+    debug!("Config: {:?}", general_config.lock().unwrap().clone());
+    if let Err(err) = logger.set_level(general_config.lock().unwrap().log_level) {
+        error!("Error setting log level: {}", err);
+    }
+    // captures are skipped until the key is available, never written in the clear
+    if let Err(err) = encryption::configure(&general_config.lock().unwrap().encryption) {
+        error!("Error loading capsule key: {}", err);
+    }
 
     let auth_config = {
         let auth = Auth::default();
@@ -123,7 +140,25 @@ async fn main() {
 
     let session_controller: SessionControllerState = Arc::new(Mutex::new(SessionController::default()));
 
-    let selected_device: SelectedDevice = Arc::new(Mutex::new(get_default_camera().unwrap()));
+    let preferred_camera_id = general_config.lock().unwrap().preferred_camera_id.clone();
+    let default_camera = match query_cameras() {
+        Ok(devices) => resolve_camera(&devices, preferred_camera_id.as_deref(), None),
+        Err(err) => {
+            error!("Error looking up cameras: {:?}", err);
+            None
+        }
+    };
+    if default_camera.is_none() {
+        warn!("No camera found, webcam captures are disabled");
+    }
+    let selected_device: SelectedDevice = Arc::new(Mutex::new(default_camera));
+
+    let current_capsule: CurrentCapsule = Arc::new(Mutex::new(None));
+
+    let pending_captures: PendingCaptures = Arc::new(Mutex::new(HashMap::new()));
+    let veto_ledger: VetoLedgerState = Arc::new(Mutex::new(VetoLedger::load()));
+
+    let database: DatabaseState = Arc::new(Mutex::new(Database::open_default_or_in_memory()));
 
     let app = tauri::Builder::default()
         .manage(AppState {
@@ -138,11 +173,15 @@ async fn main() {
         .manage(time_tracker)
         .manage(selected_device)
         .manage(session_controller)
+        .manage(current_capsule)
+        .manage(pending_captures)
+        .manage(veto_ledger)
+        .manage(database)
+        .manage(logger)
         .invoke_handler(tauri::generate_handler![
             commands::start_session,
             commands::stop_session,
             commands::get_session,
-            commands::record_screen,
             commands::set_preferences,
             commands::get_preferences,
             commands::webcam_capture,
@@ -153,6 +192,7 @@ async fn main() {
             commands::minimize_window,
             commands::list_camera_devices,
             commands::select_camera_device,
+            commands::get_selected_camera_device,
             commands::get_track_history,
             commands::get_time_tracked_today,
             commands::request_camera_permissions,
@@ -160,11 +200,28 @@ async fn main() {
             commands::request_screen_capture_permissions,
             commands::on_permissions_granted,
             commands::get_permission_status,
+            commands::get_capture_health,
+            commands::veto_capture,
+            commands::get_pending_captures,
+            commands::get_capture_preview,
+            commands::capture_now,
+            commands::get_ffmpeg_diagnostics,
+            commands::set_log_level,
+            commands::get_recent_logs,
+            commands::export_diagnostics,
+            commands::move_data_directory,
+            commands::get_data_move,
+            commands::retry_data_move,
+            commands::abandon_data_move,
+            commands::get_storage_usage,
+            commands::enforce_retention,
+            commands::get_encryption_status,
+            commands::unlock_encryption,
             commands::quit_app,
         ])
         .on_window_event(|event| {
             if let WindowEvent::CloseRequested { api, .. } = event.event() {
-                println!("close request!");
+                info!("close request!");
                 // if user is in session prevent close or end session first
                 event.window().hide().unwrap();
                 api.prevent_close();
@@ -182,6 +239,13 @@ async fn main() {
                 windows::show_permission(&app.app_handle());
             }
 
+            // keep the selected camera in sync with connected devices
+            CameraWatcher::new(app.app_handle()).spawn();
+
+            // one detector for every snapshot, the model loads on first use
+            let face_detector: FaceDetectorState = FaceDetector::from_app(&app.app_handle());
+            app.manage(face_detector);
+
             // purge stale keys
             app.state::<TimeTrackerMap>().lock().unwrap().clean_up();
 
@@ -191,13 +255,13 @@ async fn main() {
             tauri::async_runtime::spawn(async move {
                 match tauri::updater::builder(shared_handle).check().await {
                     Ok(update) => {
-                        println!("Worksmart Update: {}", update.is_update_available());
+                        info!("Worksmart Update: {}", update.is_update_available());
                         if update.is_update_available() {
                             update.download_and_install().await.unwrap();
                         }
                     }
                     Err(e) => {
-                        println!("worksmart Update failed to get update: {}", e);
+                        error!("worksmart Update failed to get update: {}", e);
                     }
                 }
             });
@@ -208,7 +272,7 @@ async fn main() {
             // Enable app auto launch
             let autostart = autostart::update(!is_debug_mode);
             if autostart.is_ok() {
-                println!(
+                info!(
                     "Auto start {}",
                     if !is_debug_mode {
                         "enabled"
@@ -218,13 +282,48 @@ async fn main() {
                 );
             }
 
-            // Ffmpeg autodownload
-            ffmpeg_sidecar::download::auto_download().unwrap();
-            if ffmpeg_is_installed() {
-                println!("FFmpeg is already installed! 🎉");
-                println!("TIP: Use `auto_download()` to skip manual customization.");
-                println!("For demo purposes, we'll re-download and unpack it anyway.");
-            }
+            // finish a data move cut short, then import capsules recorded before the database existed
+            // and only then start deleting expired capsules
+            let handle = app.app_handle();
+            let general_config = app.state::<GeneralConfig>().inner().clone();
+            let database = app.state::<DatabaseState>().inner().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                // a move that failed waits for the user to retry or abandon it
+                if let Some(data_move) = DataMove::unfinished().filter(|data_move| data_move.error.is_none()) {
+                    info!("Resuming data move to {:?}", data_move.capsule_dir.to);
+                    let progress = |progress| {
+                        handle.emit_all("DataMoveProgress", progress).ok();
+                    };
+                    if let Err(err) = data_move.run(&general_config, &database, progress) {
+                        error!("Error resuming data move: {}", err);
+                    }
+                }
+
+                let capsule_dir = general_config.lock().unwrap().capsule_dir();
+                match database.lock().unwrap().import_capsule_dirs(&capsule_dir) {
+                    Ok(report) if report.imported > 0 || report.failed > 0 => info!("Imported capsules: {:?}", report),
+                    Ok(_) => {}
+                    Err(err) => error!("Error importing capsules from {:?}: {:?}", capsule_dir, err),
+                }
+
+                StorageManager::new(handle).spawn();
+            });
+
+            // look for ffmpeg off the main thread, captures fall back to the sidecar meanwhile
+            let ffmpeg_config_path = app.state::<GeneralConfig>().lock().unwrap().ffmpeg_path.clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                let diagnostics = ffmpeg_resolver::diagnose(ffmpeg_config_path.as_deref());
+                match &diagnostics.selected {
+                    Some(ffmpeg) if ffmpeg.is_usable() => {
+                        info!("Using ffmpeg {:?} from {:?}", ffmpeg.version, ffmpeg.path)
+                    }
+                    Some(ffmpeg) => warn!(
+                        "Using ffmpeg from {:?}, missing encoders: {:?}",
+                        ffmpeg.path, ffmpeg.missing_encoders
+                    ),
+                    None => error!("No working ffmpeg found: {:?}", diagnostics.candidates),
+                }
+            });
 
             // todo: sign in on app launch based on user preference
             // todo: start tracking on app launch based on user preference
@@ -1,6 +1,7 @@
 // use core_graphics::access::ScreenCaptureAccess;
 // use nokhwa::{nokhwa_check, nokhwa_initialize};
 use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
 
 // #[cfg(target_os = "macos")]
 // use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
@@ -14,7 +15,7 @@ extern "C" {
     // fn AXIsProcessTrustedWithOptions(options: CFDictionaryRef) -> bool;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PermissionType {
     Camera,
     Accessibility,
@@ -34,7 +35,7 @@ impl PermisssionsStatus {
     }
 
     pub fn get_status() -> Self {
-        println!("CheckAvPermission: {}",check_av_permission(AVMediaType::Video));
+        info!("CheckAvPermission: {}",check_av_permission(AVMediaType::Video));
         PermisssionsStatus {
             camera: check_av_permission(AVMediaType::Video),
             accessibility: check_accessibility_permission(),
@@ -43,9 +44,9 @@ impl PermisssionsStatus {
     }
 
     pub fn request_permission(_type: PermissionType) {
-        println!("request permission");
+        info!("request permission");
         open_permission_settings(_type.clone());
-        dbg!(&_type);
+        debug!(permission = ?_type, "Requesting permission");
         match _type {
             PermissionType::Accessibility => {},
             PermissionType::Camera => {
@@ -0,0 +1,459 @@
+use std::{future::Future, path::PathBuf, time::Duration};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, time::Instant};
+use tracing::{error, info};
+
+use crate::{
+    capture::{file_names, CaptureError, CaptureKind, CaptureLog, CaptureOutcome, CaptureOutput, CaptureRecord},
+    capture_notice::CaptureNotice,
+    get_current_datetime, Shutdown,
+};
+
+/// Time reserved at the end of a capsule so media capture can finish before it closes
+pub const MEDIA_CAPTURE_LAG: u64 = 20;
+
+/// Delay between retries of a failed capture
+pub const CAPTURE_RETRY_DELAY: u64 = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum CaptureStrategy {
+    /// Captures at random offsets anywhere within the capture window
+    #[default]
+    UniformRandom,
+    /// Captures spread evenly across the capture window, each moved by up to `jitter_in_seconds`
+    EvenlySpaced { jitter_in_seconds: u64 },
+    /// Captures whenever the focused app changes, up to the planned count
+    OnWindowChange,
+}
+
+/// Range of offsets from the capsule start where captures may be planned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureWindow {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl CaptureWindow {
+    pub fn for_capsule(duration: Duration) -> Self {
+        let start = duration / 10;
+        let end = duration
+            .saturating_sub(Duration::from_secs(MEDIA_CAPTURE_LAG))
+            .max(start);
+
+        Self { start, end }
+    }
+
+    pub fn span(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureScheduler {
+    pub strategy: CaptureStrategy,
+    pub count: u32,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    /// Delay before retrying a busy device, None skips busy captures without retrying
+    pub busy_retry_delay: Option<Duration>,
+    pub notice: Option<CaptureNotice>,
+}
+
+impl CaptureScheduler {
+    pub fn new(strategy: CaptureStrategy, count: u32, retries: u32) -> Self {
+        Self {
+            strategy,
+            count,
+            retries,
+            retry_delay: Duration::from_secs(CAPTURE_RETRY_DELAY),
+            busy_retry_delay: Some(Duration::from_secs(CAPTURE_RETRY_DELAY)),
+            notice: None,
+        }
+    }
+
+    /// Show a capture notice with a veto window after every successful capture
+    pub fn with_notice(mut self, notice: Option<CaptureNotice>) -> Self {
+        self.notice = notice;
+        self
+    }
+
+    /// How long to wait before retrying a busy device, None to skip it instead
+    pub fn with_busy_retry(mut self, delay: Option<Duration>) -> Self {
+        self.busy_retry_delay = delay;
+        self
+    }
+
+    /// Plan capture offsets from the capsule start, sorted ascending
+    ///
+    /// `OnWindowChange` plans nothing up front, captures are driven by window events instead
+    pub fn plan<R: Rng>(&self, window: CaptureWindow, rng: &mut R) -> Vec<Duration> {
+        if self.count == 0 {
+            return vec![];
+        }
+
+        let start = window.start.as_millis() as u64;
+        let end = window.end.as_millis() as u64;
+
+        let mut offsets: Vec<u64> = match &self.strategy {
+            CaptureStrategy::UniformRandom => (0..self.count)
+                .map(|_| rng.gen_range(start..=end))
+                .collect(),
+            CaptureStrategy::EvenlySpaced { jitter_in_seconds } => {
+                let slot = (end - start) / self.count as u64;
+                // keep every capture inside its own slot
+                let jitter = (jitter_in_seconds * 1000).min(slot / 2) as i64;
+
+                (0..self.count as u64)
+                    .map(|index| {
+                        let center = (start + slot * index + slot / 2) as i64;
+                        let offset = center + rng.gen_range(-jitter..=jitter);
+                        offset.clamp(start as i64, end as i64) as u64
+                    })
+                    .collect()
+            }
+            CaptureStrategy::OnWindowChange => vec![],
+        };
+
+        offsets.sort_unstable();
+        offsets.into_iter().map(Duration::from_millis).collect()
+    }
+
+    /// Run the planned captures until they are done or `shutdown` fires
+    ///
+    /// Every planned capture is added to `log` with its outcome, captures that
+    /// never ran because the capsule ended are recorded as cancelled.
+    /// `capture` gets the index of the capture, the same for each of its retries.
+    /// Timing uses tokio's clock so plans can be driven with `tokio::time::pause`
+    pub async fn run<F, Fut, O>(
+        &self,
+        kind: CaptureKind,
+        plan: Vec<Duration>,
+        mut shutdown: Shutdown,
+        mut window_changes: broadcast::Receiver<()>,
+        log: CaptureLog,
+        mut capture: F,
+    ) where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<O, CaptureError>>,
+        O: Into<CaptureOutput>,
+    {
+        let started_at = Instant::now();
+        let started_at_wall = get_current_datetime();
+
+        if self.strategy == CaptureStrategy::OnWindowChange {
+            let mut taken = 0;
+            while taken < self.count {
+                tokio::select! {
+                    _ = shutdown.recv() => return,
+                    event = window_changes.recv() => {
+                        if let Err(broadcast::error::RecvError::Closed) = event {
+                            return;
+                        }
+                    }
+                }
+
+                let record = CaptureRecord::new(kind, get_current_datetime().to_rfc3339());
+                let (record, files, cancelled) =
+                    self.capture_with_retries(record, taken, &mut shutdown, &mut capture).await;
+                self.finish(record, &files, &log);
+                if cancelled {
+                    return;
+                }
+                taken += 1;
+            }
+            return;
+        }
+
+        let planned_at = |offset: Duration| {
+            (started_at_wall + chrono::Duration::from_std(offset).unwrap_or_default()).to_rfc3339()
+        };
+
+        for (index, offset) in plan.iter().enumerate() {
+            let record = CaptureRecord::new(kind, planned_at(*offset));
+
+            let cancelled = tokio::select! {
+                _ = shutdown.recv() => true,
+                _ = tokio::time::sleep_until(started_at + *offset) => false,
+            };
+
+            let (record, files, cancelled) = if cancelled {
+                (record, vec![], true)
+            } else {
+                self.capture_with_retries(record, index as u32, &mut shutdown, &mut capture).await
+            };
+            self.finish(record, &files, &log);
+
+            if cancelled {
+                let mut log = log.write().unwrap();
+                for offset in &plan[index + 1..] {
+                    log.push(CaptureRecord::new(kind, planned_at(*offset)));
+                }
+                return;
+            }
+        }
+    }
+
+    fn finish(&self, record: CaptureRecord, files: &[PathBuf], log: &CaptureLog) {
+        info!(
+            capture_id = %record.id,
+            kind = ?record.kind,
+            attempts = record.attempts,
+            outcome = ?record.outcome,
+            "Capture finished"
+        );
+        log.write().unwrap().push(record.clone());
+        if let Some(notice) = &self.notice {
+            notice.notify(&record, files, log);
+        }
+    }
+
+    /// Returns the finished record, the saved files and whether shutdown cancelled the capture
+    async fn capture_with_retries<F, Fut, O>(
+        &self,
+        mut record: CaptureRecord,
+        index: u32,
+        shutdown: &mut Shutdown,
+        capture: &mut F,
+    ) -> (CaptureRecord, Vec<PathBuf>, bool)
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<O, CaptureError>>,
+        O: Into<CaptureOutput>,
+    {
+        record.started_at = Some(get_current_datetime().to_rfc3339());
+
+        for attempt in 0..=self.retries {
+            if shutdown.is_shutdown() {
+                return (record, vec![], true);
+            }
+
+            record.attempts += 1;
+            let result = capture(index).await;
+            record.finished_at = Some(get_current_datetime().to_rfc3339());
+
+            match result {
+                Ok(output) => {
+                    let output: CaptureOutput = output.into();
+                    record.outcome = CaptureOutcome::Ok;
+                    record.files = file_names(&output.files);
+                    record.clip = output.clip;
+                    record.face = output.face;
+                    return (record, output.files, false);
+                }
+                Err(err) => {
+                    error!(
+                        "[scheduler] {:?} capture attempt {} failed: {}",
+                        record.kind,
+                        attempt + 1,
+                        err
+                    );
+                    record.outcome = CaptureOutcome::from(&err);
+                    if !err.is_retryable() {
+                        return (record, vec![], false);
+                    }
+
+                    let delay = match err {
+                        CaptureError::DeviceBusy => match self.busy_retry_delay {
+                            Some(delay) => delay,
+                            None => return (record, vec![], false),
+                        },
+                        _ => self.retry_delay,
+                    };
+
+                    if attempt < self.retries {
+                        tokio::select! {
+                            _ = shutdown.recv() => return (record, vec![], true),
+                            _ = tokio::time::sleep(delay) => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        (record, vec![], false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    const CAPSULE: Duration = Duration::from_secs(600);
+
+    fn seeded() -> StdRng {
+        StdRng::seed_from_u64(7)
+    }
+
+    #[test]
+    fn uniform_plans_are_sorted_inside_the_window() {
+        let scheduler = CaptureScheduler::new(CaptureStrategy::UniformRandom, 6, 0);
+        let window = CaptureWindow::for_capsule(CAPSULE);
+
+        let plan = scheduler.plan(window, &mut seeded());
+
+        assert_eq!(plan.len(), 6);
+        assert!(plan.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(plan.iter().all(|offset| (window.start..=window.end).contains(offset)));
+        assert_eq!(plan, scheduler.plan(window, &mut seeded()));
+    }
+
+    #[test]
+    fn evenly_spaced_plans_keep_one_capture_per_slot() {
+        let scheduler = CaptureScheduler::new(CaptureStrategy::EvenlySpaced { jitter_in_seconds: 1000 }, 4, 0);
+        let window = CaptureWindow::for_capsule(CAPSULE);
+        let slot = window.span() / 4;
+
+        let plan = scheduler.plan(window, &mut seeded());
+
+        assert_eq!(plan.len(), 4);
+        for (index, offset) in plan.iter().enumerate() {
+            let slot_start = window.start + slot * index as u32;
+            assert!((slot_start..=slot_start + slot).contains(offset), "{:?} left slot {}", offset, index);
+        }
+    }
+
+    #[test]
+    fn window_change_and_empty_plans() {
+        let window = CaptureWindow::for_capsule(CAPSULE);
+
+        assert!(CaptureScheduler::new(CaptureStrategy::OnWindowChange, 3, 0).plan(window, &mut seeded()).is_empty());
+        assert!(CaptureScheduler::new(CaptureStrategy::UniformRandom, 0, 0).plan(window, &mut seeded()).is_empty());
+    }
+
+    #[test]
+    fn short_capsules_leave_room_for_media_capture() {
+        let window = CaptureWindow::for_capsule(Duration::from_secs(10));
+
+        assert_eq!(window.start, window.end);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn captures_run_at_their_planned_offsets() {
+        let scheduler = CaptureScheduler::new(CaptureStrategy::UniformRandom, 2, 0);
+        let plan = scheduler.plan(CaptureWindow::for_capsule(CAPSULE), &mut seeded());
+        let (notify, _) = broadcast::channel(1);
+        let (_window_changes, window_rx) = broadcast::channel(1);
+        let log: CaptureLog = Arc::new(RwLock::new(vec![]));
+        let started_at = Instant::now();
+        let taken_at = Arc::new(Mutex::new(vec![]));
+
+        let taken = taken_at.clone();
+        scheduler
+            .run(CaptureKind::Screenshot, plan.clone(), Shutdown::new(notify.subscribe()), window_rx, log.clone(), |_| {
+                taken.lock().unwrap().push(started_at.elapsed());
+                async { Ok::<_, CaptureError>(vec![PathBuf::from("screenshot.png")]) }
+            })
+            .await;
+
+        assert_eq!(*taken_at.lock().unwrap(), plan);
+        let log = log.read().unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|record| record.outcome == CaptureOutcome::Ok && record.files == ["screenshot.png"]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_keep_the_capture_index() {
+        let scheduler = CaptureScheduler::new(CaptureStrategy::UniformRandom, 2, 2);
+        let plan = vec![Duration::from_secs(10), Duration::from_secs(20)];
+        let (notify, _) = broadcast::channel(1);
+        let (_window_changes, window_rx) = broadcast::channel(1);
+        let log: CaptureLog = Arc::new(RwLock::new(vec![]));
+        let indices = Arc::new(Mutex::new(vec![]));
+
+        let calls = indices.clone();
+        scheduler
+            .run(CaptureKind::Webcam, plan, Shutdown::new(notify.subscribe()), window_rx, log.clone(), |index| {
+                let mut calls = calls.lock().unwrap();
+                calls.push(index);
+                let result = if calls.len() == 1 {
+                    Err(CaptureError::Failed("camera hiccup".into()))
+                } else {
+                    Ok(vec![PathBuf::from(format!("portrait_{index}.png"))])
+                };
+                async move { result }
+            })
+            .await;
+
+        assert_eq!(*indices.lock().unwrap(), [0, 0, 1]);
+        let log = log.read().unwrap();
+        assert_eq!(log[0].attempts, 2);
+        assert_eq!(log[0].files, ["portrait_0.png"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_cancels_the_remaining_plan() {
+        let scheduler = CaptureScheduler::new(CaptureStrategy::UniformRandom, 3, 0);
+        let plan = vec![Duration::from_secs(10), Duration::from_secs(100), Duration::from_secs(200)];
+        let (notify, _) = broadcast::channel(1);
+        let (_window_changes, window_rx) = broadcast::channel(1);
+        let log: CaptureLog = Arc::new(RwLock::new(vec![]));
+
+        let shutdown = Shutdown::new(notify.subscribe());
+        let run = scheduler.run(CaptureKind::Screenshot, plan, shutdown, window_rx, log.clone(), |_| async {
+            Ok::<_, CaptureError>(vec![])
+        });
+        let stop = async {
+            tokio::time::sleep(Duration::from_secs(50)).await;
+            notify.send(()).unwrap();
+        };
+        tokio::join!(run, stop);
+
+        let outcomes: Vec<_> = log.read().unwrap().iter().map(|record| record.outcome.clone()).collect();
+        assert_eq!(outcomes, [CaptureOutcome::Ok, CaptureOutcome::Cancelled, CaptureOutcome::Cancelled]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn busy_devices_retry_after_their_own_delay() {
+        let mut scheduler = CaptureScheduler::new(CaptureStrategy::UniformRandom, 1, 2);
+        scheduler.busy_retry_delay = Some(Duration::from_secs(30));
+        let (notify, _) = broadcast::channel(1);
+        let (_window_changes, window_rx) = broadcast::channel(1);
+        let log: CaptureLog = Arc::new(RwLock::new(vec![]));
+        let started_at = Instant::now();
+        let attempted_at = Arc::new(Mutex::new(vec![]));
+
+        let attempts = attempted_at.clone();
+        scheduler
+            .run(CaptureKind::Webcam, vec![Duration::ZERO], Shutdown::new(notify.subscribe()), window_rx, log.clone(), |_| {
+                let mut attempts = attempts.lock().unwrap();
+                attempts.push(started_at.elapsed());
+                let result = if attempts.len() == 1 {
+                    Err(CaptureError::DeviceBusy)
+                } else {
+                    Ok(vec![PathBuf::from("portrait.png")])
+                };
+                async move { result }
+            })
+            .await;
+
+        assert_eq!(*attempted_at.lock().unwrap(), [Duration::ZERO, Duration::from_secs(30)]);
+        let log = log.read().unwrap();
+        assert_eq!(log[0].attempts, 2);
+        assert_eq!(log[0].outcome, CaptureOutcome::Ok);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn busy_devices_are_skipped_without_a_retry_delay() {
+        let mut scheduler = CaptureScheduler::new(CaptureStrategy::UniformRandom, 1, 2);
+        scheduler.busy_retry_delay = None;
+        let (notify, _) = broadcast::channel(1);
+        let (_window_changes, window_rx) = broadcast::channel(1);
+        let log: CaptureLog = Arc::new(RwLock::new(vec![]));
+
+        scheduler
+            .run(CaptureKind::Webcam, vec![Duration::ZERO], Shutdown::new(notify.subscribe()), window_rx, log.clone(), |_| async {
+                Err::<Vec<PathBuf>, _>(CaptureError::DeviceBusy)
+            })
+            .await;
+
+        let log = log.read().unwrap();
+        assert_eq!(log[0].attempts, 1);
+        assert_eq!(log[0].outcome, CaptureOutcome::DeviceBusy);
+    }
+}
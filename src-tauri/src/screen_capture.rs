@@ -1,9 +1,16 @@
 use std::path::PathBuf;
 
 use active_win_pos_rs::{get_active_window, ActiveWindow};
+use image::DynamicImage;
 use xcap::Monitor;
+use tracing::{error, warn};
 
-use crate::{compressor, get_current_datetime};
+use crate::{
+    capture::{CaptureError, CaptureTrigger},
+    compressor, encryption,
+    ffmpeg_backend::{CaptureBackend, FfmpegBackend, ScreenInput},
+    get_current_datetime,
+};
 
 pub fn get_focused_window() -> Option<ActiveWindow> {
     match get_active_window() {
@@ -12,7 +19,7 @@ pub fn get_focused_window() -> Option<ActiveWindow> {
                 Some(active_window)
             },
             Err(()) => {
-                println!("error occurred while getting the active window");
+                warn!("error occurred while getting the active window");
                 // Err("Could not retrieve active app window".to_string())
                 None
             }
@@ -25,19 +32,59 @@ pub struct ScreenCapture {}
 
 pub struct ScreenshotOptions {
     pub output: PathBuf,
+    pub trigger: CaptureTrigger,
+    pub backend: CaptureBackend,
     // pub window: String,
 }
 
 impl ScreenCapture {
-    pub async fn take_screenshot(options: ScreenshotOptions) -> crate::Result<()> {
-        let monitors = Monitor::all().unwrap();
+    /// Capture every monitor into `options.output`
+    ///
+    /// Returns the saved files
+    pub async fn take_screenshot(options: ScreenshotOptions) -> Result<Vec<PathBuf>, CaptureError> {
+        if !scap::has_permission() {
+            return Err(CaptureError::PermissionDenied);
+        }
+
         let window = match get_focused_window() {
             Some(w) => w.app_name,
             None => "".to_owned()
         };
 
+        let prefix = match options.trigger.tag() {
+            Some(tag) => format!("screenshot_{tag}"),
+            None => "screenshot".to_string(),
+        };
+
+        if options.backend == CaptureBackend::Ffmpeg {
+            // ffmpeg grabs the whole display as a single image
+            let window_name = if window.is_empty() { "display" } else { &window };
+            let img_path = options.output.join(format!(
+                "{}_{}_{}.png",
+                prefix,
+                window_name,
+                get_current_datetime().to_rfc3339(),
+            ));
+
+            FfmpegBackend::with_default_ffmpeg()
+                .screenshot(&ScreenInput::current(), &img_path)
+                .await?;
+            return Ok(vec![compressor::compress_image(img_path, options.output.clone())]);
+        }
+
+        let monitors = Monitor::all().map_err(|err| format!("Error listing monitors: {:?}", err))?;
+
+        let mut files = vec![];
+        let mut errors = vec![];
+
         for monitor in monitors {
-            let image = monitor.capture_image().unwrap();
+            let image = match monitor.capture_image() {
+                Ok(image) => image,
+                Err(err) => {
+                    errors.push(format!("Error capturing monitor {}: {:?}", monitor.name(), err));
+                    continue;
+                }
+            };
 
             let window_name = if window.is_empty() {
                 monitor.name()
@@ -46,23 +93,31 @@ impl ScreenCapture {
             };
 
             let img_path = options.output.clone().join(format!(
-                "screenshot_{}_{}.png",
+                "{}_{}_{}.png",
+                prefix,
                 window_name,
                 get_current_datetime().to_rfc3339(),
             ));
 
-            let file = tokio::fs::File::create(img_path.clone()).await;
+            // sealed before it is written when capsule encryption is on
+            if let Err(err) = encryption::save_image(&DynamicImage::ImageRgba8(image), &img_path) {
+                // save to error log and stream to server later
+                error!("Error saving screenshot {:?}: {}", img_path, err);
+                errors.push(format!("Error saving screenshot {:?}: {}", img_path, err));
+                continue;
+            }
+            // image compression
+            files.push(compressor::compress_image(img_path, options.output.clone()));
+        }
 
-            if file.is_ok() {
-                image.save(&img_path).unwrap();
-                // image compression
-                compressor::compress_image(img_path, options.output.clone());
+        if files.is_empty() {
+            return Err(CaptureError::Failed(if errors.is_empty() {
+                "No monitor available".to_string()
             } else {
-                // save to error log and stream to server later
-                println!("Error saving screenshot to dir: {:?}", img_path);
-            };
+                errors.join("; ")
+            }));
         }
 
-        Ok(())
+        Ok(files)
     }
 }
@@ -9,26 +9,34 @@ use std::{
     },
     time::Duration,
 };
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    get_current_datetime, get_focused_window, get_folder_datetime, screen_capture::{ScreenCapture, ScreenshotOptions}, storage, AppState, CameraController, CameraSnapshotOptions, GeneralConfig, SelectedDevice, Shutdown, TimeTrackerMap
+    capture::{file_names, CaptureError, CaptureKind, CaptureLog, CaptureOutcome, CaptureRecord, CaptureTrigger},
+    capture_notice::CaptureNotice,
+    scheduler::{CaptureScheduler, CaptureWindow},
+    storage_manager::check_disk_space,
+    encryption,
+    get_current_datetime, get_focused_window, get_folder_datetime, screen_capture::{ScreenCapture, ScreenshotOptions}, storage, with_local_timezone, AppState, CameraController, CameraSnapshotOptions, DatabaseState, FaceDetectorState, GeneralConfig, RecordChannel, RecordCommand, SelectedDevice, Shutdown, TimeTrackerMap
 };
 use chrono::Utc;
-use rand::{thread_rng, Rng};
+use rand::thread_rng;
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager};
-use tokio::sync::broadcast;
+use tokio::{sync::broadcast, task::JoinHandle};
 
 pub type SessionChannel = tokio::sync::broadcast::Sender<()>;
 pub type SessionState = Arc<Mutex<Session>>;
+/// Capsule currently being recorded, if a session is running
+pub type CurrentCapsule = Arc<Mutex<Option<TimeCapsule>>>;
 
 pub type DateTimeTz = String;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowEntry {
-    name: String,
-    title: String,
-    time: String,
+    pub name: String,
+    pub title: String,
+    pub time: String,
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +59,9 @@ pub struct TimeCapsule {
     pub started_at: String,
     pub ended_at: Option<String>,
     pub storage_path: PathBuf,
+    pub captures: CaptureLog,
+    /// Capture schedulers still adding to `captures`
+    capture_tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
     exited: Arc<AtomicBool>,
 }
 
@@ -63,27 +74,41 @@ pub struct StorageTimeCapsule {
     pub windows: Vec<WindowEntry>,
     pub started_at: String,
     pub ended_at: Option<String>,
+    #[serde(default)]
+    pub captures: Vec<CaptureRecord>,
 }
 
 impl Session {
+    #[tracing::instrument(skip_all, fields(session_id = %self.id))]
     pub async fn start(&self, app: AppHandle) -> crate::Result<()> {
-        // let record_channel = app.state::<RecordChannel>().clone();
+        let record_channel = app.state::<RecordChannel>().inner().clone();
 
         let mut shutdown = Shutdown::new(self.notify_shutdown.subscribe());
         let mut is_shutdown = false;
 
         while !is_shutdown {
             let id = get_folder_datetime(); //gen_rand_string(16);
-            let dir = app
-                .state::<GeneralConfig>()
-                .lock()
-                .unwrap()
-                .capsule_storage_dir
-                .clone();
+            let dir = app.state::<GeneralConfig>().lock().unwrap().capsule_dir();
 
-            let storage_path = storage::data_path().join(dir).join(id.clone());
+            let storage_path = dir.join(id.clone());
             std::fs::create_dir_all(&storage_path).expect("Can't create capsule directory");
 
+            let preferences = app.state::<GeneralConfig>().lock().unwrap().preferences.clone();
+            let media_allowed = check_disk_space(&storage_path, &preferences.retention).and(encryption::writer_key());
+            if let Err(err) = &media_allowed {
+                warn!("Pausing media captures: {}", err);
+            }
+            let is_recording = preferences.enable_screen_recording && media_allowed.is_ok();
+            if is_recording {
+                // each capsule gets its own video segment
+                if let Err(err) = record_channel.try_send(RecordCommand::Start {
+                    output: storage_path.clone(),
+                    fps: preferences.screen_recording_fps,
+                }) {
+                    error!("Error starting screen recording: {:?}", err);
+                }
+            }
+
             let mut time_capsule = TimeCapsule {
                 id,
                 storage_path,
@@ -93,24 +118,26 @@ impl Session {
                 windows: Arc::new(RwLock::new(vec![])),
                 started_at: get_current_datetime().to_rfc2822(),
                 ended_at: None,
+                captures: Arc::new(RwLock::new(vec![])),
+                capture_tasks: Arc::new(Mutex::new(vec![])),
                 exited: Arc::new(AtomicBool::new(false)),
             };
 
             let capsule_id = time_capsule.id.clone();
             let start_ts = Utc::now().timestamp() as u64;
 
+            *app.state::<CurrentCapsule>().lock().unwrap() = Some(time_capsule.clone());
+
             tokio::select! {
                 res = time_capsule
                     .record(app.clone(), Shutdown::new(self.notify_shutdown.subscribe())) => {
                         match res {
                                 Ok(signal) => {
                                     is_shutdown = signal;
-                                    println!("Timecapsule Finished. Shutdown signal received: {signal}");
+                                    info!("Timecapsule Finished. Shutdown signal received: {signal}");
                                 },
                             Err(err) => {
-                                // log error to server
-                                // save to local error logs to be sent later incase of network issues
-                                println!(
+                                error!(
                                     "Error: Timecapsule {} crashed, details  {:?}",
                                     capsule_id, err
                                 );
@@ -119,20 +146,43 @@ impl Session {
                     },
                 _ = shutdown.recv() => {
                     is_shutdown = true;
-                    println!("Shutdown signal: {}, session done: {}", shutdown.is_shutdown(), self.shutdown.is_shutdown());
+                    info!("Shutdown signal: {}, session done: {}", shutdown.is_shutdown(), self.shutdown.is_shutdown());
 
                 }
             }
 
+            if app
+                .state::<SessionControllerState>()
+                .lock()
+                .unwrap()
+                .is_shutdown()
+            {
+                is_shutdown = true;
+            }
+
+            if is_shutdown && preferences.capture_triggers.before_session_stop {
+                time_capsule
+                    .trigger_screenshot(&app, CaptureTrigger::SessionStop)
+                    .await;
+            }
+
+            *app.state::<CurrentCapsule>().lock().unwrap() = None;
             time_capsule.exit();
 
+            if is_recording {
+                if let Err(err) = record_channel.try_send(RecordCommand::Stop) {
+                    error!("Error stopping screen recording: {:?}", err);
+                }
+            }
+
             let handle = app.clone();
             let end_ts = Utc::now().timestamp() as u64;
 
             tokio::spawn(async move {
-                if let Err(err) = save_capsule(time_capsule).await {
-                    println!("Couldn't save time capsule: {:?}", err);
-                    // todo: log error to server and save to local error log
+                time_capsule.wait_for_captures().await;
+                let database = handle.state::<DatabaseState>().inner().clone();
+                if let Err(err) = save_capsule(time_capsule, database).await {
+                    error!("Couldn't save time capsule: {:?}", err);
                 }
 
                 let diff = end_ts - start_ts;
@@ -142,19 +192,16 @@ impl Session {
                     .unwrap()
                     .increment_track_for_today(diff);
                 handle.state::<TimeTrackerMap>().lock().unwrap().save();
+
+                let today = with_local_timezone(Utc::now()).date_naive();
+                if let Err(err) = handle.state::<DatabaseState>().lock().unwrap().add_daily_total(today, diff) {
+                    error!("Error saving daily total: {:?}", err);
+                }
             });
 
-            if app
-                .state::<SessionControllerState>()
-                .lock()
-                .unwrap()
-                .is_shutdown()
-            {
-                is_shutdown = true;
-            }
-            dbg!(is_shutdown);
+            debug!(is_shutdown, "Capsule done");
         }
-        println!("Session shutdown");
+        info!("Session shutdown");
 
         Ok(())
     }
@@ -166,13 +213,13 @@ impl Session {
 
 // const SESSION_TIME: u16 = 30;
 // const MIN_MEDIA_CAPTURE_TIME: u16 = 3;
-const MEDIA_CAPTURE_LAG: u64 = 20;
 
 impl TimeCapsule {
     /// Record all activities within the time capsule
     ///
     /// Returns bool true if recording ends normally
     /// Returns bool false if shutdown signal was received during recording
+    #[tracing::instrument(skip_all, fields(capsule_id = %self.id))]
     pub async fn record(
         &mut self,
         app_handle: AppHandle,
@@ -200,7 +247,7 @@ impl TimeCapsule {
             while !mouseclick_shutdown.is_shutdown() {
                 tokio::select! {
                     _ = mouseclick_shutdown.recv() => {
-                         println!("mouseclick listener is shutting down");
+                         info!("mouseclick listener is shutting down");
                          break;
                     },
                     resp = mouse_click_rx.recv() => {
@@ -210,7 +257,7 @@ impl TimeCapsule {
                                 mouseclicks.write().unwrap().push(dt.to_rfc2822());
                             }
                             Err(err) => {
-                                println!("mouse click Error: {:?}", err);
+                                warn!("mouse click Error: {:?}", err);
                             }
                         }
                     }
@@ -223,7 +270,7 @@ impl TimeCapsule {
             while !keystroke_shutdown.is_shutdown() {
                 tokio::select! {
                     _ = keystroke_shutdown.recv() => {
-                         println!("keystroke task is shutting down");
+                         info!("keystroke task is shutting down");
                          break;
                     },
                     resp = keystroke_rx.recv() => {
@@ -233,7 +280,7 @@ impl TimeCapsule {
                                 keystrokes.write().unwrap().push(dt.to_rfc2822());
                             }
                             Err(err) => {
-                                println!("keystroke receiver error: {:?}", err);
+                                warn!("keystroke receiver error: {:?}", err);
                             }
                         }
                     }
@@ -244,8 +291,15 @@ impl TimeCapsule {
         };
         tokio::spawn(listen_for_keystrokes);
 
+        // notifies capture schedulers using the OnWindowChange strategy
+        let (window_changes, _) = broadcast::channel::<()>(4);
+
         let exited = self.exited.clone();
         let active_windows = Arc::clone(&self.windows);
+        let window_change_tx = window_changes.clone();
+        let watched_apps = preferences.capture_triggers.watched_apps.clone();
+        let capsule = self.clone();
+        let handle = app_handle.clone();
         let log_delay_in_seconds = preferences.time_gap_duration_in_seconds / 10;
         let active_window_logger = async move {
             // initial 10 secs delay before tracking active window
@@ -269,11 +323,21 @@ impl TimeCapsule {
                         && active_window.clone().unwrap().app_name != window.app_name.as_str()
                     {
                         active_window = Some(window.clone());
+                        let is_watched = watched_apps
+                            .iter()
+                            .any(|app| app.eq_ignore_ascii_case(&window.app_name));
                         active_windows.write().unwrap().push(WindowEntry {
                             name: window.app_name,
                             title: window.title,
                             time: get_current_datetime().to_rfc3339(),
                         });
+                        let _ = window_change_tx.send(());
+
+                        if is_watched {
+                            capsule
+                                .trigger_screenshot(&handle, CaptureTrigger::AppSwitch)
+                                .await;
+                        }
                     }
                 }
             }
@@ -282,67 +346,172 @@ impl TimeCapsule {
 
         let storage_path = Arc::new(self.storage_path.clone());
 
-        let max_delay_based_on_capture_lag = preferences.time_gap_duration_in_seconds - MEDIA_CAPTURE_LAG;
-        let min_capture_start_time = preferences.time_gap_duration_in_seconds / 10;
-        let delay = {
-            let mut gen = thread_rng();
-            gen.gen_range(min_capture_start_time..=max_delay_based_on_capture_lag)
-        };
+        let capture_window = CaptureWindow::for_capsule(Duration::from_secs(
+            preferences.time_gap_duration_in_seconds,
+        ));
 
-        let media_storage_path = Arc::clone(&storage_path);
-        let capsule_exited = self.exited.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_secs(delay as u64)).await;
+        let capture_notice = CaptureNotice::from_app(&app_handle);
 
-            if capsule_exited.load(sync::atomic::Ordering::SeqCst) {
-                return;
-            }
-            if let Err(err) = ScreenCapture::take_screenshot(ScreenshotOptions {
-                output: media_storage_path.to_path_buf(),
-            })
-            .await
-            {
-                eprintln!(
-                    "Error taking screenshot {:?}, time: {}",
-                    get_focused_window().unwrap(),
-                    get_current_datetime()
-                );
-                eprintln!("Error: {:?}", err);
-            }
-        });
-
-        let device_index = app_handle
-            .state::<SelectedDevice>()
-            .lock()
-            .unwrap()
-            .clone()
-            .index()
-            .as_string();
-
-        let webcam_storage_path = Arc::clone(&storage_path);
+        let screenshot_scheduler = CaptureScheduler::new(
+            preferences.capture_strategy.clone(),
+            preferences.screenshot_count,
+            preferences.capture_retries,
+        )
+        .with_notice(capture_notice.clone());
+        let screenshot_plan = screenshot_scheduler.plan(capture_window, &mut thread_rng());
 
-        let exited = self.exited.clone();
-        tokio::spawn(async move {
-            // tokio::time::sleep(Duration::from_secs(delay as u64)).await;
+        let media_storage_path = Arc::clone(&storage_path);
+        let screenshot_shutdown = Shutdown::new(notify_end.subscribe());
+        let screenshot_window_changes = window_changes.subscribe();
+        let screenshot_log = Arc::clone(&self.captures);
+        let capture_backend = preferences.capture_backend;
+        let screenshot_retention = preferences.retention.clone();
+        let screenshot_task = tokio::spawn(async move {
+            screenshot_scheduler
+                .run(
+                    CaptureKind::Screenshot,
+                    screenshot_plan,
+                    screenshot_shutdown,
+                    screenshot_window_changes,
+                    screenshot_log,
+                    |_| {
+                        let disk_space = check_disk_space(&media_storage_path, &screenshot_retention);
+                        let screenshot = ScreenCapture::take_screenshot(ScreenshotOptions {
+                            output: media_storage_path.to_path_buf(),
+                            trigger: CaptureTrigger::Scheduled,
+                            backend: capture_backend,
+                        });
+                        async move {
+                            disk_space?;
+                            screenshot.await
+                        }
+                    },
+                )
+                .await;
+        });
+        self.capture_tasks.lock().unwrap().push(screenshot_task);
+
+        let webcam_scheduler = CaptureScheduler::new(
+            preferences.capture_strategy.clone(),
+            preferences.webcam_shot_count,
+            preferences.capture_retries,
+        )
+        .with_busy_retry(
+            preferences
+                .retry_busy_camera
+                .then(|| Duration::from_secs(preferences.busy_camera_retry_delay_in_seconds)),
+        )
+        .with_notice(capture_notice);
+        let webcam_plan = webcam_scheduler.plan(capture_window, &mut thread_rng());
+
+        let camera_enabled = app_handle.state::<GeneralConfig>().lock().unwrap().enable_camera;
+        let device = app_handle.state::<SelectedDevice>().lock().unwrap().clone();
+        let device_index = match device {
+            _ if !camera_enabled => Err(CaptureError::SkippedByPolicy("Camera is disabled".into())),
+            None => Err(CaptureError::NoCamera),
+            Some(device) => Ok(device.index().as_string()),
+        };
 
-            if exited.load(sync::atomic::Ordering::SeqCst) {
-                return;
+        match device_index {
+            Ok(device_index) => {
+                let webcam_storage_path = Arc::clone(&storage_path);
+                let webcam_shutdown = Shutdown::new(notify_end.subscribe());
+                let webcam_window_changes = window_changes.subscribe();
+                let webcam_log = Arc::clone(&self.captures);
+                let webcam_delay = preferences.webcam_delay;
+                let camera_settings = preferences.camera.clone();
+                let camera_apps = preferences.camera_apps.clone();
+                let webcam_capture_mode = preferences.webcam_capture_mode.clone();
+                let webcam_retention = preferences.retention.clone();
+                let face_detector = if preferences.face_check {
+                    app_handle.state::<FaceDetectorState>().inner().clone()
+                } else {
+                    None
+                };
+                let webcam_task = tokio::spawn(async move {
+                    webcam_scheduler
+                        .run(
+                            CaptureKind::Webcam,
+                            webcam_plan,
+                            webcam_shutdown,
+                            webcam_window_changes,
+                            webcam_log,
+                            |index| {
+                                let file_name = if index == 0 {
+                                    "portrait.png".to_string()
+                                } else {
+                                    format!("portrait_{index}.png")
+                                };
+
+                                let options = CameraSnapshotOptions {
+                                    compress: false,
+                                    delay: webcam_delay,
+                                    settings: camera_settings.clone(),
+                                    mode: webcam_capture_mode.clone(),
+                                    backend: capture_backend,
+                                    selected_device: device_index.clone(),
+                                    save_path: webcam_storage_path.to_path_buf().join(file_name),
+                                };
+                                // opening the camera mid-call would steal it or flicker its light
+                                let camera_app = get_focused_window()
+                                    .map(|window| window.app_name)
+                                    .filter(|app_name| camera_apps.iter().any(|app| app.eq_ignore_ascii_case(app_name)));
+                                let face_detector = face_detector.clone();
+                                let disk_space = check_disk_space(&webcam_storage_path, &webcam_retention);
+                                async move {
+                                    disk_space?;
+                                    if let Some(app_name) = camera_app {
+                                        warn!("Camera is likely in use by {}", app_name);
+                                        return Err(CaptureError::DeviceBusy);
+                                    }
+
+                                    let mut output = CameraController::capture(options).await?;
+
+                                    // clips are not analysed, only stills
+                                    let image = output.files.first().cloned().filter(|_| output.clip.is_none());
+                                    if let (Some(detector), Some(image)) = (face_detector, image) {
+                                        let analysis =
+                                            tokio::task::spawn_blocking(move || detector.analyze(&image).map_err(|err| err.to_string()))
+                                                .await;
+                                        match analysis {
+                                            Ok(Ok(face)) => output.face = Some(face),
+                                            Ok(Err(err)) => error!("Face check failed: {}", err),
+                                            Err(err) => error!("Face check task failed: {:?}", err),
+                                        }
+                                    }
+
+                                    Ok(output)
+                                }
+                            },
+                        )
+                        .await;
+                });
+                self.capture_tasks.lock().unwrap().push(webcam_task);
             }
-
-            if let Err(err) = CameraController
-                ::take_snapshot(
-                    CameraSnapshotOptions {
-                        compress: false,
-                        delay: preferences.webcam_delay,
-                        selected_device: device_index,
-                        save_path: webcam_storage_path.to_path_buf().join("portrait.png"),
-                    }
-                ).await
-            {
-                eprintln!("Webcam snapshot error: {err}");
+            Err(err) => {
+                warn!("Skipping webcam captures: {}", err);
+                // keep the skipped shots in the metadata so capture health reflects them
+                let started_at = get_current_datetime();
+                let mut captures = self.captures.write().unwrap();
+                for offset in &webcam_plan {
+                    let planned_at = started_at + chrono::Duration::from_std(*offset).unwrap_or_default();
+                    let mut record = CaptureRecord::new(CaptureKind::Webcam, planned_at.to_rfc3339());
+                    record.outcome = CaptureOutcome::from(&err);
+                    captures.push(record);
+                }
             }
+        }
 
-        });
+        if preferences.capture_triggers.on_capsule_start {
+            let capsule = self.clone();
+            let handle = app_handle.clone();
+            let trigger_task = tokio::spawn(async move {
+                capsule
+                    .trigger_screenshot(&handle, CaptureTrigger::CapsuleStart)
+                    .await;
+            });
+            self.capture_tasks.lock().unwrap().push(trigger_task);
+        }
 
         let timeout = tokio::spawn(tokio::time::sleep(Duration::from_secs(preferences.time_gap_duration_in_seconds)));
 
@@ -350,10 +519,10 @@ impl TimeCapsule {
         let mut shutdown_signal_received = false;
 
         tokio::select! {
-            _ = timeout => println!("Session Timeout"),
+            _ = timeout => info!("Session Timeout"),
             _ = shutdown.recv() => {
                 shutdown_signal_received = true;
-                println!("Shutdown signal received");
+                info!("Shutdown signal received");
                 drop(notify_end.clone());
             }
         }
@@ -361,21 +530,76 @@ impl TimeCapsule {
         // send drop signals
         drop(notify_end);
 
-        println!("Time capsule ended {}", self.id);
+        info!("Time capsule ended {}", self.id);
         self.ended_at = Some(get_current_datetime().to_rfc2822());
         Ok(shutdown_signal_received)
     }
 
+    /// Take a screenshot outside the capture plan and add it to the capsule captures
+    #[tracing::instrument(skip(self, app), fields(capsule_id = %self.id))]
+    pub async fn trigger_screenshot(&self, app: &AppHandle, trigger: CaptureTrigger) -> CaptureRecord {
+        let mut record = CaptureRecord::new(CaptureKind::Screenshot, get_current_datetime().to_rfc3339());
+        record.trigger = trigger;
+        record.started_at = Some(get_current_datetime().to_rfc3339());
+        record.attempts = 1;
+
+        let preferences = app.state::<GeneralConfig>().lock().unwrap().preferences.clone();
+        let result = match check_disk_space(&self.storage_path, &preferences.retention) {
+            Ok(()) => {
+                ScreenCapture::take_screenshot(ScreenshotOptions {
+                    output: self.storage_path.clone(),
+                    trigger,
+                    backend: preferences.capture_backend,
+                })
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        record.finished_at = Some(get_current_datetime().to_rfc3339());
+
+        let files = match result {
+            Ok(files) => {
+                record.outcome = CaptureOutcome::Ok;
+                record.files = file_names(&files);
+                files
+            }
+            Err(err) => {
+                error!("Error taking {:?} screenshot: {}", trigger, err);
+                record.outcome = CaptureOutcome::from(&err);
+                vec![]
+            }
+        };
+
+        info!(capture_id = %record.id, outcome = ?record.outcome, "Screenshot captured");
+        self.captures.write().unwrap().push(record.clone());
+        if let Some(notice) = CaptureNotice::from_app(app) {
+            notice.notify(&record, &files, &self.captures);
+        }
+
+        record
+    }
+
+    /// Wait for the capture schedulers so cancelled and late captures make it into the metadata
+    pub async fn wait_for_captures(&self) {
+        let tasks = std::mem::take(&mut *self.capture_tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(err) = task.await {
+                error!("Capture task failed: {:?}", err);
+            }
+        }
+    }
+
     pub fn exit(&mut self) {
         self.exited.store(true, sync::atomic::Ordering::SeqCst);
-        println!(
+        info!(
             "Exited: {}",
             self.exited.load(sync::atomic::Ordering::SeqCst)
         );
     }
 }
 
-async fn save_capsule(time_capsule: TimeCapsule) -> crate::Result<()> {
+#[tracing::instrument(skip_all, fields(capsule_id = %time_capsule.id))]
+async fn save_capsule(time_capsule: TimeCapsule, database: DatabaseState) -> crate::Result<()> {
     let TimeCapsule {
         id,
         windows,
@@ -385,6 +609,8 @@ async fn save_capsule(time_capsule: TimeCapsule) -> crate::Result<()> {
         keystrokes,
         mouse_clicks,
         storage_path,
+        captures,
+        capture_tasks: _,
         exited: _,
     } = time_capsule;
 
@@ -396,6 +622,7 @@ async fn save_capsule(time_capsule: TimeCapsule) -> crate::Result<()> {
         windows: windows.read().unwrap().clone(),
         mouse_clicks: mouse_clicks.read().unwrap().clone(),
         keystrokes: keystrokes.read().unwrap().clone(),
+        captures: captures.read().unwrap().clone(),
     };
 
     // dbg!(&value);
@@ -407,13 +634,14 @@ async fn save_capsule(time_capsule: TimeCapsule) -> crate::Result<()> {
         match std::fs::create_dir(&storage_path) {
             Ok(()) => {}
             Err(err) => {
-                println!("Error creating storage folder: {:?}", err);
+                error!("Error creating storage folder: {:?}", err);
                 panic!("Could not create storage directory")
             }
         }
     }
 
-    storage::save_to_data_path(&value, storage_path.join("metadata.json"));
+    storage::save_capsule_json(&value, &storage_path.join("metadata.json"))?;
+    database.lock().unwrap().save_capsule(&value, &storage_path)?;
 
     Ok(())
 }
@@ -1,33 +1,68 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use serde::{de::DeserializeOwned, Serialize};
 
 use tauri::AppHandle;
 // use tokio::fs;
-use std::fs;
+use std::{fs, io::Write};
+use tracing::{error, info, warn};
 
-use crate::Result;
+use crate::{encryption, Result};
+
+/// Environment variable that moves the data directory, `--data-dir` wins over it
+pub const DATA_DIR_ENV: &str = "WORKSMART_DATA_DIR";
+pub const DATA_DIR_ARG: &str = "--data-dir";
+
+static DATA_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Read the data directory override from `args` or `WORKSMART_DATA_DIR`
+///
+/// Call once at startup before anything touches storage. Configuration and
+/// auth move along with the data so separate runs don't share anything
+pub fn init_data_dir(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    let mut from_args = None;
+    while let Some(arg) = args.next() {
+        if arg == DATA_DIR_ARG {
+            from_args = args.next();
+        } else if let Some(dir) = arg.strip_prefix(DATA_DIR_ARG).and_then(|arg| arg.strip_prefix('=')) {
+            from_args = Some(dir.to_string());
+        }
+    }
+
+    let dir = from_args
+        .or_else(|| std::env::var(DATA_DIR_ENV).ok())
+        .filter(|dir| !dir.trim().is_empty())
+        .map(PathBuf::from)?;
+    // relative to where the app was started, it must not change meaning later
+    let dir = std::path::absolute(&dir).unwrap_or(dir);
+
+    Some(DATA_DIR_OVERRIDE.get_or_init(|| dir).clone())
+}
 
 pub fn get_storage_path(app_handle: &AppHandle) -> Result<PathBuf> {
     let data_path = tauri::api::path::app_data_dir(&app_handle.config()).unwrap_or_default();
     let storage_path = data_path.join("media");
     let path = storage_path.to_str().unwrap();
 
-    println!("Data path: {:?}", &data_path);
+    info!("Data path: {:?}", &data_path);
     let exists = fs::metadata(data_path.clone().to_str().unwrap()).is_ok();
     if !exists {
         match fs::create_dir(&data_path) {
             Ok(()) => {}
-            Err(err) => println!("Error creating data folder: {:?}", err),
+            Err(err) => error!("Error creating data folder: {:?}", err),
         }
     }
 
-    println!("Storage path: {:?}", &storage_path);
+    info!("Storage path: {:?}", &storage_path);
     let exists = fs::metadata(path).is_ok();
     if !exists {
         match fs::create_dir(&storage_path) {
             Ok(()) => {}
-            Err(err) => println!("Error creating storage folder: {:?}", err),
+            Err(err) => error!("Error creating storage folder: {:?}", err),
         }
     }
 
@@ -38,73 +73,288 @@ pub fn project_dirs() -> directories::ProjectDirs {
     directories::ProjectDirs::from("", "worksmart", "").expect("Can't use app directory")
 }
 
-pub fn config_path<D>() -> PathBuf {
-    let config_path = project_dirs().config_dir().to_path_buf();
+fn config_dir() -> PathBuf {
+    let config_path = match DATA_DIR_OVERRIDE.get() {
+        Some(dir) => dir.join("config"),
+        None => project_dirs().config_dir().to_path_buf(),
+    };
 
     std::fs::create_dir_all(&config_path).expect("Can't create config directory");
 
-    config_path.join(format!("{}.bin", std::any::type_name::<D>()).replace("::", "-"))
+    config_path
 }
 
-pub fn auth_path<D>() -> PathBuf {
-    let auth_path = project_dirs().data_local_dir().to_path_buf();
+pub fn config_path<D>() -> PathBuf {
+    config_dir().join(format!("{}.bin", std::any::type_name::<D>()).replace("::", "-"))
+}
 
-    std::fs::create_dir_all(&auth_path).expect("Can't create config directory");
+/// Versioned JSON configuration, see `config_migration`
+pub fn config_file_path() -> PathBuf {
+    config_dir().join("config.json")
+}
 
-    auth_path.join(format!("{}.bin", std::any::type_name::<D>()))
+pub fn auth_path<D>() -> PathBuf {
+    data_path().join(format!("{}.bin", std::any::type_name::<D>()))
 }
 
 pub fn data_path() -> PathBuf {
-    let storage_path = project_dirs().data_local_dir().to_path_buf();
+    let storage_path = match DATA_DIR_OVERRIDE.get() {
+        Some(dir) => dir.clone(),
+        None => project_dirs().data_local_dir().to_path_buf(),
+    };
 
     std::fs::create_dir_all(&storage_path).expect("Can't create config directory");
 
     storage_path
 }
 
-pub fn save<D>(data: &D)
+pub fn save<D>(data: &D) -> crate::Result<()>
 where
-    D: Serialize,
+    D: Serialize + DeserializeOwned,
 {
-    let data: Vec<u8> = bincode::serialize(data).unwrap();
-    std::fs::write(config_path::<D>(), data).expect("Can't save app configuration");
+    save_to_path(data, config_path::<D>())
 }
 
 pub fn save_to_path<D>(data: &D, path: PathBuf) -> crate::Result<()>
 where
-    D: Serialize,
+    D: Serialize + DeserializeOwned,
 {
-    let data: Vec<u8> = bincode::serialize(data).unwrap();
-    std::fs::write(&path, data).map_err(|err| err.to_string())?;
-    Ok(())
+    let data: Vec<u8> = bincode::serialize(data)?;
+    write_with_backup(&path, &data, |current| bincode::deserialize::<D>(current).is_ok())
 }
 
-pub fn save_to_data_path<D>(data: &D, dir: PathBuf)
+pub fn save_to_data_path<D>(data: &D, dir: PathBuf) -> crate::Result<()>
 where
-    D: Serialize,
+    D: Serialize + DeserializeOwned,
 {
-    let data = serde_json::json!(data);
-    let mut bytes: Vec<u8> = Vec::new();
-    serde_json::to_writer(&mut bytes, &data).unwrap();
-    std::fs::write(data_path().join(dir), bytes).expect("Can't save app configuration");
+    let data = serde_json::to_vec(data)?;
+    write_with_backup(&data_path().join(dir), &data, |current| {
+        serde_json::from_slice::<D>(current).is_ok()
+    })
 }
 
 pub fn load<D>() -> crate::Result<D>
 where
     D: DeserializeOwned,
 {
-    let data = std::fs::read(config_path::<D>())?;
-    let data: D = bincode::deserialize(&data).unwrap();
-
-    Ok(data)
+    read_with_backup(&config_path::<D>(), |data| Ok(bincode::deserialize(data)?))
 }
 
 pub fn load_from_path<D>(path: PathBuf) -> crate::Result<D>
 where
     D: DeserializeOwned,
 {
-    let data = std::fs::read(path)?;
-    let data: D = bincode::deserialize(&data).unwrap();
+    read_with_backup(&path, |data| Ok(bincode::deserialize(data)?))
+}
+
+/// Capsule metadata, sealed when capsule encryption is on
+pub fn save_capsule_json<D>(data: &D, path: &Path) -> crate::Result<()>
+where
+    D: Serialize + DeserializeOwned,
+{
+    let data = encryption::seal(&serde_json::to_vec(data)?)?;
+    write_with_backup(path, &data, |current| {
+        encryption::open(current).is_ok_and(|json| serde_json::from_slice::<D>(&json).is_ok())
+    })
+}
+
+pub fn load_capsule_json<D>(path: &Path) -> crate::Result<D>
+where
+    D: DeserializeOwned,
+{
+    read_with_backup(path, |data| Ok(serde_json::from_slice(&encryption::open(data)?)?))
+}
+
+pub fn load_json_from_path<D>(path: PathBuf) -> crate::Result<D>
+where
+    D: DeserializeOwned,
+{
+    read_with_backup(&path, |data| Ok(serde_json::from_slice(data)?))
+}
+
+/// `path` with `extension` appended, `tracker.json` becomes `tracker.json.bak`
+pub fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(extension);
+    PathBuf::from(sibling)
+}
+
+/// Replace `path` so a crash leaves either the old or the new contents, never a mix
+///
+/// The bytes go to a synced temp file with a unique name next to `path`,
+/// which is then renamed over it in one step
+pub fn write_atomic(path: &Path, data: &[u8]) -> crate::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .map_err(|err| format!("Error creating a temp file in {:?}: {}", dir, err))?;
+    file.write_all(data)?;
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .map_err(|err| format!("Error replacing {:?}: {}", path, err.error))?;
+
+    // the rename only survives a power loss once the directory is synced too
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+/// `write_atomic` keeping the replaced file as `.bak` for `read_with_backup`
+///
+/// The current file only becomes the backup when `decodes` accepts it, so a
+/// corrupt file never replaces a good backup
+pub fn write_with_backup(path: &Path, data: &[u8], decodes: impl Fn(&[u8]) -> bool) -> crate::Result<()> {
+    if let Ok(current) = fs::read(path) {
+        if decodes(&current) {
+            write_atomic(&sibling_path(path, "bak"), &current)?;
+        } else {
+            warn!("{:?} is corrupt, keeping the previous backup", path);
+        }
+    }
 
-    Ok(data)
+    write_atomic(path, data)
+}
+
+/// Decode `path`, falling back to its `.bak` when it is missing or corrupt
+///
+/// Errors with the reason the primary file failed when the backup can't be read either
+pub fn read_with_backup<D>(path: &Path, decode: impl Fn(&[u8]) -> crate::Result<D>) -> crate::Result<D> {
+    let read = |path: &Path| fs::read(path).map_err(crate::Error::from).and_then(|data| decode(&data));
+
+    let err = match read(path) {
+        Ok(data) => return Ok(data),
+        Err(err) => err,
+    };
+
+    let backup_path = sibling_path(path, "bak");
+    if !backup_path.exists() {
+        return Err(err);
+    }
+
+    match read(&backup_path) {
+        Ok(data) => {
+            warn!("Error reading {:?}: {}, using {:?}", path, err, backup_path);
+            Ok(data)
+        }
+        Err(backup_err) => {
+            error!("Error reading backup {:?}: {}", backup_path, backup_err);
+            Err(err)
+        }
+    }
+}
+
+/// Injects the failures atomic saves guard against
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    type Tracker = HashMap<String, u64>;
+
+    fn tracker(seconds: u64) -> Tracker {
+        HashMap::from([("2024-01-01".to_string(), seconds)])
+    }
+
+    #[test]
+    fn truncated_file_recovers_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.bin");
+        save_to_path(&tracker(1), path.clone()).unwrap();
+        save_to_path(&tracker(2), path.clone()).unwrap();
+
+        // cut short as if the disk filled up mid-write
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        assert_eq!(load_from_path::<Tracker>(path).unwrap(), tracker(1));
+    }
+
+    #[test]
+    fn leftover_temp_file_is_ignored_and_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.bin");
+        save_to_path(&tracker(3), path.clone()).unwrap();
+
+        // crash before the rename
+        fs::write(sibling_path(&path, "tmp"), b"\x01\x02").unwrap();
+        assert_eq!(load_from_path::<Tracker>(path.clone()).unwrap(), tracker(3));
+
+        save_to_path(&tracker(4), path.clone()).unwrap();
+        assert_eq!(load_from_path::<Tracker>(path).unwrap(), tracker(4));
+    }
+
+    #[test]
+    fn missing_file_recovers_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.bin");
+        save_to_path(&tracker(5), path.clone()).unwrap();
+        save_to_path(&tracker(6), path.clone()).unwrap();
+
+        // lost to a crash or removed by hand
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(load_from_path::<Tracker>(path).unwrap(), tracker(5));
+    }
+
+    #[test]
+    fn corrupt_file_and_backup_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.bin");
+        fs::write(&path, b"not bincode").unwrap();
+        fs::write(sibling_path(&path, "bak"), b"").unwrap();
+
+        assert!(load_from_path::<Tracker>(path).is_err());
+    }
+
+    #[test]
+    fn corrupt_json_recovers_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metadata.json");
+        write_with_backup(&path, br#"{"2024-01-01":7}"#, |_| true).unwrap();
+        write_with_backup(&path, br#"{"2024-01-01":"#, |_| true).unwrap();
+
+        assert_eq!(load_json_from_path::<Tracker>(path).unwrap(), tracker(7));
+    }
+
+    #[test]
+    fn corrupt_file_never_replaces_a_good_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.bin");
+        save_to_path(&tracker(8), path.clone()).unwrap();
+        save_to_path(&tracker(9), path.clone()).unwrap();
+        fs::write(&path, b"\x01").unwrap();
+
+        save_to_path(&tracker(10), path.clone()).unwrap();
+        fs::write(&path, b"\x01").unwrap();
+
+        assert_eq!(load_from_path::<Tracker>(path).unwrap(), tracker(8));
+    }
+
+    #[test]
+    fn concurrent_writers_always_leave_a_whole_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.json");
+        write_atomic(&path, &serde_json::to_vec(&tracker(0)).unwrap()).unwrap();
+
+        std::thread::scope(|scope| {
+            for writer in 1..=4 {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        write_atomic(path, &serde_json::to_vec(&tracker(writer)).unwrap()).unwrap();
+                        let current: Tracker = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+                        assert!((1..=4).contains(&current["2024-01-01"]));
+                    }
+                });
+            }
+        });
+
+        let leftovers = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(leftovers, 1);
+    }
 }
@@ -0,0 +1,481 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tracing::{error, info, warn};
+
+use crate::{
+    data_move,
+    database::{Database, MediaKind, MediaRef, StoredCapsule},
+    get_current_datetime, with_local_timezone, CaptureError, DatabaseState, GeneralConfig, RetentionSettings,
+};
+
+/// Seconds between retention sweeps
+pub const RETENTION_INTERVAL: u64 = 30 * 60;
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionReport {
+    pub removed_files: u32,
+    pub removed_capsules: u32,
+    pub freed_bytes: u64,
+}
+
+/// Bytes used by the capsules started on one day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DayUsage {
+    /// `YYYY-MM-DD` in local time
+    pub date: String,
+    pub screenshots: u64,
+    pub webcam: u64,
+    pub screen_recordings: u64,
+    pub metadata: u64,
+    /// Files the database doesn't know, a capsule still recording for example
+    pub other: u64,
+}
+
+impl DayUsage {
+    pub fn total(&self) -> u64 {
+        self.screenshots + self.webcam + self.screen_recordings + self.metadata + self.other
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageUsage {
+    /// Oldest first
+    pub days: Vec<DayUsage>,
+    /// Size of the capsule folders
+    pub total_bytes: u64,
+    /// None without a quota
+    pub max_bytes: Option<u64>,
+    pub free_disk_bytes: Option<u64>,
+    /// Media captures are paused for lack of disk space
+    pub captures_paused: bool,
+}
+
+/// Free space for unprivileged users on the disk holding `dir`, which may not exist yet
+pub fn available_space(dir: &Path) -> io::Result<u64> {
+    let existing = dir
+        .ancestors()
+        .find(|dir| dir.exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{:?} doesn't exist", dir)))?;
+
+    fs4::available_space(existing)
+}
+
+/// Refuse media captures while free disk space is below the configured minimum
+pub fn check_disk_space(dir: &Path, retention: &RetentionSettings) -> Result<(), CaptureError> {
+    if retention.min_free_disk_in_mb == 0 {
+        return Ok(());
+    }
+
+    match available_space(dir) {
+        Ok(free) if free < retention.min_free_disk_in_mb * MB => Err(CaptureError::SkippedByPolicy(format!(
+            "Less than {} MB of disk space left",
+            retention.min_free_disk_in_mb
+        ))),
+        Ok(_) => Ok(()),
+        Err(err) => {
+            // a failed check shouldn't stop captures
+            warn!("Error checking free disk space of {:?}: {}", dir, err);
+            Ok(())
+        }
+    }
+}
+
+/// Delete capsule data past its retention, then trim the oldest capsules down to the quota
+///
+/// Media goes first, a capsule folder is only deleted once its metadata expires
+/// or when removing every media file still doesn't fit the quota. Capsules still
+/// recording aren't in the database yet and are never touched. The database is
+/// only locked to read the capsules and to forget what was deleted, not while
+/// deleting files
+pub fn enforce_retention(
+    capsule_dir: &Path,
+    retention: &RetentionSettings,
+    database: &Mutex<Database>,
+    now: DateTime<Utc>,
+) -> crate::Result<RetentionReport> {
+    let mut report = RetentionReport::default();
+    let expired = |capsule: &StoredCapsule, days: u32| {
+        days > 0
+            && DateTime::parse_from_rfc2822(&capsule.started_at)
+                .is_ok_and(|started_at| started_at < now - chrono::Duration::days(days as i64))
+    };
+
+    let capsules = stored_capsules(&database.lock().unwrap())?;
+    let (expired_capsules, mut capsules): (Vec<_>, Vec<_>) = capsules
+        .into_iter()
+        .partition(|(capsule, _)| expired(capsule, retention.metadata_days));
+
+    for (capsule, _) in &expired_capsules {
+        remove_capsule(capsule, capsule_dir, database, &mut report)?;
+    }
+    for (capsule, media) in &mut capsules {
+        if expired(capsule, retention.screenshot_days) {
+            remove_media(capsule, media, &[MediaKind::Screenshot, MediaKind::ScreenRecording], database, &mut report)?;
+        }
+        if expired(capsule, retention.webcam_days) {
+            remove_media(capsule, media, &[MediaKind::Webcam], database, &mut report)?;
+        }
+    }
+
+    if retention.max_storage_in_mb > 0 {
+        let max_bytes = retention.max_storage_in_mb * MB;
+        let mut used = dir_size(capsule_dir);
+
+        let all_media = [MediaKind::Screenshot, MediaKind::Webcam, MediaKind::ScreenRecording];
+        for (capsule, media) in &mut capsules {
+            if used <= max_bytes {
+                break;
+            }
+            used = used.saturating_sub(remove_media(capsule, media, &all_media, database, &mut report)?);
+        }
+        for (capsule, _) in &capsules {
+            if used <= max_bytes {
+                break;
+            }
+            used = used.saturating_sub(remove_capsule(capsule, capsule_dir, database, &mut report)?);
+        }
+        if used > max_bytes {
+            warn!("Capsules still use {} MB, over the {} MB quota", used / MB, retention.max_storage_in_mb);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Usage per day of the capsules in the database
+pub fn usage(capsule_dir: &Path, retention: &RetentionSettings, database: &Database) -> crate::Result<StorageUsage> {
+    let mut days: BTreeMap<String, DayUsage> = BTreeMap::new();
+
+    for capsule in database.capsules_between(0, i64::MAX)? {
+        let Ok(started_at) = DateTime::parse_from_rfc2822(&capsule.started_at) else {
+            continue;
+        };
+        let date = with_local_timezone(started_at.with_timezone(&Utc)).date_naive().to_string();
+        let day = days.entry(date.clone()).or_insert_with(|| DayUsage {
+            date,
+            ..Default::default()
+        });
+
+        let kinds: HashMap<PathBuf, MediaKind> = database
+            .media(&capsule.id)?
+            .into_iter()
+            .map(|media| (media.path, media.kind))
+            .collect();
+        let Ok(entries) = fs::read_dir(&capsule.storage_path) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let size = dir_size(&path);
+            let is_metadata = entry.file_name().to_string_lossy().starts_with("metadata.json");
+            match kinds.get(&path) {
+                Some(MediaKind::Screenshot) => day.screenshots += size,
+                Some(MediaKind::Webcam) => day.webcam += size,
+                Some(MediaKind::ScreenRecording) => day.screen_recordings += size,
+                None if is_metadata => day.metadata += size,
+                None => day.other += size,
+            }
+        }
+    }
+
+    let free_disk_bytes = available_space(capsule_dir).ok();
+    Ok(StorageUsage {
+        days: days.into_values().collect(),
+        total_bytes: dir_size(capsule_dir),
+        max_bytes: (retention.max_storage_in_mb > 0).then(|| retention.max_storage_in_mb * MB),
+        captures_paused: check_disk_space(capsule_dir, retention).is_err(),
+        free_disk_bytes,
+    })
+}
+
+/// Runs `enforce_retention` at startup and every `RETENTION_INTERVAL`
+pub struct StorageManager {
+    app: AppHandle,
+    interval: Duration,
+}
+
+impl StorageManager {
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            interval: Duration::from_secs(RETENTION_INTERVAL),
+        }
+    }
+
+    pub fn spawn(self) {
+        tauri::async_runtime::spawn(async move { self.run().await });
+    }
+
+    async fn run(self) {
+        loop {
+            // capsules are half way between two folders
+            if data_move::in_progress() {
+                tokio::time::sleep(self.interval).await;
+                continue;
+            }
+
+            let (capsule_dir, retention) = {
+                let config = self.app.state::<GeneralConfig>();
+                let config = config.lock().unwrap();
+                (config.capsule_dir(), config.preferences.retention.clone())
+            };
+            let database = self.app.state::<DatabaseState>().inner().clone();
+
+            let sweep = tauri::async_runtime::spawn_blocking(move || {
+                enforce_retention(&capsule_dir, &retention, &database, get_current_datetime())
+            });
+            match sweep.await {
+                Ok(Ok(report)) if report.removed_files > 0 || report.removed_capsules > 0 => {
+                    info!(?report, "Removed expired capsule data")
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => error!("Error enforcing retention: {}", err),
+                Err(err) => error!("Retention task failed: {:?}", err),
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+/// Every capsule with its media files, oldest first
+fn stored_capsules(database: &Database) -> crate::Result<Vec<(StoredCapsule, Vec<MediaRef>)>> {
+    database
+        .capsules_between(0, i64::MAX)?
+        .into_iter()
+        .map(|capsule| {
+            let media = database.media(&capsule.id)?;
+            Ok((capsule, media))
+        })
+        .collect()
+}
+
+/// Returns the bytes freed
+fn remove_media(
+    capsule: &StoredCapsule,
+    media: &mut Vec<MediaRef>,
+    kinds: &[MediaKind],
+    database: &Mutex<Database>,
+    report: &mut RetentionReport,
+) -> crate::Result<u64> {
+    let mut freed = 0;
+    for media in media.iter().filter(|media| kinds.contains(&media.kind)) {
+        let size = dir_size(&media.path);
+        match fs::remove_file(&media.path) {
+            Ok(()) => {
+                freed += size;
+                report.removed_files += 1;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(format!("Error removing {:?}: {}", media.path, err).into()),
+        }
+    }
+    media.retain(|media| !kinds.contains(&media.kind));
+    database.lock().unwrap().forget_media(&capsule.id, kinds)?;
+
+    report.freed_bytes += freed;
+    Ok(freed)
+}
+
+/// Returns the bytes freed, refuses folders that aren't a capsule inside `capsule_dir`
+fn remove_capsule(
+    capsule: &StoredCapsule,
+    capsule_dir: &Path,
+    database: &Mutex<Database>,
+    report: &mut RetentionReport,
+) -> crate::Result<u64> {
+    // `..` would still start with `capsule_dir`
+    let inside = capsule.storage_path.strip_prefix(capsule_dir).is_ok_and(|path| {
+        path.components().count() > 0 && path.components().all(|part| matches!(part, Component::Normal(_)))
+    });
+    if !inside {
+        return Err(format!(
+            "Capsule {} is stored in {:?}, outside of {:?}",
+            capsule.id, capsule.storage_path, capsule_dir
+        )
+        .into());
+    }
+
+    let size = dir_size(&capsule.storage_path);
+    match fs::remove_dir_all(&capsule.storage_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(format!("Error removing {:?}: {}", capsule.storage_path, err).into()),
+    }
+    database.lock().unwrap().delete_capsule(&capsule.id)?;
+
+    report.removed_capsules += 1;
+    report.freed_bytes += size;
+    Ok(size)
+}
+
+/// Size of a file, or of everything inside a directory
+fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| dir_size(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        capture::{CaptureKind, CaptureOutcome, CaptureRecord},
+        session::StorageTimeCapsule,
+    };
+
+    const KB: usize = 1024;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    /// A capsule with a screenshot, a webcam clip and a screen recording of `media_size` bytes each
+    fn save_capsule(database: &Mutex<Database>, storage_path: &Path, days_ago: i64, media_size: usize) {
+        let started_at = (now() - chrono::Duration::days(days_ago)).to_rfc2822();
+        let id = storage_path.file_name().unwrap().to_string_lossy().to_string();
+
+        fs::create_dir_all(storage_path).unwrap();
+        for file in ["screenshot_1.png", "webcam_1.mp4", "screen_recording_1.mp4"] {
+            fs::write(storage_path.join(file), vec![0; media_size]).unwrap();
+        }
+        fs::write(storage_path.join("metadata.json"), "{}").unwrap();
+
+        let captures = [(CaptureKind::Screenshot, "screenshot_1.png"), (CaptureKind::Webcam, "webcam_1.mp4")]
+            .map(|(kind, file)| {
+                let mut record = CaptureRecord::new(kind, started_at.clone());
+                record.outcome = CaptureOutcome::Ok;
+                record.files = vec![file.into()];
+                record
+            });
+        let capsule = StorageTimeCapsule {
+            id,
+            session_id: "session".into(),
+            mouse_clicks: vec![],
+            keystrokes: vec![],
+            windows: vec![],
+            started_at,
+            ended_at: None,
+            captures: captures.into(),
+        };
+        database.lock().unwrap().save_capsule(&capsule, storage_path).unwrap();
+    }
+
+    fn media_kinds(database: &Mutex<Database>, capsule_id: &str) -> Vec<MediaKind> {
+        database.lock().unwrap().media(capsule_id).unwrap().into_iter().map(|media| media.kind).collect()
+    }
+
+    #[test]
+    fn media_and_capsules_expire_per_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Mutex::new(Database::open_in_memory().unwrap());
+        for (id, days_ago) in [("recent", 5), ("old", 15), ("older", 25), ("oldest", 35)] {
+            save_capsule(&database, &dir.path().join(id), days_ago, KB);
+        }
+        let retention = RetentionSettings {
+            screenshot_days: 10,
+            webcam_days: 20,
+            metadata_days: 30,
+            ..Default::default()
+        };
+
+        let report = enforce_retention(dir.path(), &retention, &database, now()).unwrap();
+
+        assert_eq!((report.removed_files, report.removed_capsules), (5, 1));
+        assert_eq!(
+            media_kinds(&database, "recent"),
+            [MediaKind::Screenshot, MediaKind::Webcam, MediaKind::ScreenRecording]
+        );
+        assert_eq!(media_kinds(&database, "old"), [MediaKind::Webcam]);
+        assert!(dir.path().join("old/webcam_1.mp4").exists());
+        assert!(!dir.path().join("old/screenshot_1.png").exists());
+        assert!(!dir.path().join("old/screen_recording_1.mp4").exists());
+        assert!(media_kinds(&database, "older").is_empty());
+        assert!(dir.path().join("older/metadata.json").exists());
+        assert!(!dir.path().join("older/webcam_1.mp4").exists());
+        assert!(!dir.path().join("oldest").exists());
+        assert!(!database.lock().unwrap().has_capsule("oldest").unwrap());
+    }
+
+    #[test]
+    fn quota_trims_the_oldest_capsules_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Mutex::new(Database::open_in_memory().unwrap());
+        for (id, days_ago) in [("newest", 1), ("oldest", 3), ("middle", 2)] {
+            save_capsule(&database, &dir.path().join(id), days_ago, 150 * KB);
+        }
+        let retention = RetentionSettings {
+            max_storage_in_mb: 1,
+            ..Default::default()
+        };
+
+        let report = enforce_retention(dir.path(), &retention, &database, now()).unwrap();
+
+        assert_eq!((report.removed_files, report.removed_capsules), (3, 0));
+        assert!(media_kinds(&database, "oldest").is_empty());
+        assert_eq!(media_kinds(&database, "middle").len(), 3);
+        assert_eq!(media_kinds(&database, "newest").len(), 3);
+        assert!(dir_size(dir.path()) <= MB);
+
+        // capsule folders go once no media is left to delete
+        fs::write(dir.path().join("oldest/metadata.json"), vec![0; 1100 * KB]).unwrap();
+        let report = enforce_retention(dir.path(), &retention, &database, now()).unwrap();
+
+        assert_eq!((report.removed_files, report.removed_capsules), (6, 1));
+        assert!(!dir.path().join("oldest").exists());
+        assert!(dir.path().join("middle").exists());
+        assert!(dir.path().join("newest").exists());
+    }
+
+    #[test]
+    fn nothing_is_deleted_without_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Mutex::new(Database::open_in_memory().unwrap());
+        save_capsule(&database, &dir.path().join("c1"), 1000, 100 * KB);
+
+        let report = enforce_retention(dir.path(), &RetentionSettings::default(), &database, now()).unwrap();
+
+        assert_eq!(report, RetentionReport::default());
+        assert_eq!(media_kinds(&database, "c1").len(), 3);
+        assert_eq!(fs::read_dir(dir.path().join("c1")).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn capsules_outside_the_capsule_dir_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let capsule_dir = dir.path().join("capsules");
+        let retention = RetentionSettings {
+            metadata_days: 1,
+            ..Default::default()
+        };
+
+        for storage_path in [dir.path().join("elsewhere/c1"), capsule_dir.join("../elsewhere/c2")] {
+            let database = Mutex::new(Database::open_in_memory().unwrap());
+            save_capsule(&database, &storage_path, 10, KB);
+
+            assert!(enforce_retention(&capsule_dir, &retention, &database, now()).is_err());
+            assert!(storage_path.join("metadata.json").exists());
+            assert_eq!(database.lock().unwrap().capsules_between(0, i64::MAX).unwrap().len(), 1);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::{collections::HashMap, sync::{Arc, Mutex}};
 
 use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
 
 use crate::{get_current_date, storage};
 
@@ -12,16 +13,21 @@ pub struct TrackHistory {
 
 impl Default for TrackHistory {
     fn default() -> Self {
-        if let Ok(this) = storage::load_from_path::<Self>(storage::data_path().join("tracker.json")) {
-            println!("Loaded auth from: {:?}", &this);
-            return this;
+        match storage::load_from_path::<Self>(storage::data_path().join("tracker.json")) {
+            Ok(this) => {
+                debug!("Loaded time tracker: {:?}", &this);
+                return this;
+            }
+            Err(err) => error!("Error loading time tracker: {:?}", err),
         }
 
         let this = Self {
             history: HashMap::new(),
         };
 
-        storage::save_to_path(&this, storage::data_path().join("tracker.json")).unwrap();
+        if let Err(err) = storage::save_to_path(&this, storage::data_path().join("tracker.json")) {
+            error!("Error saving time tracker: {:?}", err);
+        }
 
         this
     }
@@ -40,12 +46,14 @@ impl TrackHistory {
     }
 
     pub fn save(&self) {
-        dbg!(self);
-        storage::save_to_path(self, storage::data_path().join("tracker.json")).unwrap();
+        debug!(history = ?self.history, "Saving time tracker");
+        if let Err(err) = storage::save_to_path(self, storage::data_path().join("tracker.json")) {
+            error!("Error saving time tracker: {:?}", err);
+        }
     }
 
     pub fn clean_up(&mut self) {
-        dbg!(&self);
+        debug!(history = ?self.history, "Cleaning up time tracker");
         let today = get_current_date();
         self.history.retain(|k, _| k == &today);
         self.save();
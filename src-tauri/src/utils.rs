@@ -1,9 +1,10 @@
 use std::{
-    hash::{Hash, Hasher}, path::Path, time::{SystemTime, UNIX_EPOCH}
+    hash::{Hash, Hasher}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}
 };
 
 use chrono::{DateTime, Datelike, Timelike, Utc};
 use chrono_tz::Tz;
+use tauri::utils::platform;
 
 pub fn with_local_timezone(date_time: DateTime<Utc>) -> DateTime<Tz> {
     let tz_str = iana_time_zone::get_timezone().unwrap_or(chrono_tz::UTC.to_string());
@@ -50,3 +51,14 @@ pub fn gen_rand_number() -> u32 {
 pub fn path_exists<P: AsRef<Path>>(path: P) -> bool {
     std::fs::metadata(path).map_or(false, |_| true)
 }
+
+/// Resolve a sidecar binary that ships next to the app executable
+pub fn relative_command_path(command: impl AsRef<Path>) -> crate::Result<PathBuf> {
+    match platform::current_exe()?.parent() {
+        #[cfg(windows)]
+        Some(exe_dir) => Ok(exe_dir.join(command.as_ref()).with_extension("exe")),
+        #[cfg(not(windows))]
+        Some(exe_dir) => Ok(exe_dir.join(command.as_ref())),
+        None => Err("Error::CurrentExeHasNoParent".into()),
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{scheduler::MEDIA_CAPTURE_LAG, CaptureStrategy, Configuration, WebcamCaptureMode};
+
+/// Shortest capsule, it has to leave room for `MEDIA_CAPTURE_LAG` and a non zero window poll
+pub const MIN_TIME_GAP: u64 = 60;
+pub const MAX_TIME_GAP: u64 = 4 * 60 * 60;
+
+/// Rule a field broke
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ValidationRule {
+    Range { min: u64, max: u64 },
+    NotEmpty,
+    /// Must stay below the limit another field sets
+    Below { field: String, limit: u64 },
+    WritableDirectory,
+    ExistingFile,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Dotted path into `Configuration`, e.g. `preferences.camera.width`
+    pub field: String,
+    pub rule: ValidationRule,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Why preferences can't be applied
+#[derive(Debug, Clone)]
+pub enum ConfigError {
+    Invalid(Vec<FieldError>),
+    SaveFailed(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Invalid(errors) => {
+                let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "Invalid configuration: {}", errors.join(", "))
+            }
+            ConfigError::SaveFailed(err) => write!(f, "Error saving configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Collects every broken rule instead of stopping at the first
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn range(&mut self, field: &str, value: u64, min: u64, max: u64) -> &mut Self {
+        if value < min || value > max {
+            self.error(field, ValidationRule::Range { min, max }, format!("must be between {min} and {max}"));
+        }
+        self
+    }
+
+    /// `limit_field` names the field the limit comes from
+    pub fn below(&mut self, field: &str, value: u64, limit_field: &str, limit: u64) -> &mut Self {
+        if value >= limit {
+            self.error(
+                field,
+                ValidationRule::Below {
+                    field: limit_field.to_string(),
+                    limit,
+                },
+                format!("must be below {limit}, set by {limit_field}"),
+            );
+        }
+        self
+    }
+
+    pub fn not_empty<'a>(&mut self, field: &str, values: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        if values.into_iter().any(|value| value.trim().is_empty()) {
+            self.error(field, ValidationRule::NotEmpty, "must not be empty".to_string());
+        }
+        self
+    }
+
+    /// Checks the directory, or its closest existing parent, is a directory that isn't read only
+    ///
+    /// Nothing is created or written, the directory is made when first used
+    pub fn writable_dir(&mut self, field: &str, dir: &Path) -> &mut Self {
+        if dir.as_os_str().is_empty() {
+            self.error(field, ValidationRule::NotEmpty, "must not be empty".to_string());
+            return self;
+        }
+
+        let existing = dir.ancestors().find(|path| path.exists()).unwrap_or(dir);
+        let problem = match existing.metadata() {
+            Ok(metadata) if !metadata.is_dir() => Some(format!("{:?} is not a directory", existing)),
+            Ok(metadata) if metadata.permissions().readonly() => Some(format!("{:?} is read only", existing)),
+            Ok(_) => None,
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(problem) = problem {
+            self.error(field, ValidationRule::WritableDirectory, format!("{:?} is not writable: {}", dir, problem));
+        }
+        self
+    }
+
+    pub fn existing_file(&mut self, field: &str, path: &Path) -> &mut Self {
+        if !path.is_file() {
+            self.error(field, ValidationRule::ExistingFile, format!("{:?} does not exist", path));
+        }
+        self
+    }
+
+    fn error(&mut self, field: &str, rule: ValidationRule, message: String) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            rule,
+            message,
+        });
+    }
+
+    pub fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+impl Configuration {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut v = Validator::default();
+        let p = &self.preferences;
+        let time_gap = p.time_gap_duration_in_seconds;
+
+        v.writable_dir("capsule_storage_dir", &self.capsule_dir())
+            .writable_dir("media_storage_dir", &self.media_dir())
+            .not_empty("preferred_camera_id", self.preferred_camera_id.as_deref());
+        if let Some(ffmpeg_path) = &self.ffmpeg_path {
+            v.existing_file("ffmpeg_path", ffmpeg_path);
+        }
+
+        v.range("preferences.time_gap_duration_in_seconds", time_gap, MIN_TIME_GAP, MAX_TIME_GAP)
+            .range("preferences.webcam_delay", p.webcam_delay, 0, 30)
+            .range("preferences.screen_recording_fps", p.screen_recording_fps as u64, 1, 30)
+            .range("preferences.screenshot_count", p.screenshot_count as u64, 0, 20)
+            .range("preferences.webcam_shot_count", p.webcam_shot_count as u64, 0, 20)
+            .range("preferences.capture_retries", p.capture_retries as u64, 0, 5)
+            .range("preferences.veto_window_in_seconds", p.veto_window_in_seconds, 1, 60)
+            .below("preferences.veto_window_in_seconds", p.veto_window_in_seconds, "preferences.time_gap_duration_in_seconds", time_gap)
+            .range("preferences.max_vetoes_per_day", p.max_vetoes_per_day as u64, 0, 100)
+            .not_empty("preferences.capture_triggers.watched_apps", p.capture_triggers.watched_apps.iter().map(String::as_str))
+            .range("preferences.busy_camera_retry_delay_in_seconds", p.busy_camera_retry_delay_in_seconds, 1, 600)
+            .not_empty("preferences.camera_apps", p.camera_apps.iter().map(String::as_str));
+
+        if let CaptureStrategy::EvenlySpaced { jitter_in_seconds } = p.capture_strategy {
+            v.below("preferences.capture_strategy", jitter_in_seconds, "preferences.time_gap_duration_in_seconds", time_gap / 2);
+        }
+
+        let retention = &p.retention;
+        v.range("preferences.retention.max_storage_in_mb", retention.max_storage_in_mb, 0, 10 * 1024 * 1024)
+            .range("preferences.retention.screenshot_days", retention.screenshot_days as u64, 0, 3650)
+            .range("preferences.retention.webcam_days", retention.webcam_days as u64, 0, 3650)
+            .range("preferences.retention.metadata_days", retention.metadata_days as u64, 0, 3650)
+            .range("preferences.retention.min_free_disk_in_mb", retention.min_free_disk_in_mb, 0, 100 * 1024);
+
+        let camera = &p.camera;
+        v.range("preferences.camera.width", camera.width as u64, 160, 3840)
+            .range("preferences.camera.height", camera.height as u64, 120, 2160)
+            .range("preferences.camera.frame_rate", camera.frame_rate as u64, 1, 60)
+            .range("preferences.camera.warmup_frames", camera.warmup_frames as u64, 0, 120);
+        if let Some(output_width) = camera.output_width {
+            v.range("preferences.camera.output_width", output_width as u64, 64, camera.width as u64);
+        }
+
+        // the webcam delay and clip have to fit before the capsule closes
+        let capture_time = time_gap.saturating_sub(MEDIA_CAPTURE_LAG);
+        v.below("preferences.webcam_delay", p.webcam_delay, "preferences.time_gap_duration_in_seconds", capture_time);
+        if let WebcamCaptureMode::Clip { duration_in_seconds, width } = p.webcam_capture_mode {
+            v.range("preferences.webcam_capture_mode", duration_in_seconds as u64, 1, 30)
+                .range("preferences.webcam_capture_mode", width as u64, 64, camera.width as u64)
+                .below(
+                    "preferences.webcam_capture_mode",
+                    p.webcam_delay + duration_in_seconds as u64,
+                    "preferences.time_gap_duration_in_seconds",
+                    capture_time,
+                );
+        }
+
+        v.finish()
+    }
+
+    /// Storage folders set to an absolute path that doesn't exist, usually on a disk that isn't mounted
+    pub fn missing_storage_dirs(&self) -> Vec<PathBuf> {
+        [&self.capsule_storage_dir, &self.media_storage_dir]
+            .into_iter()
+            .filter(|dir| dir.is_absolute() && !dir.exists())
+            .cloned()
+            .collect()
+    }
+
+    /// Reset every invalid field to its default, used for configuration read from disk
+    ///
+    /// Absolute storage folders are kept even when unavailable, the disk holding
+    /// them may just not be mounted yet, `missing_storage_dirs` reports them.
+    /// Falls back to the defaults entirely when that still doesn't validate
+    pub fn repaired(self) -> Self {
+        let kept = |err: &FieldError| {
+            err.rule == ValidationRule::WritableDirectory
+                && match err.field.as_str() {
+                    "capsule_storage_dir" => self.capsule_storage_dir.is_absolute(),
+                    "media_storage_dir" => self.media_storage_dir.is_absolute(),
+                    _ => false,
+                }
+        };
+        let (kept_errors, errors): (Vec<FieldError>, Vec<FieldError>) =
+            self.validate().err().unwrap_or_default().into_iter().partition(kept);
+        for err in &kept_errors {
+            error!("Keeping unavailable storage folder {}", err);
+        }
+        if errors.is_empty() {
+            return self;
+        }
+        for err in &errors {
+            warn!("Resetting invalid configuration field {}", err);
+        }
+
+        let repaired = serde_json::to_value(&self)
+            .and_then(|mut config| {
+                let defaults = serde_json::to_value(Self::initial())?;
+                for err in &errors {
+                    let pointer = format!("/{}", err.field.replace('.', "/"));
+                    if let (Some(field), Some(default)) = (config.pointer_mut(&pointer), defaults.pointer(&pointer)) {
+                        *field = default.clone();
+                    }
+                }
+                serde_json::from_value::<Self>(config)
+            })
+            .ok()
+            .filter(|config| config.validate().err().unwrap_or_default().iter().all(kept));
+
+        repaired.unwrap_or_else(|| {
+            error!("Configuration still invalid, using the defaults");
+            Self::initial()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writable_dir_leaves_the_disk_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("capsules").join("nested");
+
+        let mut v = Validator::default();
+        v.writable_dir("capsule_storage_dir", &missing);
+
+        assert!(v.finish().is_ok());
+        assert!(!dir.path().join("capsules").exists());
+    }
+
+    #[test]
+    fn writable_dir_rejects_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+
+        let mut v = Validator::default();
+        v.writable_dir("capsule_storage_dir", &file.join("capsules"))
+            .writable_dir("media_storage_dir", Path::new(""));
+
+        let rules: Vec<_> = v.finish().unwrap_err().into_iter().map(|err| err.rule).collect();
+        assert_eq!(rules, [ValidationRule::WritableDirectory, ValidationRule::NotEmpty]);
+    }
+}
@@ -5,7 +5,8 @@ pub enum AppWindow {
     Track,
     TimeCard,
     Settings,
-    Permissions
+    Permissions,
+    CaptureNotice,
 }
 
 impl AppWindow {
@@ -16,6 +17,7 @@ impl AppWindow {
             AppWindow::TimeCard => "time-card".to_string(),
             AppWindow::Settings => "settings".to_string(),
             AppWindow::Permissions => "permissions".to_string(),
+            AppWindow::CaptureNotice => "capture-notice".to_string(),
         }
     }
 
@@ -26,6 +28,7 @@ impl AppWindow {
             AppWindow::TimeCard => "time card".to_string(),
             AppWindow::Settings => "settings".to_string(),
             AppWindow::Permissions => "permissions".to_string(),
+            AppWindow::CaptureNotice => "capture notice".to_string(),
         }
     }
 
@@ -36,6 +39,7 @@ impl AppWindow {
             "time-card" => AppWindow::TimeCard,
             "settings" => AppWindow::Settings,
             "permissions" => AppWindow::Permissions,
+            "capture-notice" => AppWindow::CaptureNotice,
             _ => unreachable!("unknown window: {label}")
         }
     }
@@ -162,6 +166,35 @@ impl AppWindow {
                     .theme(Some(tauri::Theme::Dark))
                     .build()?
             },
+            AppWindow::CaptureNotice => {
+                let window = tauri::WindowBuilder::new(app, label, tauri::WindowUrl::App("/capture-notice".into()))
+                    .title(self.title())
+                    .hidden_title(true)
+                    .maximizable(false)
+                    .minimizable(false)
+                    .maximized(false)
+                    .resizable(false)
+                    .transparent(true)
+                    .decorations(false)
+                    .always_on_top(true)
+                    .focused(false)
+                    .inner_size(503.0, 233.0)
+                    .title_bar_style(tauri::TitleBarStyle::Overlay)
+                    .theme(Some(tauri::Theme::Dark))
+                    .build()?;
+
+                if let Some(monitor) = window.current_monitor()? {
+                    window.set_position(
+                        // top right position
+                        PhysicalPosition {
+                                x: (monitor.size().width as f64) - 503.0 * monitor.scale_factor(), // right
+                                y: 100.0 // top
+                        }
+                    )?;
+                }
+
+                window
+            },
         })
     }
 
@@ -214,6 +247,14 @@ pub fn close_permission(app: &AppHandle) {
     (AppWindow::Permissions).close(app);
 }
 
+pub fn show_capture_notice(app: &AppHandle) {
+    (AppWindow::CaptureNotice).show(app).ok();
+}
+
+pub fn close_capture_notice(app: &AppHandle) {
+    (AppWindow::CaptureNotice).close(app);
+}
+
 // pub fn show_window(app: &AppHandle, name: String) -> crate::Result<()> {
 //     if let Some(window) = app.get_window(&name) {
 //         window.show().unwrap();
@@ -0,0 +1,26 @@
+//! Repairs configuration against a temporary data directory, in its own process since the data directory is global
+
+use std::fs;
+
+use worksmart::{storage, Configuration};
+
+#[test]
+fn repair_keeps_unavailable_storage_folders() {
+    let root = tempfile::tempdir().unwrap();
+    storage::init_data_dir(["--data-dir".to_string(), root.path().join("data").to_string_lossy().to_string()]);
+    // a file where the disk would be mounted makes the folder unusable
+    let unmounted = root.path().join("unmounted");
+    fs::write(&unmounted, b"").unwrap();
+
+    let mut config = Configuration::initial();
+    config.capsule_storage_dir = unmounted.join("capsules");
+    config.preferences.time_gap_duration_in_seconds = 1;
+    let repaired = config.repaired();
+
+    assert_eq!(repaired.capsule_storage_dir, unmounted.join("capsules"));
+    assert_eq!(
+        repaired.preferences.time_gap_duration_in_seconds,
+        Configuration::initial().preferences.time_gap_duration_in_seconds
+    );
+    assert_eq!(repaired.missing_storage_dirs(), [unmounted.join("capsules")]);
+}
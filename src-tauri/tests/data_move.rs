@@ -0,0 +1,75 @@
+//! Moves data around a temporary data directory, in its own process since the data directory is global
+
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use worksmart::{data_move, session::StorageTimeCapsule, storage, Configuration, DataMove, Database};
+
+#[test]
+fn failed_moves_can_be_abandoned_and_redone() {
+    let root = tempfile::tempdir().unwrap();
+    let data = root.path().join("data");
+    storage::init_data_dir(["--data-dir".to_string(), data.to_string_lossy().to_string()]);
+
+    let config = Arc::new(Mutex::new(Configuration::initial()));
+    let (capsule_dir, media_dir) = {
+        let config = config.lock().unwrap();
+        (config.capsule_dir(), config.media_dir())
+    };
+    for id in ["c1", "c2"] {
+        fs::create_dir_all(capsule_dir.join(id)).unwrap();
+        fs::write(capsule_dir.join(id).join("screenshot_1.png"), id).unwrap();
+    }
+    fs::create_dir_all(&media_dir).unwrap();
+    fs::write(media_dir.join("preview.png"), b"preview").unwrap();
+    let mut database = Database::open_in_memory().unwrap();
+    let capsule = StorageTimeCapsule {
+        id: "c1".into(),
+        session_id: "session".into(),
+        mouse_clicks: vec![],
+        keystrokes: vec![],
+        windows: vec![],
+        started_at: "Mon, 1 Jan 2024 10:00:00 +0000".into(),
+        ended_at: None,
+        captures: vec![],
+    };
+    database.save_capsule(&capsule, &capsule_dir.join("c1")).unwrap();
+    let database = Arc::new(Mutex::new(database));
+
+    // the media entry can't be moved once its new folder is replaced by a file
+    let broken = root.path().join("broken");
+    let data_move = DataMove::plan(capsule_dir.clone(), media_dir.clone(), &broken).unwrap();
+    fs::remove_dir(broken.join("media")).unwrap();
+    fs::write(broken.join("media"), b"").unwrap();
+    assert!(data_move.run(&config, &database, |_| {}).is_err());
+
+    let failed = DataMove::unfinished().unwrap();
+    assert!(data_move::in_progress());
+    assert!(failed.error.is_some());
+    assert_eq!((failed.moved.len(), failed.pending.len()), (2, 1));
+    assert!(broken.join("capsules").join("c1").exists());
+
+    failed.abandon().unwrap().run(&config, &database, |_| {}).unwrap();
+    assert!(!data_move::in_progress());
+    assert!(capsule_dir.join("c1").join("screenshot_1.png").exists());
+    assert!(capsule_dir.join("c2").join("screenshot_1.png").exists());
+    assert!(media_dir.join("preview.png").exists());
+    assert!(!broken.join("capsules").exists());
+    assert_eq!(config.lock().unwrap().capsule_dir(), capsule_dir);
+
+    let target = root.path().join("elsewhere");
+    let data_move = DataMove::plan(capsule_dir.clone(), media_dir.clone(), &target).unwrap();
+    let mut progress = vec![];
+    data_move.run(&config, &database, |update| progress.push(update)).unwrap();
+
+    assert!(!data_move::in_progress());
+    assert!(progress.last().unwrap().done);
+    assert!(target.join("capsules").join("c2").join("screenshot_1.png").exists());
+    assert!(target.join("media").join("preview.png").exists());
+    assert!(!capsule_dir.exists());
+    assert_eq!(config.lock().unwrap().capsule_dir(), target.join("capsules"));
+    let stored = database.lock().unwrap().capsules_between(0, i64::MAX).unwrap();
+    assert_eq!(stored[0].storage_path, target.join("capsules").join("c1"));
+}
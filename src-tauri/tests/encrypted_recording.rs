@@ -0,0 +1,54 @@
+//! Records webcam and screen media through ffmpeg with encryption on, `FFMPEG` picks an ffmpeg
+//! other than the one on PATH: `cargo test --test encrypted_recording -- --ignored`
+//!
+//! In its own process since the data directory and the encryption settings are global
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use worksmart::{
+    encryption, storage, CameraSettings, EncryptionSettings, FfmpegBackend, FfmpegInput, KeySource, RecordCommand,
+    Recorder, ScreenInput, SyntheticFrameSource,
+};
+
+#[tokio::test]
+#[ignore = "needs ffmpeg"]
+async fn ffmpeg_output_is_encrypted_at_rest() {
+    let ffmpeg = std::env::var_os("FFMPEG").map_or_else(|| "ffmpeg".into(), PathBuf::from);
+    let data_dir = tempfile::tempdir().unwrap();
+    storage::init_data_dir(["--data-dir".to_string(), data_dir.path().to_string_lossy().to_string()]);
+    let capsule = data_dir.path().join("capsules").join("Date_2024_1_1_10_0");
+    fs::create_dir_all(&capsule).unwrap();
+    encryption::configure(&EncryptionSettings {
+        enabled: true,
+        key_source: KeySource::KeyFile,
+    })
+    .unwrap();
+
+    let backend = FfmpegBackend::new(ffmpeg.clone());
+    let settings = CameraSettings::default();
+    backend.still(&FfmpegInput::TestSource, &capsule.join("portrait.png"), &settings).await.unwrap();
+    backend.clip(&FfmpegInput::TestSource, &capsule.join("portrait_1.mp4"), &settings, 1, 320).await.unwrap();
+    backend.screenshot(&ScreenInput::TestSource, &capsule.join("screenshot.png")).await.unwrap();
+
+    let (commands, receiver) = tokio::sync::mpsc::channel(4);
+    let recorder = Recorder::new(Box::new(SyntheticFrameSource::new(320, 240)), ffmpeg).spawn(receiver);
+    commands
+        .send(RecordCommand::Start {
+            output: capsule.clone(),
+            fps: 5,
+        })
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    drop(commands);
+    recorder.join().unwrap();
+
+    let files: Vec<PathBuf> = fs::read_dir(&capsule).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert!(files.iter().any(|file| file.to_string_lossy().contains("screen_recording_")));
+    for file in &files {
+        let data = fs::read(file).unwrap();
+        assert!(encryption::is_encrypted(&data), "{:?} is not encrypted", file);
+        assert!(!data.windows(4).any(|window| window == b"ftyp" || window == b"\x89PNG"), "{:?} leaks media", file);
+    }
+    assert!(encryption::load_image(&capsule.join("portrait.png")).is_ok());
+}
@@ -0,0 +1,106 @@
+//! Writes capsule files with encryption on and checks nothing readable reaches the disk
+//!
+//! In its own process since the data directory and the encryption settings are global
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use image::{DynamicImage, RgbImage};
+use worksmart::{
+    capture::{CaptureKind, CaptureRecord},
+    encryption,
+    session::StorageTimeCapsule,
+    storage, CaptureHealthReport, Database, EncryptionSettings, KeySource,
+};
+
+/// Shows up in plaintext metadata, must never be found on disk
+const MARKER: &str = "plaintext-marker-window-title";
+
+#[test]
+fn capsule_files_are_encrypted_at_rest() {
+    let data_dir = tempfile::tempdir().unwrap();
+    storage::init_data_dir(["--data-dir".to_string(), data_dir.path().to_string_lossy().to_string()]);
+    let capsule_dir = data_dir.path().join("capsules");
+    let capsule = capsule_dir.join("Date_2024_1_1_10_0");
+    fs::create_dir_all(&capsule).unwrap();
+
+    encryption::configure(&EncryptionSettings {
+        enabled: true,
+        key_source: KeySource::KeyFile,
+    })
+    .unwrap();
+    assert!(encryption::unlock("correct horse").is_err());
+
+    let mut record = CaptureRecord::new(CaptureKind::Screenshot, "2024-01-01T10:00:30+00:00".into());
+    record.files = vec!["screenshot.png".into()];
+    let metadata = StorageTimeCapsule {
+        id: "Date_2024_1_1_10_0".into(),
+        session_id: MARKER.into(),
+        mouse_clicks: vec![],
+        keystrokes: vec![],
+        windows: vec![],
+        started_at: "Mon, 1 Jan 2024 10:00:00 +0000".into(),
+        ended_at: Some("Mon, 1 Jan 2024 10:05:00 +0000".into()),
+        captures: vec![record],
+    };
+    storage::save_capsule_json(&metadata, &capsule.join("metadata.json")).unwrap();
+    // saved twice so the `.bak` copy is checked as well
+    storage::save_capsule_json(&metadata, &capsule.join("metadata.json")).unwrap();
+
+    let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8, y as u8, 128])));
+    encryption::save_image(&image, &capsule.join("screenshot.png")).unwrap();
+    encryption::save_image(&image, &capsule.join("portrait.png")).unwrap();
+
+    let mut files = vec![];
+    list_files(&capsule_dir, &mut files);
+    assert_eq!(files.len(), 4);
+    for file in &files {
+        let data = fs::read(file).unwrap();
+        assert!(encryption::is_encrypted(&data), "{:?} is not encrypted", file);
+        let leaks: [&[u8]; 3] = [MARKER.as_bytes(), b"\x89PNG", b"started_at"];
+        for plaintext in leaks {
+            assert!(!contains(&data, plaintext), "{:?} leaks {:?}", file, String::from_utf8_lossy(plaintext));
+        }
+    }
+    assert!(encryption::has_encrypted_capsules(&capsule_dir).unwrap());
+
+    let loaded: StorageTimeCapsule = storage::load_capsule_json(&capsule.join("metadata.json")).unwrap();
+    assert_eq!(loaded.session_id, MARKER);
+    assert_eq!(encryption::load_image(&capsule.join("screenshot.png")).unwrap().to_rgb8(), image.to_rgb8());
+
+    let mut database = Database::open_in_memory().unwrap();
+    let report = database.import_capsule_dirs(&capsule_dir).unwrap();
+    assert_eq!(report.imported, 1);
+    let date = "2024-01-01".parse().unwrap();
+    assert_eq!(CaptureHealthReport::collect(&database, date, date).unwrap().capsules, 1);
+
+    // a passphrase key is locked after launch, captures are refused rather than written in the clear
+    let passphrase = EncryptionSettings {
+        enabled: true,
+        key_source: KeySource::Passphrase,
+    };
+    encryption::configure(&passphrase).unwrap();
+    assert!(encryption::writer_key().is_err());
+    assert!(storage::save_capsule_json(&metadata, &capsule.join("metadata.json")).is_err());
+    encryption::unlock("correct horse").unwrap();
+    encryption::configure(&passphrase).unwrap();
+    assert!(encryption::unlock("battery staple").is_err());
+    assert!(encryption::writer_key().unwrap().is_some());
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            list_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
@@ -0,0 +1,144 @@
+//! Validates configuration against a temporary data directory, in its own process since the data directory is global
+
+use std::sync::OnceLock;
+
+use serde_json::json;
+use tempfile::TempDir;
+use worksmart::{
+    scheduler::MEDIA_CAPTURE_LAG,
+    storage,
+    validation::{MAX_TIME_GAP, MIN_TIME_GAP},
+    ConfigError, Configuration, FieldError, ValidationRule, WebcamCaptureMode, WorksmartError,
+};
+
+/// Defaults with the data directory moved to a temporary one, shared by every test here
+fn config() -> Configuration {
+    static DATA_DIR: OnceLock<TempDir> = OnceLock::new();
+    let dir = DATA_DIR.get_or_init(|| tempfile::tempdir().unwrap());
+    storage::init_data_dir([storage::DATA_DIR_ARG.to_string(), dir.path().to_string_lossy().to_string()]);
+
+    Configuration::initial()
+}
+
+fn errors(config: &Configuration) -> Vec<FieldError> {
+    config.validate().err().unwrap_or_default()
+}
+
+fn fields(config: &Configuration) -> Vec<String> {
+    errors(config).into_iter().map(|err| err.field).collect()
+}
+
+#[test]
+fn defaults_are_valid() {
+    assert_eq!(errors(&config()), []);
+}
+
+#[test]
+fn time_gap_has_a_minimum() {
+    let mut config = config();
+    config.preferences.time_gap_duration_in_seconds = MIN_TIME_GAP - 1;
+
+    let errors = errors(&config);
+
+    let time_gap = errors.iter().find(|err| err.field == "preferences.time_gap_duration_in_seconds");
+    assert_eq!(
+        time_gap.map(|err| &err.rule),
+        Some(&ValidationRule::Range {
+            min: MIN_TIME_GAP,
+            max: MAX_TIME_GAP,
+        })
+    );
+
+    config.preferences.time_gap_duration_in_seconds = MIN_TIME_GAP;
+    assert_eq!(fields(&config), Vec::<String>::new());
+}
+
+#[test]
+fn veto_window_stays_below_the_time_gap() {
+    let mut config = config();
+    config.preferences.time_gap_duration_in_seconds = MIN_TIME_GAP;
+    config.preferences.veto_window_in_seconds = MIN_TIME_GAP;
+
+    let errors = errors(&config);
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field, "preferences.veto_window_in_seconds");
+    assert_eq!(
+        errors[0].rule,
+        ValidationRule::Below {
+            field: "preferences.time_gap_duration_in_seconds".into(),
+            limit: MIN_TIME_GAP,
+        }
+    );
+}
+
+#[test]
+fn webcam_delay_and_clip_fit_before_the_capsule_closes() {
+    let mut config = config();
+    let time_gap = MIN_TIME_GAP;
+    let capture_time = time_gap - MEDIA_CAPTURE_LAG;
+    config.preferences.time_gap_duration_in_seconds = time_gap;
+    config.preferences.webcam_delay = 10;
+    config.preferences.webcam_capture_mode = WebcamCaptureMode::Clip {
+        duration_in_seconds: (capture_time - 10 - 1) as u32,
+        width: 320,
+    };
+    assert_eq!(fields(&config), Vec::<String>::new());
+
+    config.preferences.webcam_capture_mode = WebcamCaptureMode::Clip {
+        duration_in_seconds: (capture_time - 10) as u32,
+        width: 320,
+    };
+    let errors = errors(&config);
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field, "preferences.webcam_capture_mode");
+    assert_eq!(
+        errors[0].rule,
+        ValidationRule::Below {
+            field: "preferences.time_gap_duration_in_seconds".into(),
+            limit: capture_time,
+        }
+    );
+}
+
+#[test]
+fn repair_resets_only_the_invalid_fields() {
+    let mut config = config();
+    config.preferences.time_gap_duration_in_seconds = 300;
+    config.preferences.screenshot_count = 7;
+    config.preferences.webcam_delay = 99;
+
+    let repaired = config.repaired();
+
+    assert_eq!(repaired.preferences.webcam_delay, Configuration::initial().preferences.webcam_delay);
+    assert_eq!(repaired.preferences.time_gap_duration_in_seconds, 300);
+    assert_eq!(repaired.preferences.screenshot_count, 7);
+}
+
+#[test]
+fn rejected_preferences_list_every_field() {
+    let mut config = config();
+    config.preferences.screenshot_count = 50;
+    config.preferences.camera_apps.push(" ".into());
+
+    let err: WorksmartError = ConfigError::Invalid(errors(&config)).into();
+    let value = serde_json::to_value(&err).unwrap();
+
+    assert_eq!(value["code"], "config_invalid");
+    assert_eq!(
+        value["details"]["fields"],
+        json!([
+            {
+                "field": "preferences.screenshot_count",
+                "rule": { "Range": { "min": 0, "max": 20 } },
+                "message": "must be between 0 and 20",
+            },
+            {
+                "field": "preferences.camera_apps",
+                "rule": "NotEmpty",
+                "message": "must not be empty",
+            },
+        ])
+    );
+}
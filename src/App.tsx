@@ -2,7 +2,6 @@
 // // import reactLogo from "./assets/react.svg";
 import "./App.css";
 // import {
-//     record_screen,
 //     start_session,
 //     stop_session,
 //     update_config,
@@ -41,7 +40,6 @@ function App() {
                     <>
                         <button onClick={start_session}>Start session</button>
                         <button onClick={stop_session}>Stop session</button>
-                        <button onClick={record_screen}>Record Screen</button>
                         <button onClick={update_config}>Update config</button>
                         <button onClick={webcam_capture}>
                             Take Webcam shot
@@ -0,0 +1,118 @@
+import { useEffect, useRef, useState } from "react";
+import { listen } from "@tauri-apps/api/event";
+import "./styles/CaptureNotice.css";
+import { CaptureNoticePayload, WorksmartError } from "./types";
+import {
+  get_capture_preview,
+  get_pending_captures,
+  hide_window,
+  veto_capture,
+} from "./ipc";
+
+const CaptureNotice = () => {
+  const [notice, setNotice] = useState<CaptureNoticePayload>();
+  const [preview, setPreview] = useState<string>();
+  const [secondsLeft, setSecondsLeft] = useState(0);
+  const [error, setError] = useState<string>();
+  const eventRefs = useRef<Function[]>([]);
+
+  const showNotice = (payload: CaptureNoticePayload) => {
+    setNotice(payload);
+    setSecondsLeft(payload.veto_window_in_seconds);
+    setError(undefined);
+  };
+
+  const listenForEvents = async () => {
+    eventRefs.current.push(
+      await listen<CaptureNoticePayload>("CaptureTaken", (event) =>
+        showNotice(event.payload),
+      ),
+    );
+    eventRefs.current.push(
+      await listen<string>("CaptureNoticeExpired", (event) => {
+        setNotice((current) =>
+          current?.id === event.payload ? undefined : current,
+        );
+      }),
+    );
+  };
+
+  useEffect(() => {
+    // the window may open after the first notice was emitted
+    get_pending_captures().then((pending) => pending[0] && showNotice(pending[0]));
+    listenForEvents();
+    return () => eventRefs.current.forEach((unlisten) => unlisten());
+  }, []);
+
+  useEffect(() => {
+    setPreview(undefined);
+    if (!notice?.preview) return;
+    let current = true;
+    get_capture_preview(notice.preview)
+      .then((preview) => current && setPreview(preview))
+      .catch((err) => console.warn("Capture preview failed", err));
+    return () => {
+      current = false;
+    };
+  }, [notice?.id]);
+
+  useEffect(() => {
+    if (secondsLeft <= 0) return;
+    const timeout = setTimeout(() => setSecondsLeft(secondsLeft - 1), 1000);
+    return () => clearTimeout(timeout);
+  }, [secondsLeft]);
+
+  const onDiscard = async () => {
+    if (!notice) return;
+    try {
+      await veto_capture(notice.id);
+      setNotice(undefined);
+      await hide_window("capture-notice");
+    } catch (err) {
+      setError((err as WorksmartError).message);
+    }
+  };
+
+  const onKeep = async () => {
+    setNotice(undefined);
+    await hide_window("capture-notice");
+  };
+
+  if (!notice) return null;
+
+  return (
+    <div data-tauri-drag-region className="capture-notice">
+      {preview &&
+        (notice.preview?.endsWith(".mp4") ? (
+          <video src={preview} autoPlay muted loop />
+        ) : (
+          <img src={preview} alt="Capture preview" />
+        ))}
+      <div className="details" data-tauri-drag-region>
+        <p>
+          {notice.kind === "Webcam"
+            ? notice.preview?.endsWith(".mp4")
+              ? "Webcam clip"
+              : "Webcam picture"
+            : "Screenshot"}{" "}
+          taken
+        </p>
+        <p>
+          Discard within {secondsLeft}s ({notice.vetoes_left} left today)
+        </p>
+        {error && <p className="error">{error}</p>}
+        <div className="d-flex gap-2">
+          <button
+            onClick={onDiscard}
+            disabled={secondsLeft <= 0 || notice.vetoes_left === 0}
+          >
+            Discard
+          </button>
+          <button onClick={onKeep}>Keep</button>
+        </div>
+      </div>
+    </div>
+  );
+};
+
+export default CaptureNotice;
@@ -4,27 +4,74 @@ import {
   useEffect,
   useState,
 } from "react";
+import { listen } from "@tauri-apps/api/event";
 import {
+  abandon_data_move,
+  enforce_retention,
+  export_diagnostics,
+  get_data_move,
+  get_encryption_status,
   get_preferences,
+  get_selected_camera_device,
+  get_storage_usage,
   hide_window,
   list_camera_devices,
+  move_data_directory,
+  retry_data_move,
   select_camera_device,
   set_preferences,
+  unlock_encryption,
   webcam_capture,
 } from "./ipc";
 import "./styles/Settings.css"; // Assuming styles are moved to a separate CSS file named Settings.css
-import { Configuration } from "./types";
+import {
+  CameraDevice,
+  CameraDevicesChangedPayload,
+  Configuration,
+  DataMove,
+  DataMoveProgress,
+  DayUsage,
+  EncryptionStatus,
+  FieldError,
+  KeySource,
+  RetentionSettings,
+  StorageUsage,
+  WorksmartError,
+} from "./types";
+
+const toMb = (bytes: number) => `${Math.round(bytes / (1024 * 1024))} MB`;
+
+// retention is off until the user sets a limit, 0 keeps everything
+const retentionFields: [keyof RetentionSettings, string][] = [
+  ["screenshot_days", "Keep screenshots and recordings (days)"],
+  ["webcam_days", "Keep webcam images (days)"],
+  ["metadata_days", "Keep capsules (days)"],
+  ["max_storage_in_mb", "Limit capsules to (MB)"],
+  ["min_free_disk_in_mb", "Pause captures below free disk (MB)"],
+];
 
-let mockDevices = ["FaceTime HD Camera", "Logitech Webcam", "External Camera"];
+const dayTotal = (day: DayUsage) =>
+  day.screenshots +
+  day.webcam +
+  day.screen_recordings +
+  day.metadata +
+  day.other;
 
 const Settings = () => {
   const [preferences, setPreferences] = useState<Configuration>();
-  const [cameraDevices, setCameraDevices] = useState<string[]>(
-    () => mockDevices,
-  );
-  const [selectedDevice, setSelectedDevices] = useState<string>(
-    () => mockDevices[0],
-  );
+  const [cameraDevices, setCameraDevices] = useState<CameraDevice[]>([]);
+  const [selectedDevice, setSelectedDevices] = useState<string>("");
+  const [fieldErrors, setFieldErrors] = useState<FieldError[]>([]);
+  const [saveError, setSaveError] = useState<string>();
+  const [diagnostics, setDiagnostics] = useState<string>();
+  const [dataDir, setDataDir] = useState<string>("");
+  const [dataMove, setDataMove] = useState<string>();
+  const [failedMove, setFailedMove] = useState<DataMove>();
+  const [storageUsage, setStorageUsage] = useState<StorageUsage>();
+  const [retention, setRetention] = useState<string>();
+  const [encryption, setEncryption] = useState<EncryptionStatus>();
+  const [passphrase, setPassphrase] = useState<string>("");
+  const [unlockError, setUnlockError] = useState<string>();
   const [preview, setPreview] = useState<string>(
     "https://placehold.co/150x175@3x/FFFFFF/png",
   );
@@ -33,20 +80,110 @@ const Settings = () => {
     setPreferences(await get_preferences());
   };
 
+  const getFailedMove = async () => {
+    const unfinished = await get_data_move();
+    setFailedMove(unfinished?.error ? unfinished : undefined);
+  };
+
+  const getStorageUsage = async () => {
+    get_storage_usage()
+      .then(setStorageUsage)
+      .catch((err) => console.warn("Storage usage failed", err));
+  };
+
   const getDevices = async () => {
     let devices = await list_camera_devices();
     console.log("devices", devices);
     setCameraDevices(devices);
-    setSelectedDevices(devices[0]);
+    get_selected_camera_device()
+      .then((device) => setSelectedDevices(device.id))
+      .catch(() => setSelectedDevices(""));
   };
 
   useEffect(() => {
     getPreferences();
     getDevices();
+    getStorageUsage();
+    getFailedMove();
+    get_encryption_status().then(setEncryption);
+
+    // cameras can be plugged in or out while settings are open
+    const unlisten = listen<CameraDevicesChangedPayload>(
+      "CameraDevicesChanged",
+      (event) => {
+        setCameraDevices(event.payload.devices);
+        setSelectedDevices(event.payload.selected ?? "");
+      },
+    );
+    const unlistenDataMove = listen<DataMoveProgress>(
+      "DataMoveProgress",
+      ({ payload }) => {
+        setDataMove(
+          payload.done
+            ? `Moved ${payload.total} items`
+            : `Moving ${payload.moved + 1} of ${payload.total}`,
+        );
+        if (payload.done) getPreferences();
+      },
+    );
+    return () => {
+      unlisten.then((unlisten) => unlisten());
+      unlistenDataMove.then((unlisten) => unlisten());
+    };
   }, []);
 
   console.log("Preferences", preferences);
 
+  const onMoveData = async () => {
+    try {
+      await move_data_directory(dataDir);
+    } catch (err) {
+      setDataMove((err as WorksmartError).message);
+    }
+    getFailedMove();
+  };
+
+  const onFinishFailedMove = async (abandon: boolean) => {
+    try {
+      await (abandon ? abandon_data_move() : retry_data_move());
+      setDataMove(undefined);
+    } catch (err) {
+      setDataMove((err as WorksmartError).message);
+    }
+    getFailedMove();
+  };
+
+  const onEnforceRetention = async () => {
+    try {
+      const report = await enforce_retention();
+      setRetention(
+        `Removed ${report.removed_files} files and ${report.removed_capsules} capsules, ${toMb(report.freed_bytes)} freed`,
+      );
+      getStorageUsage();
+    } catch (err) {
+      setRetention((err as WorksmartError).message);
+    }
+  };
+
+  const onUnlockEncryption = async () => {
+    try {
+      setEncryption(await unlock_encryption(passphrase));
+      setPassphrase("");
+      setUnlockError(undefined);
+    } catch (err) {
+      setUnlockError((err as WorksmartError).message);
+    }
+  };
+
+  const onExportDiagnostics = async () => {
+    try {
+      const exported = await export_diagnostics();
+      setDiagnostics(`Diagnostics saved to ${exported.path}`);
+    } catch (err) {
+      setDiagnostics((err as WorksmartError).message);
+    }
+  };
+
   const onSubmit: FormEventHandler = async (evt) => {
     evt.preventDefault();
     let form = evt?.target as HTMLFormElement;
@@ -58,13 +195,26 @@ const Settings = () => {
       track_on_signin: form["trackOnSignin"].checked,
       enable_camera: form["enableCamera"].checked,
       preferences: {
+        ...preferences?.preferences,
         webcam_delay: parseInt(form["webcamDelay"].value),
         time_gap_duration_in_seconds:
           preferences?.preferences.time_gap_duration_in_seconds,
       },
     } as Configuration;
-    await set_preferences(config);
-    setPreferences(config);
+    try {
+      await set_preferences(config);
+      setFieldErrors([]);
+      setSaveError(undefined);
+      setPreferences(config);
+      setEncryption(await get_encryption_status());
+    } catch (err) {
+      const error = err as WorksmartError;
+      if (error.code === "config_invalid") {
+        setFieldErrors(error.details?.fields ?? []);
+      } else {
+        setSaveError(error.message);
+      }
+    }
   };
 
   const onDeviceSelectionChange: ChangeEventHandler<HTMLSelectElement> = async (
@@ -72,12 +222,19 @@ const Settings = () => {
   ) => {
     evt.preventDefault();
     console.log("Selection changed", evt.target.value);
-    select_camera_device(evt.target.value);
+    setSelectedDevices(evt.target.value);
+    await select_camera_device(evt.target.value);
+    preferences &&
+      setPreferences({ ...preferences, preferred_camera_id: evt.target.value });
   };
 
   const onCameraTest = async () => {
-    const preview = await webcam_capture();
-    setPreview(`data:image/png;base64,${preview}`);
+    try {
+      const preview = await webcam_capture();
+      setPreview(`data:image/png;base64,${preview}`);
+    } catch (err) {
+      console.warn("Camera test failed", err as WorksmartError);
+    }
   };
 
   return (
@@ -186,11 +343,13 @@ const Settings = () => {
               <select
                 className="form-select"
                 id="cameraDriver"
-                defaultValue={selectedDevice}
+                value={selectedDevice}
                 onChange={onDeviceSelectionChange}
               >
                 {cameraDevices.map((device) => (
-                  <option key={device}>{device}</option>
+                  <option key={device.id} value={device.id}>
+                    {device.name}
+                  </option>
                 ))}
               </select>
             </div>
@@ -236,7 +395,201 @@ const Settings = () => {
             </button>
           </div>
         </div>
+        <div className="mb-4">
+          <button
+            type="button"
+            className="btn btn-outline-light"
+            onClick={onExportDiagnostics}
+          >
+            Export Diagnostics
+          </button>
+          {diagnostics && <p className="small mt-2">{diagnostics}</p>}
+        </div>
+        <div className="mb-4">
+          <label htmlFor="dataDir" className="form-label">
+            Move capsules and media to
+          </label>
+          <div className="d-flex">
+            <input
+              id="dataDir"
+              type="text"
+              className="form-control me-2"
+              placeholder={preferences?.capsule_storage_dir}
+              value={dataDir}
+              onChange={(evt) => setDataDir(evt.target.value)}
+            />
+            <button
+              type="button"
+              className="btn btn-outline-light"
+              disabled={!dataDir}
+              onClick={onMoveData}
+            >
+              Move
+            </button>
+          </div>
+          {dataMove && <p className="small mt-2">{dataMove}</p>}
+          {failedMove && (
+            <div className="mt-2">
+              <p className="small text-danger">
+                Moving data to {failedMove.capsule_dir.to} stopped after{" "}
+                {failedMove.moved.length} of {failedMove.total} items:{" "}
+                {failedMove.error}
+              </p>
+              <button
+                type="button"
+                className="btn btn-outline-light me-2"
+                onClick={() => onFinishFailedMove(false)}
+              >
+                Retry
+              </button>
+              <button
+                type="button"
+                className="btn btn-outline-light"
+                onClick={() => onFinishFailedMove(true)}
+              >
+                Move back
+              </button>
+            </div>
+          )}
+        </div>
+        <div className="mb-4">
+          <div className="form-check">
+            <input
+              type="checkbox"
+              className="form-check-input"
+              id="encryptCapsules"
+              checked={preferences?.encryption.enabled ?? false}
+              onChange={(evt) =>
+                setPreferences({
+                  ...(preferences as Configuration),
+                  encryption: {
+                    ...(preferences as Configuration).encryption,
+                    enabled: evt.target.checked,
+                  },
+                })
+              }
+            />
+            <label className="form-check-label" htmlFor="encryptCapsules">
+              Encrypt capsules
+            </label>
+          </div>
+          <select
+            className="form-select mt-2"
+            value={preferences?.encryption.key_source ?? "KeyFile"}
+            onChange={(evt) =>
+              setPreferences({
+                ...(preferences as Configuration),
+                encryption: {
+                  ...(preferences as Configuration).encryption,
+                  key_source: evt.target.value as KeySource,
+                },
+              })
+            }
+          >
+            <option value="KeyFile">Key file on this computer</option>
+            <option value="Passphrase">Passphrase</option>
+          </select>
+          {encryption?.key_source === "Passphrase" && !encryption.unlocked && (
+            <div className="d-flex mt-2">
+              <input
+                type="password"
+                className="form-control me-2"
+                placeholder="Capsule passphrase"
+                value={passphrase}
+                onChange={(evt) => setPassphrase(evt.target.value)}
+              />
+              <button
+                type="button"
+                className="btn btn-outline-light"
+                disabled={!passphrase}
+                onClick={onUnlockEncryption}
+              >
+                Unlock
+              </button>
+            </div>
+          )}
+          {unlockError && (
+            <p className="small text-danger mt-2">{unlockError}</p>
+          )}
+        </div>
+        <div className="mb-4">
+          <p className="mb-1">Retention, 0 turns a limit off</p>
+          {retentionFields.map(([field, label]) => (
+            <div key={field} className="d-flex align-items-center gap-2 mb-2">
+              <label htmlFor={field} className="form-label flex-grow-1 mb-0">
+                {label}
+              </label>
+              <input
+                id={field}
+                type="number"
+                min={0}
+                className="form-control w-25"
+                value={preferences?.preferences.retention[field] ?? 0}
+                onChange={(evt) =>
+                  preferences &&
+                  setPreferences({
+                    ...preferences,
+                    preferences: {
+                      ...preferences.preferences,
+                      retention: {
+                        ...preferences.preferences.retention,
+                        [field]: Math.max(0, parseInt(evt.target.value) || 0),
+                      },
+                    },
+                  })
+                }
+              />
+            </div>
+          ))}
+        </div>
+        {storageUsage && (
+          <div className="mb-4">
+            <p className="mb-1">
+              Storage used: {toMb(storageUsage.total_bytes)}
+              {storageUsage.max_bytes !== null &&
+                ` of ${toMb(storageUsage.max_bytes)}`}
+              {storageUsage.free_disk_bytes !== null &&
+                `, ${toMb(storageUsage.free_disk_bytes)} free on disk`}
+            </p>
+            {storageUsage.captures_paused && (
+              <p className="small text-warning mb-1">
+                Screenshots and webcam captures are paused until disk space is
+                freed
+              </p>
+            )}
+            <ul className="small mb-2">
+              {storageUsage.days
+                .slice(-7)
+                .reverse()
+                .map((day) => (
+                  <li key={day.date}>
+                    {day.date}: {toMb(dayTotal(day))} (screenshots{" "}
+                    {toMb(day.screenshots)}, webcam {toMb(day.webcam)},
+                    recordings {toMb(day.screen_recordings)})
+                  </li>
+                ))}
+            </ul>
+            <button
+              type="button"
+              className="btn btn-outline-light"
+              onClick={onEnforceRetention}
+            >
+              Clean Up Now
+            </button>
+            {retention && <p className="small mt-2">{retention}</p>}
+          </div>
+        )}
         <hr />
+        {(saveError || fieldErrors.length > 0) && (
+          <ul className="text-danger small">
+            {saveError && <li>{saveError}</li>}
+            {fieldErrors.map((error) => (
+              <li key={`${error.field}-${error.message}`}>
+                {error.field}: {error.message}
+              </li>
+            ))}
+          </ul>
+        )}
         <div className="row">
           <div className="col-12 col-md-12">
             <div className="d-flex justify-content-between">
@@ -1,5 +1,22 @@
 import { invoke } from "@tauri-apps/api/tauri";
-import { Configuration, PermisssionsStatus, Session, User } from "../types";
+import {
+  CameraDevice,
+  CaptureHealthReport,
+  CaptureNoticePayload,
+  CaptureRecord,
+  Configuration,
+  DataMove,
+  DiagnosticsExport,
+  EncryptionStatus,
+  FfmpegDiagnostics,
+  LogEntry,
+  LogLevel,
+  PermisssionsStatus,
+  RetentionReport,
+  Session,
+  StorageUsage,
+  User,
+} from "../types";
 
 export const get_permission_status = async (): Promise<PermisssionsStatus> => {
   return await invoke("get_permission_status");
@@ -21,10 +38,6 @@ export const on_permissions_granted = async () => {
   await invoke("on_permissions_granted");
 };
 
-export async function record_screen() {
-  await invoke("record_screen");
-}
-
 export async function start_session(): Promise<Session> {
   return await invoke("start_session");
 }
@@ -77,12 +90,18 @@ export async function minimize_window(name: string) {
   await invoke("minimize_window", { name });
 }
 
-export async function list_camera_devices(): Promise<string[]> {
+export async function list_camera_devices(): Promise<CameraDevice[]> {
   return await invoke("list_camera_devices");
 }
 
-export async function select_camera_device(name: string) {
-  await invoke("select_camera_device", { name });
+export async function select_camera_device(
+  id: string,
+): Promise<CameraDevice> {
+  return await invoke("select_camera_device", { id });
+}
+
+export async function get_selected_camera_device(): Promise<CameraDevice> {
+  return await invoke("get_selected_camera_device");
 }
 
 export async function get_time_tracked_today() {
@@ -92,3 +111,83 @@ export async function get_time_tracked_today() {
 export async function quit_app() {
   await invoke("quit_app");
 }
+
+export async function get_capture_health(
+  from: string,
+  to: string,
+): Promise<CaptureHealthReport> {
+  return await invoke("get_capture_health", { from, to });
+}
+
+export async function veto_capture(id: string) {
+  await invoke("veto_capture", { id });
+}
+
+export async function get_pending_captures(): Promise<CaptureNoticePayload[]> {
+  return await invoke("get_pending_captures");
+}
+
+/** Data URL of a capture file, capsule files may be encrypted on disk */
+export async function get_capture_preview(path: string): Promise<string> {
+  return await invoke("get_capture_preview", { path });
+}
+
+export async function capture_now(): Promise<CaptureRecord> {
+  return await invoke("capture_now");
+}
+
+export async function get_ffmpeg_diagnostics(): Promise<FfmpegDiagnostics> {
+  return await invoke("get_ffmpeg_diagnostics");
+}
+
+export async function set_log_level(level: LogLevel) {
+  await invoke("set_log_level", { level });
+}
+
+export async function get_recent_logs(
+  limit: number,
+  level?: LogLevel,
+): Promise<LogEntry[]> {
+  return await invoke("get_recent_logs", { limit, level });
+}
+
+export async function export_diagnostics(
+  path?: string,
+  capsule_count?: number,
+): Promise<DiagnosticsExport> {
+  return await invoke("export_diagnostics", { path, capsuleCount: capsule_count });
+}
+
+export async function move_data_directory(target: string) {
+  await invoke("move_data_directory", { target });
+}
+
+export async function get_data_move(): Promise<DataMove | null> {
+  return await invoke("get_data_move");
+}
+
+export async function retry_data_move() {
+  await invoke("retry_data_move");
+}
+
+export async function abandon_data_move() {
+  await invoke("abandon_data_move");
+}
+
+export async function get_storage_usage(): Promise<StorageUsage> {
+  return await invoke("get_storage_usage");
+}
+
+export async function enforce_retention(): Promise<RetentionReport> {
+  return await invoke("enforce_retention");
+}
+
+export async function get_encryption_status(): Promise<EncryptionStatus> {
+  return await invoke("get_encryption_status");
+}
+
+export async function unlock_encryption(
+  passphrase: string,
+): Promise<EncryptionStatus> {
+  return await invoke("unlock_encryption", { passphrase });
+}
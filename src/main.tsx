@@ -10,6 +10,7 @@ import Settings from "./Settings";
 import TimeCard from "./Timecard";
 import Track from "./Track";
 import PermissionRequest from "./Permissions";
+import CaptureNotice from "./CaptureNotice";
 
 const router = createBrowserRouter([
   {
@@ -36,6 +37,10 @@ const router = createBrowserRouter([
     path: "/permissions",
     element: <PermissionRequest />,
   },
+  {
+    path: "/capture-notice",
+    element: <CaptureNotice />,
+  },
 ]);
 
 ReactDOM.createRoot(document.getElementById("root") as HTMLElement).render(
@@ -16,16 +16,296 @@ export interface Configuration {
   signin_on_launch: boolean;
   track_on_signin: boolean;
   enable_camera: boolean;
+  preferred_camera_id: string | null;
+  ffmpeg_path: string | null;
+  log_level: LogLevel;
+  encryption: EncryptionSettings;
   preferences: Preferences;
 }
 
+export type KeySource = "KeyFile" | "Passphrase";
+
+export interface EncryptionSettings {
+  enabled: boolean;
+  key_source: KeySource;
+}
+
+export interface EncryptionStatus {
+  enabled: boolean;
+  key_source: KeySource;
+  unlocked: boolean;
+}
+
+export interface CameraFormatInfo {
+  format: string;
+  width: number;
+  height: number;
+  frame_rate: number;
+}
+
+export interface CameraDevice {
+  id: string;
+  index: string;
+  name: string;
+  misc: string;
+  description: string;
+  formats: CameraFormatInfo[];
+}
+
+export interface CameraDevicesChangedPayload {
+  devices: CameraDevice[];
+  selected: string | null;
+}
+
 export interface Preferences {
   time_gap_duration_in_seconds: number;
   webcam_delay: number;
+  enable_screen_recording: boolean;
+  screen_recording_fps: number;
+  capture_strategy: CaptureStrategy;
+  screenshot_count: number;
+  webcam_shot_count: number;
+  capture_retries: number;
+  capture_notice: boolean;
+  veto_window_in_seconds: number;
+  max_vetoes_per_day: number;
+  capture_triggers: CaptureTriggers;
+  camera: CameraSettings;
+  webcam_capture_mode: WebcamCaptureMode;
+  capture_backend: CaptureBackend;
+  face_check: boolean;
+  retry_busy_camera: boolean;
+  busy_camera_retry_delay_in_seconds: number;
+  camera_apps: string[];
+  retention: RetentionSettings;
+}
+
+/** 0 disables a limit */
+export interface RetentionSettings {
+  max_storage_in_mb: number;
+  screenshot_days: number;
+  webcam_days: number;
+  metadata_days: number;
+  min_free_disk_in_mb: number;
+}
+
+export type CaptureBackend = "Native" | "Ffmpeg";
+
+export type WebcamCaptureMode =
+  | "Still"
+  | { Clip: { duration_in_seconds: number; width: number } };
+
+export interface CameraSettings {
+  width: number;
+  height: number;
+  frame_rate: number;
+  warmup_frames: number;
+  output_width: number | null;
+}
+
+export interface CaptureTriggers {
+  on_capsule_start: boolean;
+  before_session_stop: boolean;
+  watched_apps: string[];
 }
 
+export type CaptureStrategy =
+  | "UniformRandom"
+  | { EvenlySpaced: { jitter_in_seconds: number } }
+  | "OnWindowChange";
+
 export interface PermisssionsStatus {
   camera: boolean;
   accessibility: boolean;
   screen_capture: boolean;
 }
+
+export interface CaptureStats {
+  planned: number;
+  ok: number;
+  skipped: number;
+  permission_denied: number;
+  device_busy: number;
+  errors: number;
+  cancelled: number;
+  vetoed: number;
+  success_rate: number | null;
+}
+
+export interface CaptureHealthReport {
+  from: string;
+  to: string;
+  capsules: number;
+  screenshot: CaptureStats;
+  webcam: CaptureStats;
+  database_error: string | null;
+}
+
+export type CaptureKind = "Screenshot" | "Webcam";
+
+export interface CaptureNoticePayload {
+  id: string;
+  kind: CaptureKind;
+  preview: string | null;
+  veto_window_in_seconds: number;
+  vetoes_left: number;
+}
+
+export type CaptureTrigger =
+  | "Scheduled"
+  | "OnDemand"
+  | "AppSwitch"
+  | "CapsuleStart"
+  | "SessionStop";
+
+export type CaptureOutcome =
+  | "Ok"
+  | { SkippedByPolicy: string }
+  | "PermissionDenied"
+  | "DeviceBusy"
+  | { Error: string }
+  | "Cancelled"
+  | "UserVetoed";
+
+export type ValidationRule =
+  | { Range: { min: number; max: number } }
+  | "NotEmpty"
+  | { Below: { field: string; limit: number } }
+  | "WritableDirectory"
+  | "ExistingFile";
+
+export interface FieldError {
+  field: string;
+  rule: ValidationRule;
+  message: string;
+}
+
+export type WorksmartErrorCode =
+  | "storage_failed"
+  | "capture_skipped"
+  | "capture_failed"
+  | "camera_busy"
+  | "camera_not_found"
+  | "camera_failed"
+  | "permission_denied"
+  | "config_invalid"
+  | "config_save_failed"
+  | "session_not_running"
+  | "session_failed"
+  | "veto_rejected"
+  | "invalid_input"
+  | "internal";
+
+/** Error every command rejects with */
+export interface WorksmartError {
+  code: WorksmartErrorCode;
+  message: string;
+  details: {
+    reason?: string;
+    permission?: "Camera" | "Accessibility" | "ScreenCapture";
+    fields?: FieldError[];
+  } | null;
+}
+
+export interface CaptureRecord {
+  id: string;
+  kind: CaptureKind;
+  trigger: CaptureTrigger;
+  planned_at: string;
+  started_at: string | null;
+  finished_at: string | null;
+  attempts: number;
+  outcome: CaptureOutcome;
+  files: string[];
+  clip: ClipMetadata | null;
+  face: FacePresence | null;
+}
+
+export type FaceResult = "Present" | "Absent" | "Multiple";
+
+export interface FacePresence {
+  result: FaceResult;
+  faces: number;
+  confidence: number;
+}
+
+export interface ClipMetadata {
+  duration_in_seconds: number;
+  size_in_bytes: number;
+  width: number;
+}
+
+export type FfmpegSource = "Config" | "Sidecar" | "SystemPath";
+
+export interface FfmpegCandidate {
+  source: FfmpegSource;
+  path: string;
+  version: string | null;
+  outdated: boolean;
+  missing_encoders: string[];
+  error: string | null;
+}
+
+export interface FfmpegDiagnostics {
+  selected: FfmpegCandidate | null;
+  candidates: FfmpegCandidate[];
+}
+
+export type LogLevel = "Error" | "Warn" | "Info" | "Debug" | "Trace";
+
+export interface LogEntry {
+  timestamp: string;
+  level: string;
+  target: string;
+  fields: Record<string, unknown>;
+  span: Record<string, unknown> | null;
+}
+
+export interface DiagnosticsExport {
+  path: string;
+  files: string[];
+}
+
+export interface MoveEntry {
+  from: string;
+  to: string;
+}
+
+export interface DataMove {
+  capsule_dir: MoveEntry;
+  media_dir: MoveEntry;
+  pending: MoveEntry[];
+  moved: MoveEntry[];
+  total: number;
+  error: string | null;
+}
+
+export interface DataMoveProgress {
+  moved: number;
+  total: number;
+  current: string | null;
+  done: boolean;
+}
+
+export interface DayUsage {
+  date: string;
+  screenshots: number;
+  webcam: number;
+  screen_recordings: number;
+  metadata: number;
+  other: number;
+}
+
+export interface StorageUsage {
+  days: DayUsage[];
+  total_bytes: number;
+  max_bytes: number | null;
+  free_disk_bytes: number | null;
+  captures_paused: boolean;
+}
+
+export interface RetentionReport {
+  removed_files: number;
+  removed_capsules: number;
+  freed_bytes: number;
+}